@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::path::Path;
+use std::process::ExitCode;
+
+use database::btree::{BTree, CheckReport};
+use database::storage::buffer_pool::BufferPool;
+use database::storage::disk_manager::DiskManager;
+use database::storage::recovery;
+use database::storage::wal::WriteAheadLog;
+
+// Checks a database file offline. The file is opened read-only and the
+// write-ahead log is not replayed, so the check sees exactly what is on disk
+// and never changes it; log records recovery would still replay are reported.
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, path] = args.as_slice() else {
+        eprintln!("Usage: db-check <file>");
+        return ExitCode::from(2);
+    };
+    if !Path::new(path).exists() {
+        eprintln!("{}: no such file", path);
+        return ExitCode::from(2);
+    }
+
+    let report = match check(path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Cannot check {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", report);
+    if report.is_consistent() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn check(path: &str) -> Result<CheckReport, Box<dyn Error>> {
+    let wal_path = Path::new(path).with_extension("wal");
+    if wal_path.exists() {
+        let pending = recovery::pending_writes(&WriteAheadLog::read_file(&wal_path)?);
+        if pending > 0 {
+            println!(
+                "Write-ahead log {} has {} page writes since its last checkpoint; \
+                 they are not replayed, so the file may not reflect them yet",
+                wal_path.display(),
+                pending
+            );
+        }
+    }
+
+    let buffer_pool = BufferPool::new(1000, DiskManager::open_read_only(path)?);
+    let root_page_id = buffer_pool
+        .root_page_id()
+        .ok_or("the file has no B-tree root")?;
+    Ok(BTree::new(root_page_id).check(&buffer_pool)?)
+}
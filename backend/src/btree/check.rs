@@ -0,0 +1,298 @@
+// Structural integrity checks. `check` walks every node and overflow chain and
+// records each problem it finds instead of stopping at the first one. It also
+// accounts for every allocated page as a tree node, an overflow page or a free
+// page, so pages that leaked out of all three show up as unreachable.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{overflow, BTree, BTreeNode, Payload};
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::error::{DatabaseError, Result};
+
+/// A broken invariant found by `BTree::check`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    pub page_id: u32,
+    pub problem: String,
+}
+
+/// What `BTree::check` found: the shape of the tree, every violation, and the
+/// allocated pages nothing refers to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    /// Levels from the root down to the leaves.
+    pub depth: usize,
+    pub internal_nodes: usize,
+    pub leaves: usize,
+    pub entries: usize,
+    pub overflow_pages: usize,
+    pub free_pages: usize,
+    /// Allocated pages that are not in the tree, an overflow chain or the free
+    /// list. They waste space but do not make the tree wrong.
+    pub unreachable_pages: Vec<u32>,
+    pub violations: Vec<Violation>,
+}
+
+impl CheckReport {
+    /// Whether the tree is free of violations.
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.is_consistent() {
+            "OK"
+        } else {
+            "CORRUPT"
+        };
+        writeln!(f, "B-tree check: {}", status)?;
+        writeln!(
+            f,
+            "  depth {}, {} internal nodes, {} leaves, {} entries",
+            self.depth, self.internal_nodes, self.leaves, self.entries
+        )?;
+        writeln!(
+            f,
+            "  {} overflow pages, {} free pages",
+            self.overflow_pages, self.free_pages
+        )?;
+        if !self.unreachable_pages.is_empty() {
+            writeln!(f, "  unreachable pages: {:?}", self.unreachable_pages)?;
+        }
+        for violation in &self.violations {
+            writeln!(f, "  page {}: {}", violation.page_id, violation.problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl BTree {
+    /// Walks the whole file and checks the tree's invariants: keys ascend within
+    /// every node and stay within the separators above them, internal nodes have
+    /// one more child than keys, all leaves are at the same depth, only the root
+    /// may be empty, every node fits in its page and records its own page ID, the
+    /// leaf chain links the leaves in key order, overflow chains hold the sizes
+    /// their entries expect, and no page is reachable twice. The walk takes no
+    /// latches, so the caller keeps writers out while it runs.
+    pub fn check(&self, buffer_pool: &BufferPool) -> Result<CheckReport> {
+        let root_page_id = *self.root_page_id.read().unwrap();
+        let mut walk = Walk {
+            page_count: buffer_pool.header().next_page_id,
+            buffer_pool,
+            report: CheckReport::default(),
+            seen: HashSet::new(),
+            leaves: Vec::new(),
+        };
+
+        walk.node(root_page_id, None, None, 0);
+        walk.leaf_chain();
+        walk.free_list();
+        walk.report.unreachable_pages = (1..walk.page_count)
+            .filter(|page_id| !walk.seen.contains(page_id))
+            .collect();
+        Ok(walk.report)
+    }
+
+    /// Runs `check` and fails on the first violation or unreachable page. Tests
+    /// call this after mutating the tree.
+    pub fn verify(&self, buffer_pool: &BufferPool) -> Result<()> {
+        let report = self.check(buffer_pool)?;
+        if let Some(violation) = report.violations.first() {
+            return Err(DatabaseError::InvalidData(format!(
+                "B-tree invariant violated at page {}: {}",
+                violation.page_id, violation.problem
+            )));
+        }
+        if !report.unreachable_pages.is_empty() {
+            return Err(DatabaseError::InvalidData(format!(
+                "Allocated pages are unreachable: {:?}",
+                report.unreachable_pages
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct Walk<'a> {
+    buffer_pool: &'a BufferPool,
+    page_count: u32,
+    report: CheckReport,
+    seen: HashSet<u32>,
+    // Page ID, links and depth of each leaf, in key order
+    leaves: Vec<(u32, Option<u32>, Option<u32>, usize)>,
+}
+
+impl Walk<'_> {
+    fn fail(&mut self, page_id: u32, problem: impl Into<String>) {
+        self.report.violations.push(Violation {
+            page_id,
+            problem: problem.into(),
+        });
+    }
+
+    /// Marks `page_id` as reached. Returns false if it cannot be walked into: it
+    /// lies outside the file or was already reached from somewhere else.
+    fn visit(&mut self, page_id: u32) -> bool {
+        if page_id == 0 || page_id >= self.page_count {
+            self.fail(page_id, "page is referenced but lies outside the file");
+            return false;
+        }
+        if !self.seen.insert(page_id) {
+            self.fail(page_id, "page is reachable more than once");
+            return false;
+        }
+        true
+    }
+
+    /// Checks the subtree at `page_id`, whose keys must lie in `[lower, upper)`.
+    fn node(&mut self, page_id: u32, lower: Option<&[u8]>, upper: Option<&[u8]>, depth: usize) {
+        if !self.visit(page_id) {
+            return;
+        }
+        let node = match self
+            .buffer_pool
+            .get_page(page_id)
+            .and_then(|frame| BTreeNode::deserialize(&frame.page().data))
+        {
+            Ok(node) => node,
+            Err(e) => return self.fail(page_id, format!("node cannot be read: {}", e)),
+        };
+        self.report.depth = self.report.depth.max(depth + 1);
+
+        if node.page_id != page_id {
+            self.fail(
+                page_id,
+                format!("node records page ID {} instead", node.page_id),
+            );
+        }
+        if node.is_overfull() {
+            self.fail(page_id, "node does not fit in its page");
+        }
+
+        let keys: Vec<&[u8]> = if node.is_leaf {
+            node.entries
+                .iter()
+                .map(|entry| entry.key.as_slice())
+                .collect()
+        } else {
+            node.keys.iter().map(Vec::as_slice).collect()
+        };
+        if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            self.fail(page_id, "keys are not in ascending order");
+        }
+        let in_bounds = |key: &&[u8]| {
+            lower.is_none_or(|lower| *key >= lower) && upper.is_none_or(|upper| *key < upper)
+        };
+        if !keys.iter().all(in_bounds) {
+            self.fail(page_id, "key lies outside the separators above it");
+        }
+        if depth > 0 && keys.is_empty() {
+            self.fail(page_id, "non-root node is empty");
+        }
+
+        if node.is_leaf {
+            self.report.leaves += 1;
+            self.report.entries += node.entries.len();
+            self.leaves
+                .push((page_id, node.prev_leaf, node.next_leaf, depth));
+            for entry in &node.entries {
+                if let Payload::Overflow {
+                    size,
+                    first_page_id,
+                } = entry.payload
+                {
+                    self.overflow_chain(page_id, first_page_id, size as usize);
+                }
+            }
+            return;
+        }
+
+        self.report.internal_nodes += 1;
+        if node.children.len() != node.keys.len() + 1 {
+            self.fail(
+                page_id,
+                format!(
+                    "internal node has {} children for {} keys",
+                    node.children.len(),
+                    node.keys.len()
+                ),
+            );
+        }
+        for (idx, &child) in node.children.iter().enumerate() {
+            let child_lower = match idx {
+                0 => lower,
+                _ => node.keys.get(idx - 1).map(Vec::as_slice).or(upper),
+            };
+            let child_upper = node.keys.get(idx).map(Vec::as_slice).or(upper);
+            self.node(child, child_lower, child_upper, depth + 1);
+        }
+    }
+
+    /// Checks that the chain starting at `first_page_id`, referenced from the leaf
+    /// `leaf_page_id`, holds exactly `size` bytes.
+    fn overflow_chain(&mut self, leaf_page_id: u32, first_page_id: u32, size: usize) {
+        let mut page_id = first_page_id;
+        let mut stored = 0;
+        while page_id != 0 {
+            if !self.visit(page_id) {
+                return;
+            }
+            self.report.overflow_pages += 1;
+            match overflow::read_link(page_id, self.buffer_pool) {
+                Ok((next_page_id, chunk_len)) => {
+                    stored += chunk_len;
+                    page_id = next_page_id;
+                }
+                Err(e) => {
+                    return self.fail(page_id, format!("overflow page cannot be read: {}", e))
+                }
+            }
+        }
+        if stored != size {
+            self.fail(
+                leaf_page_id,
+                format!(
+                    "overflow chain at page {} holds {} bytes, expected {}",
+                    first_page_id, stored, size
+                ),
+            );
+        }
+    }
+
+    fn leaf_chain(&mut self) {
+        let Some(&(_, _, _, leaf_depth)) = self.leaves.first() else {
+            return;
+        };
+        let leaves = std::mem::take(&mut self.leaves);
+        for (idx, &(page_id, prev_leaf, next_leaf, depth)) in leaves.iter().enumerate() {
+            if depth != leaf_depth {
+                self.fail(
+                    page_id,
+                    format!("leaf is at depth {}, others at {}", depth, leaf_depth),
+                );
+            }
+            let prev = idx.checked_sub(1).map(|prev| leaves[prev].0);
+            let next = leaves.get(idx + 1).map(|next| next.0);
+            if prev_leaf != prev || next_leaf != next {
+                self.fail(page_id, "leaf chain is out of key order");
+            }
+        }
+    }
+
+    fn free_list(&mut self) {
+        let free_pages = match self.buffer_pool.free_page_ids() {
+            Ok(free_pages) => free_pages,
+            Err(e) => return self.fail(0, format!("free list cannot be read: {}", e)),
+        };
+        for page_id in free_pages {
+            if self.visit(page_id) {
+                self.report.free_pages += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// A cursor walks the leaf chain one entry at a time instead of collecting a
+// range into memory. It sits between two entries, like a text cursor: `next`
+// returns the entry after it and `prev` the entry before it. The cursor holds no
+// latches between calls: it copies the leaf it is on, with the values loaded,
+// and finds the next leaf by descending again to the fence separating the two,
+// so a leaf split or merged meanwhile is never followed by a stale link.
+
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, RwLock};
+
+use super::descend;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::error::Result;
+use crate::storage::key::Key;
+use crate::storage::transaction::VersionId;
+use crate::storage::value::Value;
+
+pub struct BTreeCursor<'a> {
+    buffer_pool: &'a BufferPool,
+    root_page_id: Arc<RwLock<u32>>,
+    // Encoded bounds of the range the cursor is limited to
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    // The leaf the cursor is on and the index of the entry `next` returns. A
+    // cursor that has not moved yet is on no leaf.
+    leaf: Option<LeafEntries>,
+    pos: usize,
+}
+
+/// A copy of a leaf's entries, taken under its latch, with the separators that
+/// fenced it in at the time.
+struct LeafEntries {
+    entries: Vec<(Vec<u8>, VersionId, Value)>,
+    lower_fence: Option<Vec<u8>>,
+    upper_fence: Option<Vec<u8>>,
+}
+
+impl<'a> BTreeCursor<'a> {
+    pub(super) fn new<R: RangeBounds<Key>>(
+        root_page_id: Arc<RwLock<u32>>,
+        range: R,
+        buffer_pool: &'a BufferPool,
+    ) -> Self {
+        Self {
+            buffer_pool,
+            root_page_id,
+            lower: range.start_bound().map(Key::encode),
+            upper: range.end_bound().map(Key::encode),
+            leaf: None,
+            pos: 0,
+        }
+    }
+
+    /// Moves the cursor just before the first entry at or after `key`. A key
+    /// below the cursor's range moves it to the start of the range.
+    pub fn seek(&mut self, key: impl Into<Key>) -> Result<()> {
+        let key = key.into().encode();
+        let below_range = match &self.lower {
+            Bound::Included(lower) => key < *lower,
+            Bound::Excluded(lower) => key <= *lower,
+            Bound::Unbounded => false,
+        };
+        let target = if below_range {
+            self.lower.clone()
+        } else {
+            Bound::Included(key)
+        };
+        self.seek_bound(target, true)
+    }
+
+    /// Returns the entry `next` would return, without moving past it.
+    pub fn current(&mut self) -> Option<Result<(Key, Value)>> {
+        match self.settle_forward() {
+            Ok(true) => Some(self.entry_at(self.pos).map(|(key, _, value)| (key, value))),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Moves back over the previous entry and returns it. A cursor that has not
+    /// moved yet starts from the end of its range.
+    pub fn prev(&mut self) -> Option<Result<(Key, Value)>> {
+        self.prev_version()
+            .map(|entry| entry.map(|(key, _, value)| (key, value)))
+    }
+
+    /// Like `next`, also returning the version stored with the entry.
+    pub fn next_version(&mut self) -> Option<Result<(Key, VersionId, Value)>> {
+        match self.settle_forward() {
+            Ok(true) => {
+                self.pos += 1;
+                Some(self.entry_at(self.pos - 1))
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Like `prev`, also returning the version stored with the entry.
+    pub fn prev_version(&mut self) -> Option<Result<(Key, VersionId, Value)>> {
+        match self.settle_backward() {
+            Ok(true) => {
+                self.pos -= 1;
+                Some(self.entry_at(self.pos))
+            }
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Moves onto the leaf holding the entry after the cursor, past leaves it has
+    /// reached the end of. Returns whether that entry exists and lies within the
+    /// range.
+    fn settle_forward(&mut self) -> Result<bool> {
+        if self.leaf.is_none() {
+            self.seek_bound(self.lower.clone(), true)?;
+        }
+        loop {
+            let leaf = self.leaf.as_ref().unwrap();
+            if let Some((key, _, _)) = leaf.entries.get(self.pos) {
+                return Ok(self.below_upper(key));
+            }
+            // Every key in the next leaf is at least the fence
+            let Some(fence) = leaf.upper_fence.clone() else {
+                return Ok(false);
+            };
+            if !self.below_upper(&fence) {
+                return Ok(false);
+            }
+            self.seek_bound(Bound::Included(fence), true)?;
+        }
+    }
+
+    /// Like `settle_forward`, for the entry before the cursor.
+    fn settle_backward(&mut self) -> Result<bool> {
+        if self.leaf.is_none() {
+            self.seek_bound(self.upper.clone(), false)?;
+        }
+        loop {
+            let leaf = self.leaf.as_ref().unwrap();
+            if self.pos > 0 {
+                return Ok(self.above_lower(&leaf.entries[self.pos - 1].0));
+            }
+            // Every key in the previous leaf is below the fence
+            let Some(fence) = leaf.lower_fence.clone() else {
+                return Ok(false);
+            };
+            if !self.above_lower(&fence) {
+                return Ok(false);
+            }
+            self.seek_bound(Bound::Excluded(fence), false)?;
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Included(upper) => key <= upper.as_slice(),
+            Bound::Excluded(upper) => key < upper.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn above_lower(&self, key: &[u8]) -> bool {
+        match &self.lower {
+            Bound::Included(lower) => key >= lower.as_slice(),
+            Bound::Excluded(lower) => key > lower.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Descends to the leaf for `bound` and places the cursor at it: as a lower
+    /// bound (`forward`) before the first entry inside it, otherwise after the
+    /// last entry inside it.
+    fn seek_bound(&mut self, bound: Bound<Vec<u8>>, forward: bool) -> Result<()> {
+        let leaf = descend(&self.root_page_id, &bound, forward, self.buffer_pool)?;
+        // Values are loaded while the leaf is latched, so their overflow chains
+        // cannot be freed underneath the cursor
+        let entries = leaf
+            .node
+            .entries
+            .iter()
+            .map(|entry| {
+                let value = entry.payload.load(self.buffer_pool)?;
+                Ok((entry.key.clone(), entry.version, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.pos = match (&bound, forward) {
+            (Bound::Unbounded, true) => 0,
+            (Bound::Unbounded, false) => entries.len(),
+            (Bound::Included(key), true) | (Bound::Excluded(key), false) => {
+                entries.partition_point(|(entry_key, _, _)| entry_key < key)
+            }
+            (Bound::Excluded(key), true) | (Bound::Included(key), false) => {
+                entries.partition_point(|(entry_key, _, _)| entry_key <= key)
+            }
+        };
+        self.leaf = Some(LeafEntries {
+            entries,
+            lower_fence: leaf.lower_fence,
+            upper_fence: leaf.upper_fence,
+        });
+        Ok(())
+    }
+
+    fn entry_at(&self, pos: usize) -> Result<(Key, VersionId, Value)> {
+        let (key, version, value) = &self.leaf.as_ref().unwrap().entries[pos];
+        Ok((Key::decode(key)?, *version, value.clone()))
+    }
+}
+
+impl Iterator for BTreeCursor<'_> {
+    type Item = Result<(Key, Value)>;
+
+    /// Moves forward over the next entry and returns it. A cursor that has not
+    /// moved yet starts from the beginning of its range.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_version()
+            .map(|entry| entry.map(|(key, _, value)| (key, value)))
+    }
+}
@@ -1,36 +1,130 @@
+mod check;
+mod cursor;
+mod overflow;
+
+pub use check::{CheckReport, Violation};
+pub use cursor::BTreeCursor;
+
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{RwLock, RwLockWriteGuard};
 
-use crate::storage::buffer_pool::BufferPool;
+use crate::storage::buffer_pool::{BufferPool, PageGuard};
 use crate::storage::error::{DatabaseError, Result};
-use crate::storage::page::Page;
+use crate::storage::key::Key;
+use crate::storage::latch::LatchMode;
+use crate::storage::page::{PAGE_HEADER_SIZE, PAGE_SIZE};
+use crate::storage::transaction::{TransactionId, VersionId};
 use crate::storage::value::Value;
 
-const ORDER: usize = 4; // Maximum number of children per node
-const MAX_KEYS: usize = ORDER - 1;
-const MIN_KEYS: usize = (ORDER / 2) - 1;
+// Nodes split when their serialized form no longer fits in a page, so the
+// number of keys per node depends on the size of the keys and values.
+const NODE_CAPACITY: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
+const NODE_HEADER_SIZE: usize = 7; // page_id, node kind, key count
+const LEAF_LINKS_SIZE: usize = 8; // prev_leaf, next_leaf
+const KEY_LEN_SIZE: usize = 2;
+const CHILD_SIZE: usize = 4;
+const VERSION_SIZE: usize = 16; // xmin, xmax
+                                // A node smaller than this after a delete borrows from or merges with a sibling
+const MIN_NODE_SIZE: usize = NODE_CAPACITY / 4;
+
+/// Longest encoded key the tree accepts. Keys are kept inline in both leaves and
+/// internal nodes, so this keeps several of them to a page.
+pub const MAX_KEY_SIZE: usize = NODE_CAPACITY / 8;
+
+// Most a separator with its child pointer adds to an internal node
+const MAX_SEPARATOR_SIZE: usize = KEY_LEN_SIZE + MAX_KEY_SIZE + CHILD_SIZE;
+
+/// Largest serialized value kept inline in a leaf. Larger values move to a chain
+/// of overflow pages, so a leaf always has room for several entries.
+pub const MAX_INLINE_VALUE_SIZE: usize = NODE_CAPACITY / 4;
+
+// Tags an overflow pointer in a leaf entry, in place of a value's type tag
+const OVERFLOW_TAG: u8 = 0xFF;
+const OVERFLOW_POINTER_SIZE: usize = 9; // tag, value size, first page ID
+
+// Leaf links are stored as page IDs; page 0 is the database header, so it marks
+// the end of the chain.
+const NO_SIBLING: u32 = 0;
+
+/// What a leaf entry stores for its value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Inline(Value),
+    /// The serialized value lives in a chain of overflow pages.
+    Overflow {
+        size: u32,
+        first_page_id: u32,
+    },
+}
+
+impl Payload {
+    fn size(&self) -> usize {
+        match self {
+            Payload::Inline(value) => value.serialized_size(),
+            Payload::Overflow { .. } => OVERFLOW_POINTER_SIZE,
+        }
+    }
+
+    fn load(&self, buffer_pool: &BufferPool) -> Result<Value> {
+        match self {
+            Payload::Inline(value) => Ok(value.clone()),
+            Payload::Overflow {
+                size,
+                first_page_id,
+            } => {
+                let bytes = overflow::read_chain(*first_page_id, *size as usize, buffer_pool)?;
+                let (value, _) = Value::deserialize(&bytes)
+                    .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+                Ok(value)
+            }
+        }
+    }
+}
 
+/// A leaf entry. The key is kept in its memcomparable encoding, so entries and
+/// separators are compared as plain bytes. The version records which
+/// transactions created and deleted the value; the tree itself ignores it.
 #[derive(Debug)]
 pub struct KeyValue {
-    key: i32,
-    value: Value,
+    pub key: Vec<u8>,
+    pub version: VersionId,
+    pub payload: Payload,
+}
+
+impl KeyValue {
+    fn size(&self) -> usize {
+        key_size(&self.key) + VERSION_SIZE + self.payload.size()
+    }
 }
 
 impl Clone for KeyValue {
     fn clone(&self) -> Self {
         Self {
-            key: self.key,
-            value: self.value.clone(),
+            key: self.key.clone(),
+            version: self.version,
+            payload: self.payload.clone(),
         }
     }
 }
 
+/// Serialized size of an encoded key: its length, then its bytes.
+fn key_size(key: &[u8]) -> usize {
+    KEY_LEN_SIZE + key.len()
+}
+
+/// A B+tree node. Leaves hold the key-value pairs and are linked to their
+/// neighbours; internal nodes hold separator keys only, where `children[i]`
+/// covers the keys in `keys[i - 1]..keys[i]`.
 #[derive(Debug)]
 pub struct BTreeNode {
     pub page_id: u32,
     pub is_leaf: bool,
-    pub entries: Vec<KeyValue>, // Key-value pairs
+    pub entries: Vec<KeyValue>, // Key-value pairs, leaves only
+    pub keys: Vec<Vec<u8>>,     // Encoded separator keys, internal nodes only
     pub children: Vec<u32>,     // Page IDs of children
+    pub prev_leaf: Option<u32>,
+    pub next_leaf: Option<u32>,
 }
 
 impl BTreeNode {
@@ -38,68 +132,92 @@ impl BTreeNode {
         Self {
             page_id,
             is_leaf,
-            entries: Vec::with_capacity(MAX_KEYS),
-            children: Vec::with_capacity(ORDER),
+            entries: Vec::new(),
+            keys: Vec::new(),
+            children: Vec::new(),
+            prev_leaf: None,
+            next_leaf: None,
         }
     }
 
-    pub fn search(&self, key: i32) -> Result<Option<Value>> {
-        let mut idx = 0;
-
-        while idx < self.entries.len() && key > self.entries[idx].key {
-            idx += 1;
-        }
-
-        if idx < self.entries.len() && key == self.entries[idx].key {
-            Ok(Some(self.entries[idx].value.clone()))
-        } else if self.is_leaf {
-            Ok(None)
-        } else if idx < self.children.len() {
-            Ok(None) // Return None for internal nodes, we'll handle navigation in the BTree search
+    /// Number of keys in the node, entries for a leaf and separators otherwise.
+    pub fn len(&self) -> usize {
+        if self.is_leaf {
+            self.entries.len()
         } else {
-            Ok(None)
+            self.keys.len()
         }
     }
 
-    pub fn insert_non_full(&mut self, key: i32, value: Value) -> Result<()> {
-        let mut idx = self.entries.len();
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
+    /// Length of the node's serialized form.
+    pub fn size(&self) -> usize {
         if self.is_leaf {
-            while idx > 0 && key < self.entries[idx - 1].key {
-                idx -= 1;
-            }
-
-            self.entries.insert(idx, KeyValue { key, value });
+            NODE_HEADER_SIZE
+                + LEAF_LINKS_SIZE
+                + self.entries.iter().map(KeyValue::size).sum::<usize>()
         } else {
-            while idx > 0 && key < self.entries[idx - 1].key {
-                idx -= 1;
-            }
+            NODE_HEADER_SIZE
+                + self.keys.iter().map(|key| key_size(key)).sum::<usize>()
+                + self.children.len() * CHILD_SIZE
         }
+    }
 
-        Ok(())
+    fn is_overfull(&self) -> bool {
+        self.size() > NODE_CAPACITY
     }
 
-    pub fn is_full(&self) -> bool {
-        self.entries.len() >= MAX_KEYS
+    fn is_underfull(&self) -> bool {
+        self.size() < MIN_NODE_SIZE
+    }
+
+    /// Index of the child that covers `key`.
+    fn child_index(&self, key: &[u8]) -> usize {
+        self.keys
+            .partition_point(|separator| separator.as_slice() <= key)
     }
 
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer = Vec::new();
 
-        // Header
+        // Header. Leaves are tagged 0 so that a page which never reached disk
+        // reads back as an empty leaf.
         buffer.extend_from_slice(&self.page_id.to_le_bytes());
-        buffer.push(if self.is_leaf { 1 } else { 0 });
-        buffer.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
-
-        // Entries
-        for entry in &self.entries {
-            buffer.extend_from_slice(&entry.key.to_le_bytes());
-            buffer.extend(entry.value.serialize());
-        }
+        buffer.push(if self.is_leaf { 0 } else { 1 });
+        buffer.extend_from_slice(&(self.len() as u16).to_le_bytes());
 
-        // Children section: each child is 4 bytes
-        for child in &self.children {
-            buffer.extend_from_slice(&child.to_le_bytes());
+        if self.is_leaf {
+            for entry in &self.entries {
+                write_key(&entry.key, &mut buffer);
+                buffer.extend_from_slice(&entry.version.xmin().0.to_le_bytes());
+                let xmax = entry.version.xmax().map_or(0, |xmax| xmax.0);
+                buffer.extend_from_slice(&xmax.to_le_bytes());
+                match &entry.payload {
+                    Payload::Inline(value) => buffer.extend(value.serialize()),
+                    Payload::Overflow {
+                        size,
+                        first_page_id,
+                    } => {
+                        buffer.push(OVERFLOW_TAG);
+                        buffer.extend_from_slice(&size.to_le_bytes());
+                        buffer.extend_from_slice(&first_page_id.to_le_bytes());
+                    }
+                }
+            }
+            for link in [self.prev_leaf, self.next_leaf] {
+                buffer.extend_from_slice(&link.unwrap_or(NO_SIBLING).to_le_bytes());
+            }
+        } else {
+            // Separators, then one more child than there are separators
+            for key in &self.keys {
+                write_key(key, &mut buffer);
+            }
+            for child in &self.children {
+                buffer.extend_from_slice(&child.to_le_bytes());
+            }
         }
 
         buffer
@@ -109,63 +227,179 @@ impl BTreeNode {
         if buffer.len() < 7 {
             return Err(DatabaseError::InvalidData(
                 "Buffer too short for BTreeNode header".to_string(),
-            )
-            .into());
+            ));
         }
 
-        let page_id = u32::from_le_bytes(buffer[0..4].try_into().map_err(|_| {
-            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid page_id")
-        })?);
-        let is_leaf = buffer[4] == 1;
-        let entry_count = u16::from_le_bytes(buffer[5..7].try_into().map_err(|_| {
-            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid entry_count")
-        })?) as usize;
-
-        let entries_start = 7;
-        let entries_end = entries_start + (entry_count * 8);
+        let page_id = u32::from_le_bytes(buffer[0..4].try_into().unwrap());
+        let is_leaf = buffer[4] == 0;
+        let count = u16::from_le_bytes(buffer[5..7].try_into().unwrap()) as usize;
+        let mut node = Self::new(page_id, is_leaf);
+
+        // Nodes are read back from full-size pages, so the trailing bytes are padding
+        let mut current_pos = 7;
+        let read = |current_pos: &mut usize, len: usize, what: &str| -> Result<&[u8]> {
+            let bytes = buffer
+                .get(*current_pos..*current_pos + len)
+                .ok_or_else(|| {
+                    DatabaseError::InvalidData(format!("Buffer too short for {}", what))
+                })?;
+            *current_pos += len;
+            Ok(bytes)
+        };
+        let read_u32 = |current_pos: &mut usize, what: &str| -> Result<[u8; 4]> {
+            Ok(read(current_pos, 4, what)?.try_into().unwrap())
+        };
+        let read_key = |current_pos: &mut usize, what: &str| -> Result<Vec<u8>> {
+            let len =
+                u16::from_le_bytes(read(current_pos, KEY_LEN_SIZE, what)?.try_into().unwrap());
+            Ok(read(current_pos, len as usize, what)?.to_vec())
+        };
+
+        if is_leaf {
+            for _ in 0..count {
+                let key = read_key(&mut current_pos, "entries")?;
+                let xmin =
+                    u64::from_le_bytes(read(&mut current_pos, 8, "entries")?.try_into().unwrap());
+                let xmax =
+                    u64::from_le_bytes(read(&mut current_pos, 8, "entries")?.try_into().unwrap());
+                let mut version = VersionId::new(TransactionId(xmin));
+                if xmax != 0 {
+                    version = version.deleted_by(TransactionId(xmax));
+                }
+                let payload = if buffer.get(current_pos) == Some(&OVERFLOW_TAG) {
+                    current_pos += 1;
+                    Payload::Overflow {
+                        size: u32::from_le_bytes(read_u32(&mut current_pos, "entries")?),
+                        first_page_id: u32::from_le_bytes(read_u32(&mut current_pos, "entries")?),
+                    }
+                } else {
+                    let (value, value_size) = Value::deserialize(&buffer[current_pos..])
+                        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+                    current_pos += value_size;
+                    Payload::Inline(value)
+                };
 
-        if buffer.len() < entries_end {
-            return Err(
-                DatabaseError::InvalidData("Buffer too short for entries".to_string()).into(),
-            );
+                node.entries.push(KeyValue {
+                    key,
+                    version,
+                    payload,
+                });
+            }
+            let link = |page_id: u32| Some(page_id).filter(|&id| id != NO_SIBLING);
+            node.prev_leaf = link(u32::from_le_bytes(read_u32(&mut current_pos, "links")?));
+            node.next_leaf = link(u32::from_le_bytes(read_u32(&mut current_pos, "links")?));
+        } else {
+            for _ in 0..count {
+                node.keys.push(read_key(&mut current_pos, "keys")?);
+            }
+            for _ in 0..=count {
+                let child = u32::from_le_bytes(read_u32(&mut current_pos, "children")?);
+                node.children.push(child);
+            }
         }
 
-        let mut current_pos = entries_start;
-        let mut entries = Vec::new();
-        for _ in 0..entry_count {
-            let key =
-                i32::from_le_bytes(buffer[current_pos..current_pos + 4].try_into().map_err(
-                    |e| DatabaseError::InvalidData(format!("Invalid key bytes: {}", e)),
-                )?);
-            current_pos += 4;
+        Ok(node)
+    }
+}
 
-            let (value, value_size) = Value::deserialize(&buffer[current_pos..])
-                .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
-            current_pos += value_size;
+fn write_key(key: &[u8], buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(key);
+}
 
-            entries.push(KeyValue { key, value });
-        }
+pub struct BTree {
+    root_page_id: Arc<RwLock<u32>>,
+}
 
-        let children: Vec<u32> = buffer[current_pos..]
-            .chunks_exact(4)
-            .map(|chunk| {
-                chunk.try_into().map(u32::from_le_bytes).map_err(|e| {
-                    DatabaseError::InvalidData(format!("Invalid child page ID: {}", e))
-                })
-            })
-            .collect::<Result<_>>()?;
+/// A leaf reached by `descend`, latched shared, with the separators fencing it
+/// in: its keys are at least `lower_fence` and below `upper_fence`.
+struct LatchedLeaf {
+    _latch: PageGuard,
+    node: BTreeNode,
+    lower_fence: Option<Vec<u8>>,
+    upper_fence: Option<Vec<u8>>,
+}
 
-        Ok(Self {
-            page_id,
-            is_leaf,
-            entries,
-            children,
-        })
+/// Descends to the leaf covering `bound`, crabbing with shared latches: a child
+/// is latched before its parent is released, so readers never see a node half
+/// split and never block one another. An excluded bound leads to the leaf with
+/// the keys after it when going `forward`, and to the one before it otherwise.
+fn descend(
+    root_page_id: &RwLock<u32>,
+    bound: &Bound<Vec<u8>>,
+    forward: bool,
+    buffer_pool: &BufferPool,
+) -> Result<LatchedLeaf> {
+    // The root lock is held until the root is latched, so it cannot be replaced
+    // in between
+    let root = root_page_id.read().unwrap();
+    let mut latch = buffer_pool.latch(*root, LatchMode::Shared)?;
+    drop(root);
+
+    let mut lower_fence = None;
+    let mut upper_fence = None;
+    loop {
+        let node = BTreeNode::deserialize(&latch.page().data)?;
+        if node.is_leaf {
+            return Ok(LatchedLeaf {
+                _latch: latch,
+                node,
+                lower_fence,
+                upper_fence,
+            });
+        }
+
+        let child_idx = match bound {
+            Bound::Included(key) => node.child_index(key),
+            Bound::Excluded(key) if forward => node.child_index(key),
+            Bound::Excluded(key) => node.keys.partition_point(|separator| separator < key),
+            Bound::Unbounded if forward => 0,
+            Bound::Unbounded => node.children.len() - 1,
+        };
+        if child_idx > 0 {
+            lower_fence = Some(node.keys[child_idx - 1].clone());
+        }
+        if let Some(separator) = node.keys.get(child_idx) {
+            upper_fence = Some(separator.clone());
+        }
+        let child_page_id = *node
+            .children
+            .get(child_idx)
+            .ok_or_else(|| DatabaseError::InvalidData("Invalid child index".to_string()))?;
+        latch = buffer_pool.latch(child_page_id, LatchMode::Shared)?;
     }
 }
 
-pub struct BTree {
-    root_page_id: Arc<RwLock<u32>>,
+/// The exclusive latches a writer holds: the root lock while the root may be
+/// replaced, the path from the highest node the change can reach down to the
+/// leaf, and the siblings it latched to rebalance or relink.
+struct WritePath<'a> {
+    root: Option<RwLockWriteGuard<'a, u32>>,
+    latches: Vec<PageGuard>,
+    siblings: Vec<PageGuard>,
+}
+
+impl WritePath<'_> {
+    /// Latches `page_id` exclusively unless the writer already holds it.
+    fn latch(&mut self, page_id: u32, buffer_pool: &BufferPool) -> Result<()> {
+        let held = self
+            .latches
+            .iter()
+            .chain(&self.siblings)
+            .any(|latch| latch.page_id() == page_id);
+        if !held {
+            self.siblings
+                .push(buffer_pool.latch(page_id, LatchMode::Exclusive)?);
+        }
+        Ok(())
+    }
+
+    /// Releases the path below `depth` and every sibling, once the levels below
+    /// are finished with.
+    fn release_below(&mut self, depth: usize) {
+        self.latches.truncate(depth + 1);
+        self.siblings.clear();
+    }
 }
 
 impl BTree {
@@ -175,425 +409,928 @@ impl BTree {
         }
     }
 
-    pub fn init(&self, buffer_pool: &mut BufferPool) -> Result<()> {
+    pub fn init(&self, buffer_pool: &BufferPool) -> Result<()> {
         let root_page_id = *self.root_page_id.read().unwrap();
         let root = BTreeNode::new(root_page_id, true);
-        let mut page = Page::new(root_page_id);
-        page.data = root.serialize();
-        buffer_pool.write_page(root_page_id, page)?;
-        Ok(())
+        self.write_node(&root, buffer_pool)
     }
 
     pub fn root_page_id(&self) -> u32 {
         *self.root_page_id.read().unwrap()
     }
 
-    pub fn search(&self, key: i32, buffer_pool: &mut BufferPool) -> Result<Option<Value>> {
-        let mut current_page_id = *self.root_page_id.read().unwrap();
+    pub fn search(&self, key: impl Into<Key>, buffer_pool: &BufferPool) -> Result<Option<Value>> {
+        Ok(self
+            .search_version(key, buffer_pool)?
+            .map(|(_, value)| value))
+    }
 
-        loop {
-            let page = buffer_pool.get_page(current_page_id)?;
-            let node = BTreeNode::deserialize(&page.data)?;
-
-            // First check if key exists in current node
-            let idx = node.entries.binary_search_by_key(&key, |entry| entry.key);
-
-            match idx {
-                Ok(i) => return Ok(Some(node.entries[i].value.clone())),
-                Err(i) if !node.is_leaf => {
-                    // Key not found in current node, traverse to appropriate child
-                    if i < node.children.len() {
-                        current_page_id = node.children[i];
-                    } else {
-                        return Ok(None);
-                    }
-                }
-                _ => return Ok(None),
+    /// Looks up `key` together with the version stored with it.
+    pub fn search_version(
+        &self,
+        key: impl Into<Key>,
+        buffer_pool: &BufferPool,
+    ) -> Result<Option<(VersionId, Value)>> {
+        let key = key.into().encode();
+        // The leaf stays latched until the value is loaded, so its overflow
+        // chain cannot be freed meanwhile
+        let leaf = descend(
+            &self.root_page_id,
+            &Bound::Included(key.clone()),
+            true,
+            buffer_pool,
+        )?;
+
+        match leaf
+            .node
+            .entries
+            .binary_search_by(|entry| entry.key.cmp(&key))
+        {
+            Ok(idx) => {
+                let entry = &leaf.node.entries[idx];
+                Ok(Some((entry.version, entry.payload.load(buffer_pool)?)))
             }
+            Err(_) => Ok(None),
         }
     }
 
-    pub fn insert(&mut self, key: i32, value: Value, buffer_pool: &mut BufferPool) -> Result<()> {
-        let root_page_id = *self.root_page_id.read().unwrap();
-        // Get root node
-        let root_page = buffer_pool.get_page(root_page_id)?;
-        let root_node = BTreeNode::deserialize(&root_page.data)?;
-
-        if root_node.is_full() {
-            // Create new root
-            let new_root_page = buffer_pool.new_page()?;
-            let mut new_root = BTreeNode::new(new_root_page.header.page_id, false);
-            new_root.children.push(root_page_id);
-
-            // Write new empty root
-            let mut page = Page::new(new_root.page_id);
-            page.data = new_root.serialize();
-            buffer_pool.write_page(new_root.page_id, page)?;
-
-            // Split old root
-            self.split_child(new_root.page_id, 0, buffer_pool)?;
-
-            // Update root page id
-            let mut root_page_id_write = self.root_page_id.write().unwrap();
-            *root_page_id_write = new_root.page_id;
-            // Insert into new root
-
-            drop(root_page_id_write);
-            self.insert_non_full(new_root.page_id, key, value, buffer_pool)?;
-        } else {
-            self.insert_non_full(root_page_id, key, value, buffer_pool)?;
+    /// Descends to the leaf for `key` with exclusive latches. Once a node is
+    /// safe, meaning the change cannot spread above it, the latches above it and
+    /// the root lock are released, so writers in different subtrees only contend
+    /// near the root. `is_safe` is also told whether the node is the root.
+    fn descend_exclusive(
+        &self,
+        key: &[u8],
+        mut is_safe: impl FnMut(&BTreeNode, bool) -> bool,
+        buffer_pool: &BufferPool,
+    ) -> Result<WritePath<'_>> {
+        let root = self.root_page_id.write().unwrap();
+        let mut page_id = *root;
+        let mut path = WritePath {
+            root: Some(root),
+            latches: Vec::new(),
+            siblings: Vec::new(),
+        };
+
+        let mut is_root = true;
+        loop {
+            let latch = buffer_pool.latch(page_id, LatchMode::Exclusive)?;
+            let node = BTreeNode::deserialize(&latch.page().data)?;
+            if is_safe(&node, is_root) {
+                path.root = None;
+                path.latches.clear();
+            }
+            path.latches.push(latch);
+            if node.is_leaf {
+                return Ok(path);
+            }
+            page_id = node.children[node.child_index(key)];
+            is_root = false;
         }
-        Ok(())
     }
 
-    fn insert_non_full(
-        &mut self,
-        page_id: u32,
-        key: i32,
+    /// Inserts `key`, replacing its value if it is already present. Values over
+    /// `MAX_INLINE_VALUE_SIZE` are written to overflow pages first; keys over
+    /// `MAX_KEY_SIZE` are rejected. The value is stored as frozen, visible to
+    /// every snapshot.
+    pub fn insert(
+        &self,
+        key: impl Into<Key>,
         value: Value,
-        buffer_pool: &mut BufferPool,
+        buffer_pool: &BufferPool,
     ) -> Result<()> {
-        let page = buffer_pool.get_page(page_id)?;
-        let mut node = BTreeNode::deserialize(&page.data)?;
-
-        if node.is_leaf {
-            // Insert into leaf node
-            let pos = node
-                .entries
-                .iter()
-                .position(|entry| entry.key > key)
-                .unwrap_or(node.entries.len());
-
-            node.entries.insert(
-                pos,
-                KeyValue {
-                    key,
-                    value: value.clone(),
-                },
-            );
-
-            // Write updated node
-            let mut page = Page::new(node.page_id);
-            page.data = node.serialize();
-            buffer_pool.write_page(node.page_id, page)?;
-        } else {
-            // Find child to insert into
-            let child_idx = node.entries.partition_point(|entry| entry.key <= key);
-
-            // Ensure child_idx is valid
-            if child_idx >= node.children.len() {
-                return Err(DatabaseError::InvalidData("Invalid child index".to_string()).into());
-            }
-
-            let child_page_id = node.children[child_idx];
-            let child_page = buffer_pool.get_page(child_page_id)?;
-            let child_node = BTreeNode::deserialize(&child_page.data)?;
-
-            if child_node.is_full() {
-                // Split child if full
-                self.split_child(page_id, child_idx, buffer_pool)?;
+        self.insert_version(key, VersionId::frozen(), value, buffer_pool)
+    }
 
-                // Get updated node after split
-                let page = buffer_pool.get_page(page_id)?;
-                let node = BTreeNode::deserialize(&page.data)?;
+    /// Like `insert`, storing `version` with the value.
+    pub fn insert_version(
+        &self,
+        key: impl Into<Key>,
+        version: VersionId,
+        value: Value,
+        buffer_pool: &BufferPool,
+    ) -> Result<()> {
+        let key = key.into().encode();
+        if key.len() > MAX_KEY_SIZE {
+            return Err(DatabaseError::KeyTooLarge {
+                size: key.len(),
+                max: MAX_KEY_SIZE,
+            });
+        }
 
-                // Determine which child to follow
-                let new_child_idx = if key > node.entries[child_idx].key {
-                    child_idx + 1
+        let entry = KeyValue {
+            key,
+            version,
+            payload: self.store(value, buffer_pool)?,
+        };
+        let entry_size = entry.size();
+        let mut path = self.descend_exclusive(
+            &entry.key.clone(),
+            |node, _| {
+                // A leaf split adds one separator to the parent, rarely two
+                let growth = if node.is_leaf {
+                    entry_size
                 } else {
-                    child_idx
+                    2 * MAX_SEPARATOR_SIZE
                 };
-
-                // Recursively insert into child
-                self.insert_non_full(node.children[new_child_idx], key, value, buffer_pool)?;
-            } else {
-                // Recursively insert into child
-                self.insert_non_full(child_page_id, key, value, buffer_pool)?;
-            }
+                node.size() + growth <= NODE_CAPACITY
+            },
+            buffer_pool,
+        )?;
+
+        let top_page_id = path.latches[0].page_id();
+        let splits = self.insert_into(top_page_id, entry, buffer_pool, &mut path)?;
+        if splits.is_empty() {
+            return Ok(());
         }
-        Ok(())
-    }
-
-    fn split_child(
-        &mut self,
-        parent_page_id: u32,
-        child_idx: usize,
-        buffer_pool: &mut BufferPool,
-    ) -> Result<()> {
-        // Get parent and child nodes
-        let parent_page = buffer_pool.get_page(parent_page_id)?;
-        let mut parent = BTreeNode::deserialize(&parent_page.data)?;
-
-        let child_page_id = parent.children[child_idx];
-        let child_page = buffer_pool.get_page(child_page_id)?;
-        let mut child = BTreeNode::deserialize(&child_page.data)?;
-
-        // Create new sibling
-        let new_sibling_page = buffer_pool.new_page()?;
-        let mut new_sibling = BTreeNode::new(new_sibling_page.header.page_id, child.is_leaf);
+        let Some(root) = path.root.as_mut() else {
+            return Err(DatabaseError::InvalidData(
+                "A split reached past the highest latched node".to_string(),
+            ));
+        };
+
+        // The root split, so the tree grows by a level
+        let new_root_page_id = buffer_pool.new_page()?.page_id();
+        let mut new_root = BTreeNode::new(new_root_page_id, false);
+        new_root.children.push(top_page_id);
+        for (separator, sibling_page_id) in splits {
+            new_root.keys.push(separator);
+            new_root.children.push(sibling_page_id);
+        }
+        self.write_node(&new_root, buffer_pool)?;
 
-        // Move half of child's entries to new sibling
-        let mid_idx = (ORDER - 1) / 2;
-        let mid_key = child.entries[mid_idx].clone();
+        // Update root page id, in the header first so the change is logged
+        // together with the split
+        self.set_root(root, new_root_page_id, buffer_pool)
+    }
 
-        new_sibling.entries = child.entries.split_off(mid_idx + 1);
-        child.entries.pop(); // Remove middle key
+    /// Inserts into the subtree at `page_id`. If the node had to split, returns the
+    /// separator key and page ID of each new right sibling, in key order, for the
+    /// parent to add.
+    fn insert_into(
+        &self,
+        page_id: u32,
+        entry: KeyValue,
+        buffer_pool: &BufferPool,
+        path: &mut WritePath,
+    ) -> Result<Vec<(Vec<u8>, u32)>> {
+        let mut node = self.get_node(page_id, buffer_pool)?;
 
-        if !child.is_leaf {
-            new_sibling.children = child.children.split_off(mid_idx + 1);
+        if node.is_leaf {
+            match node
+                .entries
+                .binary_search_by(|other| other.key.cmp(&entry.key))
+            {
+                Ok(idx) => {
+                    let old = std::mem::replace(&mut node.entries[idx], entry);
+                    self.release(&old.payload, buffer_pool)?;
+                }
+                Err(idx) => node.entries.insert(idx, entry),
+            }
+        } else {
+            let child_idx = node.child_index(&entry.key);
+            let splits = self.insert_into(node.children[child_idx], entry, buffer_pool, path)?;
+            if splits.is_empty() {
+                return Ok(splits);
+            }
+            for (offset, (separator, sibling_page_id)) in splits.into_iter().enumerate() {
+                node.keys.insert(child_idx + offset, separator);
+                node.children
+                    .insert(child_idx + offset + 1, sibling_page_id);
+            }
         }
 
-        // Insert new key into parent
-        parent.entries.insert(child_idx, mid_key);
-        parent.children.insert(child_idx + 1, new_sibling.page_id);
+        if node.is_overfull() {
+            return if node.is_leaf {
+                self.split_leaf(node, buffer_pool, path)
+            } else {
+                self.split_internal(node, buffer_pool)
+                    .map(|split| vec![split])
+            };
+        }
+        self.write_node(&node, buffer_pool)?;
+        Ok(Vec::new())
+    }
 
-        // Write all changes back to disk
-        let mut parent_page = Page::new(parent.page_id);
-        parent_page.data = parent.serialize();
-        buffer_pool.write_page(parent.page_id, parent_page)?;
+    /// Splits an overfull leaf into as many leaves as it takes for each to fit in a
+    /// page, usually two, and links the new ones into the leaf chain. Each new
+    /// leaf's first key is copied up as its separator.
+    fn split_leaf(
+        &self,
+        mut node: BTreeNode,
+        buffer_pool: &BufferPool,
+        path: &mut WritePath,
+    ) -> Result<Vec<(Vec<u8>, u32)>> {
+        let mut parts = partition_entries(std::mem::take(&mut node.entries)).into_iter();
+        node.entries = parts.next().unwrap();
+
+        let next_page_id = node.next_leaf;
+        let mut splits = Vec::new();
+        let mut left = node;
+        for entries in parts {
+            let sibling_page_id = buffer_pool.new_page()?.page_id();
+            let mut sibling = BTreeNode::new(sibling_page_id, true);
+            sibling.entries = entries;
+            sibling.prev_leaf = Some(left.page_id);
+            left.next_leaf = Some(sibling_page_id);
+            self.write_node(&left, buffer_pool)?;
+
+            splits.push((sibling.entries[0].key.clone(), sibling_page_id));
+            left = sibling;
+        }
 
-        let mut child_page = Page::new(child.page_id);
-        child_page.data = child.serialize();
-        buffer_pool.write_page(child.page_id, child_page)?;
+        left.next_leaf = next_page_id;
+        if let Some(next_page_id) = next_page_id {
+            path.latch(next_page_id, buffer_pool)?;
+            let mut next = self.get_node(next_page_id, buffer_pool)?;
+            next.prev_leaf = Some(left.page_id);
+            self.write_node(&next, buffer_pool)?;
+        }
+        self.write_node(&left, buffer_pool)?;
+        Ok(splits)
+    }
 
-        let mut sibling_page = Page::new(new_sibling.page_id);
-        sibling_page.data = new_sibling.serialize();
-        buffer_pool.write_page(new_sibling.page_id, sibling_page)?;
+    /// Moves the upper half of an internal node, by size, into a new right sibling
+    /// and returns the middle key, which moves up as the separator between them.
+    fn split_internal(
+        &self,
+        mut node: BTreeNode,
+        buffer_pool: &BufferPool,
+    ) -> Result<(Vec<u8>, u32)> {
+        let sibling_page_id = buffer_pool.new_page()?.page_id();
+        let mut sibling = BTreeNode::new(sibling_page_id, false);
+
+        let mid = split_point(&node.keys);
+        sibling.keys = node.keys.split_off(mid + 1);
+        sibling.children = node.children.split_off(mid + 1);
+        let separator = node.keys.pop().unwrap();
+
+        self.write_node(&node, buffer_pool)?;
+        self.write_node(&sibling, buffer_pool)?;
+        Ok((separator, sibling_page_id))
+    }
 
-        Ok(())
+    /// Replaces the version stored with `key`, leaving its value as it is. Fails
+    /// with `KeyNotFound` if the key is not in the tree.
+    pub fn set_version(
+        &self,
+        key: impl Into<Key>,
+        version: VersionId,
+        buffer_pool: &BufferPool,
+    ) -> Result<()> {
+        let key = key.into();
+        let encoded = key.encode();
+        // The entry keeps its size, so no node above the leaf changes
+        let path = self.descend_exclusive(&encoded, |_, _| true, buffer_pool)?;
+
+        let mut leaf = self.get_node(path.latches[0].page_id(), buffer_pool)?;
+        let Ok(idx) = leaf
+            .entries
+            .binary_search_by(|entry| entry.key.cmp(&encoded))
+        else {
+            return Err(DatabaseError::KeyNotFound(key));
+        };
+        leaf.entries[idx].version = version;
+        self.write_node(&leaf, buffer_pool)
     }
 
-    pub fn delete(&mut self, key: i32, buffer_pool: &mut BufferPool) -> Result<()> {
-        let root_page_id = *self.root_page_id.read().unwrap();
-        self.delete_key(root_page_id, key, buffer_pool)
-            .map_err(|_| DatabaseError::KeyNotFound(key))
+    /// Deletes `key`, failing with `KeyNotFound` if it is not in the tree.
+    pub fn delete(&self, key: impl Into<Key>, buffer_pool: &BufferPool) -> Result<()> {
+        let key = key.into();
+        let encoded = key.encode();
+
+        // A node with the key as a separator replaces it with the key's successor,
+        // read from the leaves below, so none of those can be released early
+        let mut below_separator = false;
+        let mut path = self.descend_exclusive(
+            &encoded,
+            |node, is_root| {
+                if below_separator {
+                    return false;
+                }
+                let safe = if node.is_leaf {
+                    match node
+                        .entries
+                        .binary_search_by(|entry| entry.key.cmp(&encoded))
+                    {
+                        Ok(idx) => {
+                            is_root || node.size() - node.entries[idx].size() >= MIN_NODE_SIZE
+                        }
+                        Err(_) => true,
+                    }
+                } else if is_root {
+                    // A merge below takes at most one key, and the root only
+                    // collapses once it has none
+                    node.keys.len() >= 2
+                } else {
+                    node.size() >= MIN_NODE_SIZE + MAX_SEPARATOR_SIZE
+                };
+                below_separator = !node.is_leaf && node.keys.binary_search(&encoded).is_ok();
+                safe
+            },
+            buffer_pool,
+        )?;
+
+        let top_page_id = path.latches[0].page_id();
+        if self
+            .delete_from(top_page_id, &encoded, 0, buffer_pool, &mut path)?
+            .is_none()
+        {
+            return Err(DatabaseError::KeyNotFound(key));
+        }
+        self.collapse_root(&mut path, buffer_pool)
     }
 
-    fn delete_key(&mut self, page_id: u32, key: i32, buffer_pool: &mut BufferPool) -> Result<()> {
-        let page = buffer_pool.get_page(page_id)?;
-        let mut node = BTreeNode::deserialize(&page.data)?;
+    /// Deletes `key` from the subtree at `page_id`, the node at `depth` on the
+    /// latched path, rebalancing children that drop below the minimum. Returns
+    /// whether the node itself is now underfull, or `None` if the key was not
+    /// found.
+    fn delete_from(
+        &self,
+        page_id: u32,
+        key: &[u8],
+        depth: usize,
+        buffer_pool: &BufferPool,
+        path: &mut WritePath,
+    ) -> Result<Option<bool>> {
+        let mut node = self.get_node(page_id, buffer_pool)?;
 
         if node.is_leaf {
-            // Case 1: If the key is in this leaf node, remove it
-            return if let Some(idx) = node.entries.iter().position(|entry| entry.key == key) {
-                node.entries.remove(idx);
-                let mut page = Page::new(page_id);
-                page.data = node.serialize();
-                buffer_pool.write_page(page_id, page)?;
-                Ok(())
-            } else {
-                Ok(())
+            let Ok(idx) = node
+                .entries
+                .binary_search_by(|entry| entry.key.as_slice().cmp(key))
+            else {
+                return Ok(None);
             };
+            let entry = node.entries.remove(idx);
+            self.release(&entry.payload, buffer_pool)?;
+            self.write_node(&node, buffer_pool)?;
         } else {
-            // Find the child which might contain the key
-            let idx = match node.entries.binary_search_by_key(&key, |entry| entry.key) {
-                Ok(i) => i + 1,
-                Err(i) => i,
+            let child_idx = node.child_index(key);
+            let Some(child_underfull) =
+                self.delete_from(node.children[child_idx], key, depth + 1, buffer_pool, path)?
+            else {
+                return Ok(None);
             };
-
-            let child_page_id = node.children[idx];
-
-            // Ensure the child has at least MIN_KEYS + 1 keys
-            self.ensure_min_keys(page_id, idx, buffer_pool)?;
-
-            // Recursively delete from the child
-            self.delete_key(child_page_id, key, buffer_pool)?;
+            self.replace_separator(&mut node, child_idx, key, buffer_pool)?;
+            if child_underfull {
+                // Release the levels below before latching siblings: a writer
+                // working under a sibling may be waiting for a leaf down there
+                path.release_below(depth + 1);
+                self.rebalance_child(&mut node, child_idx, buffer_pool, path)?;
+            }
         }
-        Ok(())
+
+        Ok(Some(node.is_underfull()))
     }
 
-    fn ensure_min_keys(
-        &mut self,
-        parent_page_id: u32,
+    /// After `key` was deleted below `node.children[child_idx]`, replaces the
+    /// separator equal to it with its successor, the new smallest key of that
+    /// child, so separators only name keys in the tree. The old separator still
+    /// routes correctly, so it stays if the child is empty or the successor does
+    /// not fit in the node.
+    ///
+    /// The successor is read without latches. The writer has held the node since
+    /// before it descended along the child's leftmost path, and nothing else can
+    /// reach that path except through the node.
+    fn replace_separator(
+        &self,
+        node: &mut BTreeNode,
         child_idx: usize,
-        buffer_pool: &mut BufferPool,
+        key: &[u8],
+        buffer_pool: &BufferPool,
     ) -> Result<()> {
-        // Get parent node
-        let parent_page = buffer_pool.get_page(parent_page_id)?;
-        let parent = BTreeNode::deserialize(&parent_page.data)?;
-
-        let child_page_id = parent.children[child_idx];
-        let child_page = buffer_pool.get_page(child_page_id)?;
-        let child = BTreeNode::deserialize(&child_page.data)?;
-
-        if child.entries.len() >= MIN_KEYS {
+        if child_idx == 0 || node.keys[child_idx - 1] != key {
             return Ok(());
         }
 
-        // try to borrow from left sibling
-        if child_idx > 0 {
-            let left_sibling_page_id = parent.children[child_idx - 1];
-            let left_sibling_page = buffer_pool.get_page(left_sibling_page_id)?;
-            let mut left_sibling = BTreeNode::deserialize(&left_sibling_page.data)?;
-
-            if left_sibling.entries.len() > MIN_KEYS {
-                let mut child = child;
-                let mut parent = parent;
+        let mut leaf = self.get_node(node.children[child_idx], buffer_pool)?;
+        while !leaf.is_leaf {
+            leaf = self.get_node(leaf.children[0], buffer_pool)?;
+        }
+        let Some(successor) = leaf.entries.first() else {
+            return Ok(());
+        };
+        if node.size() - key_size(key) + key_size(&successor.key) > NODE_CAPACITY {
+            return Ok(());
+        }
 
-                let separator = parent.entries.remove(child_idx - 1);
-                child.entries.insert(0, separator);
+        node.keys[child_idx - 1] = successor.key.clone();
+        self.write_node(node, buffer_pool)
+    }
 
-                let new_separator = left_sibling.entries.pop().unwrap();
-                parent.entries.insert(child_idx - 1, new_separator);
+    /// Refills the underfull child at `child_idx` by borrowing from a sibling, or
+    /// merges it with one. Writes every changed node, including `parent`. A child
+    /// that can do neither, because its siblings hold large values, stays as it is.
+    fn rebalance_child(
+        &self,
+        parent: &mut BTreeNode,
+        child_idx: usize,
+        buffer_pool: &BufferPool,
+        path: &mut WritePath,
+    ) -> Result<()> {
+        let mut child = self.get_node(parent.children[child_idx], buffer_pool)?;
 
-                if !child.is_leaf {
-                    let moved_child = left_sibling.children.pop().unwrap();
-                    child.children.insert(0, moved_child);
+        // try to borrow from left sibling
+        if child_idx > 0 {
+            path.latch(parent.children[child_idx - 1], buffer_pool)?;
+            let mut left = self.get_node(parent.children[child_idx - 1], buffer_pool)?;
+            if can_borrow(parent, child_idx - 1, &left, &child, true) {
+                if child.is_leaf {
+                    child.entries.insert(0, left.entries.pop().unwrap());
+                    parent.keys[child_idx - 1] = child.entries[0].key.clone();
+                } else {
+                    // Rotate through the parent's separator
+                    let separator = std::mem::replace(
+                        &mut parent.keys[child_idx - 1],
+                        left.keys.pop().unwrap(),
+                    );
+                    child.keys.insert(0, separator);
+                    child.children.insert(0, left.children.pop().unwrap());
                 }
 
-                // Write changes back to pages
-                let mut parent_page = Page::new(parent_page_id);
-                let mut child_page = Page::new(child_page_id);
-                let mut left_sibling_page = Page::new(left_sibling_page_id);
-
-                parent_page.data = parent.serialize();
-                child_page.data = child.serialize();
-                left_sibling_page.data = left_sibling.serialize();
+                self.write_node(&left, buffer_pool)?;
+                self.write_node(&child, buffer_pool)?;
+                return self.write_node(parent, buffer_pool);
+            }
+        }
 
-                buffer_pool.write_page(parent_page_id, parent_page)?;
-                buffer_pool.write_page(child_page_id, child_page)?;
-                buffer_pool.write_page(left_sibling_page_id, left_sibling_page)?;
+        // try to borrow from right sibling
+        if child_idx + 1 < parent.children.len() {
+            path.latch(parent.children[child_idx + 1], buffer_pool)?;
+            let mut right = self.get_node(parent.children[child_idx + 1], buffer_pool)?;
+            if can_borrow(parent, child_idx, &right, &child, false) {
+                if child.is_leaf {
+                    child.entries.push(right.entries.remove(0));
+                    parent.keys[child_idx] = right.entries[0].key.clone();
+                } else {
+                    let separator =
+                        std::mem::replace(&mut parent.keys[child_idx], right.keys.remove(0));
+                    child.keys.push(separator);
+                    child.children.push(right.children.remove(0));
+                }
 
-                return Ok(());
+                self.write_node(&right, buffer_pool)?;
+                self.write_node(&child, buffer_pool)?;
+                return self.write_node(parent, buffer_pool);
             }
         }
 
-        // try to borrow from right sibling
-        if child_idx < parent.children.len() - 1 {
-            let right_sibling_page_id = parent.children[child_idx + 1];
-            let right_sibling_page = buffer_pool.get_page(right_sibling_page_id)?;
-            let mut right_sibling = BTreeNode::deserialize(&right_sibling_page.data)?;
+        // If we can't borrow, merge with a sibling the two fit together with
+        if child_idx > 0 && self.can_merge(parent, child_idx - 1, buffer_pool)? {
+            return self.merge_children(parent, child_idx - 1, buffer_pool, path);
+        }
+        if child_idx + 1 < parent.children.len()
+            && self.can_merge(parent, child_idx, buffer_pool)?
+        {
+            return self.merge_children(parent, child_idx, buffer_pool, path);
+        }
+        Ok(())
+    }
 
-            if right_sibling.entries.len() > MIN_KEYS {
-                let mut child = child;
-                let mut parent = parent;
+    /// Whether the children either side of `parent.keys[left_idx]` fit in one page.
+    fn can_merge(
+        &self,
+        parent: &BTreeNode,
+        left_idx: usize,
+        buffer_pool: &BufferPool,
+    ) -> Result<bool> {
+        let left = self.get_node(parent.children[left_idx], buffer_pool)?;
+        let right = self.get_node(parent.children[left_idx + 1], buffer_pool)?;
+        let merged_size = if left.is_leaf {
+            left.size() + right.size() - NODE_HEADER_SIZE - LEAF_LINKS_SIZE
+        } else {
+            // The separator comes down between them
+            left.size() + right.size() - NODE_HEADER_SIZE + key_size(&parent.keys[left_idx])
+        };
+        Ok(merged_size <= NODE_CAPACITY)
+    }
 
-                let separator = parent.entries.remove(child_idx);
-                child.entries.push(separator);
+    /// Merges the child right of `parent.keys[left_idx]` into the one left of it
+    /// and frees the right child's page.
+    fn merge_children(
+        &self,
+        parent: &mut BTreeNode,
+        left_idx: usize,
+        buffer_pool: &BufferPool,
+        path: &mut WritePath,
+    ) -> Result<()> {
+        let mut left = self.get_node(parent.children[left_idx], buffer_pool)?;
+        let mut right = self.get_node(parent.children[left_idx + 1], buffer_pool)?;
+
+        let separator = parent.keys.remove(left_idx);
+        parent.children.remove(left_idx + 1);
+
+        if left.is_leaf {
+            left.entries.append(&mut right.entries);
+            left.next_leaf = right.next_leaf;
+            if let Some(next_page_id) = right.next_leaf {
+                path.latch(next_page_id, buffer_pool)?;
+                let mut next = self.get_node(next_page_id, buffer_pool)?;
+                next.prev_leaf = Some(left.page_id);
+                self.write_node(&next, buffer_pool)?;
+            }
+        } else {
+            left.keys.push(separator);
+            left.keys.append(&mut right.keys);
+            left.children.append(&mut right.children);
+        }
 
-                let new_separator = right_sibling.entries.remove(0);
-                parent.entries.insert(child_idx, new_separator);
+        self.write_node(&left, buffer_pool)?;
+        self.write_node(parent, buffer_pool)?;
+        buffer_pool.free_page(right.page_id)
+    }
 
-                if !child.is_leaf {
-                    let moved_child = right_sibling.children.remove(0);
-                    child.children.push(moved_child);
-                }
+    /// Makes the only child of an empty internal root the new root, so the tree
+    /// shrinks by a level once a merge has taken the root's last separator. Only
+    /// a writer still holding the root lock can have emptied the root.
+    fn collapse_root(&self, path: &mut WritePath, buffer_pool: &BufferPool) -> Result<()> {
+        let Some(root) = path.root.as_mut() else {
+            return Ok(());
+        };
+        let root_page_id = **root;
+        let node = self.get_node(root_page_id, buffer_pool)?;
+        if node.is_leaf || !node.keys.is_empty() {
+            return Ok(());
+        }
 
-                // Write changes back to pages
-                let mut parent_page = Page::new(parent_page_id);
-                let mut child_page = Page::new(child_page_id);
-                let mut right_sibling_page = Page::new(right_sibling_page_id);
+        self.set_root(root, node.children[0], buffer_pool)?;
+        buffer_pool.free_page(root_page_id)
+    }
 
-                parent_page.data = parent.serialize();
-                child_page.data = child.serialize();
-                right_sibling_page.data = right_sibling.serialize();
+    fn set_root(&self, root: &mut u32, page_id: u32, buffer_pool: &BufferPool) -> Result<()> {
+        buffer_pool.set_root_page_id(page_id)?;
+        *root = page_id;
+        Ok(())
+    }
 
-                buffer_pool.write_page(parent_page_id, parent_page)?;
-                buffer_pool.write_page(child_page_id, child_page)?;
-                buffer_pool.write_page(right_sibling_page_id, right_sibling_page)?;
+    /// Keeps `value` inline if it is small enough, and otherwise writes it to a
+    /// new overflow chain.
+    fn store(&self, value: Value, buffer_pool: &BufferPool) -> Result<Payload> {
+        let size = value.serialized_size();
+        if size <= MAX_INLINE_VALUE_SIZE {
+            return Ok(Payload::Inline(value));
+        }
+        let size = u32::try_from(size).map_err(|_| DatabaseError::ValueTooLarge {
+            size,
+            max: u32::MAX as usize,
+        })?;
+
+        let first_page_id = overflow::write_chain(&value.serialize(), buffer_pool)?;
+        Ok(Payload::Overflow {
+            size,
+            first_page_id,
+        })
+    }
 
-                return Ok(());
+    /// Frees the overflow chain behind a payload that is being dropped.
+    fn release(&self, payload: &Payload, buffer_pool: &BufferPool) -> Result<()> {
+        match payload {
+            Payload::Inline(_) => Ok(()),
+            Payload::Overflow { first_page_id, .. } => {
+                overflow::free_chain(*first_page_id, buffer_pool)
             }
         }
+    }
 
-        // If we can't borrow, we need to merge
-        self.merge_children(parent_page_id, child_idx, buffer_pool)
+    /// Reads a node. Callers hold its latch, or otherwise know it cannot change.
+    fn get_node(&self, page_id: u32, buffer_pool: &BufferPool) -> Result<BTreeNode> {
+        BTreeNode::deserialize(&buffer_pool.get_page(page_id)?.page().data)
     }
 
-    fn merge_children(
-        &mut self,
-        parent_page_id: u32,
-        child_idx: usize,
-        buffer_pool: &mut BufferPool,
+    /// Writes `node` into its cached page in place; the buffer pool marks the page
+    /// dirty and writes it back later. The page keeps its full size so the logged
+    /// before and after images line up byte for byte.
+    fn write_node(&self, node: &BTreeNode, buffer_pool: &BufferPool) -> Result<()> {
+        let bytes = node.serialize();
+        if bytes.len() > PAGE_SIZE - PAGE_HEADER_SIZE {
+            return Err(DatabaseError::PageFull);
+        }
+        buffer_pool.modify_page(node.page_id, |page| {
+            page.data.fill(0);
+            page.data[..bytes.len()].copy_from_slice(&bytes);
+            Ok(())
+        })
+    }
+
+    pub fn update(
+        &self,
+        key: impl Into<Key>,
+        value: Value,
+        buffer_pool: &BufferPool,
     ) -> Result<()> {
-        let parent_page = buffer_pool.get_page(parent_page_id)?;
-        let mut parent = BTreeNode::deserialize(&parent_page.data)?;
+        self.insert(key, value, buffer_pool)
+    }
 
-        let left_child_page_id = parent.children[child_idx];
-        let right_child_page_id = parent.children[child_idx + 1];
+    /// Builds the tree bottom-up from `entries`, which must come in strictly
+    /// ascending key order, packing each leaf and internal node as full as it
+    /// fits. Every page is written once, where inserting key by key rewrites a
+    /// path per key. The tree must be empty, and stays empty if loading fails,
+    /// with the pages allocated so far freed again. Entries are stored with
+    /// `version`, normally created by the loading transaction. Returns the
+    /// number of entries loaded.
+    pub fn bulk_load<I>(
+        &self,
+        entries: I,
+        version: VersionId,
+        buffer_pool: &BufferPool,
+    ) -> Result<usize>
+    where
+        I: IntoIterator<Item = (Key, Value)>,
+    {
+        // The new tree is built off to the side; only swapping in its root needs
+        // the root lock, but holding it keeps other writers out of the empty tree
+        let mut root = self.root_page_id.write().unwrap();
+        let old_root_page_id = *root;
+        let _latch = buffer_pool.latch(old_root_page_id, LatchMode::Exclusive)?;
+        let old_root = self.get_node(old_root_page_id, buffer_pool)?;
+        if !old_root.is_leaf || !old_root.entries.is_empty() {
+            return Err(DatabaseError::InvalidOperation(
+                "Bulk loading needs an empty tree".to_string(),
+            ));
+        }
+
+        let mut loaded = LoadedPages::default();
+        match self.build_tree(entries, version, &mut loaded, buffer_pool) {
+            Ok(Some((root_page_id, count))) => {
+                self.set_root(&mut root, root_page_id, buffer_pool)?;
+                buffer_pool.free_page(old_root_page_id)?;
+                Ok(count)
+            }
+            Ok(None) => Ok(0),
+            Err(e) => {
+                loaded.free(buffer_pool)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the nodes of a bulk load, recording every page it allocates in
+    /// `loaded`. Returns the new root and the number of entries, or `None` if
+    /// there were no entries.
+    fn build_tree<I>(
+        &self,
+        entries: I,
+        version: VersionId,
+        loaded: &mut LoadedPages,
+        buffer_pool: &BufferPool,
+    ) -> Result<Option<(u32, usize)>>
+    where
+        I: IntoIterator<Item = (Key, Value)>,
+    {
+        // First key and page of each node on the level being built
+        let mut level: Vec<(Vec<u8>, u32)> = Vec::new();
+        // A full leaf is written once the next one has a page it can link to
+        let mut leaf: Option<BTreeNode> = None;
+        let mut count = 0;
+        for (key, value) in entries {
+            let encoded = key.encode();
+            if encoded.len() > MAX_KEY_SIZE {
+                return Err(DatabaseError::KeyTooLarge {
+                    size: encoded.len(),
+                    max: MAX_KEY_SIZE,
+                });
+            }
+            if let Some(current) = &leaf {
+                if current.entries.last().unwrap().key >= encoded {
+                    return Err(DatabaseError::InvalidOperation(format!(
+                        "Bulk load input is not in ascending key order at key {}",
+                        key
+                    )));
+                }
+            }
 
-        let left_child_page = buffer_pool.get_page(left_child_page_id)?;
-        let mut left_child = BTreeNode::deserialize(&left_child_page.data)?;
+            let payload = self.store(value, buffer_pool)?;
+            if let Payload::Overflow { first_page_id, .. } = payload {
+                loaded.chains.push(first_page_id);
+            }
+            let entry = KeyValue {
+                key: encoded,
+                version,
+                payload,
+            };
+            match &mut leaf {
+                Some(current) if current.size() + entry.size() <= NODE_CAPACITY => {
+                    current.entries.push(entry)
+                }
+                _ => {
+                    let page_id = buffer_pool.new_page()?.page_id();
+                    loaded.nodes.push(page_id);
+                    let mut next = BTreeNode::new(page_id, true);
+                    if let Some(mut full) = leaf.take() {
+                        full.next_leaf = Some(page_id);
+                        next.prev_leaf = Some(full.page_id);
+                        self.write_node(&full, buffer_pool)?;
+                    }
+                    level.push((entry.key.clone(), page_id));
+                    next.entries.push(entry);
+                    leaf = Some(next);
+                }
+            }
+            count += 1;
+        }
+        let Some(last_leaf) = leaf else {
+            return Ok(None);
+        };
+        self.write_node(&last_leaf, buffer_pool)?;
 
-        let right_child_page = buffer_pool.get_page(right_child_page_id)?;
-        let mut right_child = BTreeNode::deserialize(&right_child_page.data)?;
+        while level.len() > 1 {
+            level = self.build_internal_level(level, &mut loaded.nodes, buffer_pool)?;
+        }
+        Ok(Some((level[0].1, count)))
+    }
 
-        // Move seperator to left child
-        let seperator = parent.entries.remove(child_idx);
-        left_child.entries.push(seperator);
+    /// Packs `children`, given by first key and page, into as few internal nodes
+    /// as fit, and returns the first key and page of each new node. The pages
+    /// allocated are added to `pages`.
+    fn build_internal_level(
+        &self,
+        children: Vec<(Vec<u8>, u32)>,
+        pages: &mut Vec<u32>,
+        buffer_pool: &BufferPool,
+    ) -> Result<Vec<(Vec<u8>, u32)>> {
+        let mut groups: Vec<Vec<(Vec<u8>, u32)>> = Vec::new();
+        let mut size = 0;
+        for child in children {
+            // Every child but a node's first comes with a separator
+            let added = key_size(&child.0) + CHILD_SIZE;
+            match groups.last_mut() {
+                Some(group) if size + added <= NODE_CAPACITY => {
+                    group.push(child);
+                    size += added;
+                }
+                _ => {
+                    groups.push(vec![child]);
+                    size = NODE_HEADER_SIZE + CHILD_SIZE;
+                }
+            }
+        }
+        // An internal node needs a separator, so a last node left with a single
+        // child takes another from its neighbour
+        let count = groups.len();
+        if count > 1 && groups[count - 1].len() == 1 {
+            let moved = groups[count - 2].pop().unwrap();
+            groups[count - 1].insert(0, moved);
+        }
 
-        // Move all keys and children from right child to left child
-        left_child.entries.extend(right_child.entries.drain(..));
-        if !left_child.is_leaf {
-            left_child.children.extend(right_child.children.drain(..));
+        let mut level = Vec::with_capacity(groups.len());
+        for group in groups {
+            let page_id = buffer_pool.new_page()?.page_id();
+            pages.push(page_id);
+            let mut node = BTreeNode::new(page_id, false);
+            let mut first_key = None;
+            for (key, child) in group {
+                if first_key.is_none() {
+                    first_key = Some(key);
+                } else {
+                    node.keys.push(key);
+                }
+                node.children.push(child);
+            }
+            self.write_node(&node, buffer_pool)?;
+            level.push((first_key.unwrap(), page_id));
         }
+        Ok(level)
+    }
 
-        // Remove right child from parent
-        parent.children.remove(child_idx + 1);
+    /// Returns the entries whose keys fall in `range`, in key order, stopping after
+    /// `limit` entries. Descends once to the first leaf in the range, then follows
+    /// the leaf chain.
+    pub fn range<R: RangeBounds<Key>>(
+        &self,
+        range: R,
+        limit: Option<usize>,
+        buffer_pool: &BufferPool,
+    ) -> Result<Vec<(Key, Value)>> {
+        self.range_cursor(range, buffer_pool)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
+    }
 
-        // Write changes back to pages
-        let mut parent_page = Page::new(parent_page_id);
-        let mut left_child_page = Page::new(left_child_page_id);
-        let mut right_child_page = Page::new(right_child_page_id);
+    pub fn all(&self, buffer_pool: &BufferPool) -> Result<Vec<(Key, Value)>> {
+        self.range(.., None, buffer_pool)
+    }
 
-        parent_page.data = parent.serialize();
-        left_child_page.data = left_child.serialize();
-        right_child_page.data = right_child.serialize();
+    /// Returns a cursor over the whole tree. The cursor holds no latches between
+    /// calls, so writers can change the tree while it is open.
+    pub fn cursor<'a>(&self, buffer_pool: &'a BufferPool) -> BTreeCursor<'a> {
+        self.range_cursor(.., buffer_pool)
+    }
 
-        buffer_pool.write_page(parent_page_id, parent_page)?;
-        buffer_pool.write_page(left_child_page_id, left_child_page)?;
+    /// Returns a cursor that only sees the entries with keys in `range`.
+    pub fn range_cursor<'a, R: RangeBounds<Key>>(
+        &self,
+        range: R,
+        buffer_pool: &'a BufferPool,
+    ) -> BTreeCursor<'a> {
+        BTreeCursor::new(Arc::clone(&self.root_page_id), range, buffer_pool)
+    }
+}
 
-        buffer_pool.free_page(right_child_page_id)?;
+/// Pages a bulk load has allocated so far.
+#[derive(Default)]
+struct LoadedPages {
+    // Leaf and internal node pages
+    nodes: Vec<u32>,
+    // First pages of overflow chains
+    chains: Vec<u32>,
+}
 
+impl LoadedPages {
+    /// Frees the pages of a bulk load that failed.
+    fn free(self, buffer_pool: &BufferPool) -> Result<()> {
+        for page_id in self.nodes {
+            buffer_pool.free_page(page_id)?;
+        }
+        for first_page_id in self.chains {
+            overflow::free_chain(first_page_id, buffer_pool)?;
+        }
         Ok(())
     }
+}
 
-    fn get_node(&self, page_id: u32, buffer_pool: &mut BufferPool) -> Result<BTreeNode> {
-        let page = buffer_pool.get_page(page_id)?;
-        BTreeNode::deserialize(&page.data)
+/// Splits `entries` into runs that each fit in a leaf, cutting where the two sides
+/// come out closest in size. A run of one entry always fits, since larger values
+/// are moved to overflow pages.
+fn partition_entries(mut entries: Vec<KeyValue>) -> Vec<Vec<KeyValue>> {
+    let total: usize = entries.iter().map(KeyValue::size).sum();
+    if NODE_HEADER_SIZE + LEAF_LINKS_SIZE + total <= NODE_CAPACITY || entries.len() < 2 {
+        return vec![entries];
     }
 
-    pub fn update(&mut self, key: i32, value: Value, buffer_pool: &mut BufferPool) -> Result<()> {
-        let _ = self.delete(key, buffer_pool);
-        self.insert(key, value, buffer_pool)
+    let mut prefix = 0;
+    let mut best = (usize::MAX, 1);
+    for (idx, entry) in entries[..entries.len() - 1].iter().enumerate() {
+        prefix += entry.size();
+        let larger = prefix.max(total - prefix);
+        if larger < best.0 {
+            best = (larger, idx + 1);
+        }
     }
 
-    pub fn traverse(
-        &self,
-        page_id: u32,
-        buffer_pool: &mut BufferPool,
-        result: &mut Vec<(i32, Value)>,
-    ) -> Result<()> {
-        let node = self.get_node(page_id, buffer_pool)?;
+    let right = entries.split_off(best.1);
+    let mut parts = partition_entries(entries);
+    parts.extend(partition_entries(right));
+    parts
+}
 
-        if node.is_leaf {
-            // For leaf nodes, add all entries
-            result.extend(
-                node.entries
-                    .iter()
-                    .map(|entry| (entry.key, entry.value.clone())),
-            );
-        } else {
-            // For internal nodes, traverse in order
-            for i in 0..=node.entries.len() {
-                if i < node.children.len() {
-                    // Traverse child
-                    self.traverse(node.children[i], buffer_pool, result)?;
-                }
-                if i < node.entries.len() {
-                    // Add current entry
-                    result.push((node.entries[i].key, node.entries[i].value.clone()));
-                }
-            }
+/// Index of the key to move up when splitting an internal node with `keys`,
+/// chosen so the two halves come out closest in size.
+fn split_point(keys: &[Vec<u8>]) -> usize {
+    let total: usize = keys.iter().map(|key| key_size(key)).sum();
+    let mut prefix = 0;
+    let mut best = (usize::MAX, keys.len() / 2);
+    for (idx, key) in keys.iter().enumerate() {
+        let larger = prefix.max(total - prefix - key_size(key));
+        if larger < best.0 {
+            best = (larger, idx);
         }
-
-        Ok(())
+        prefix += key_size(key);
     }
+    best.1
+}
 
-    pub fn all(&self, buffer_pool: &mut BufferPool) -> Result<Vec<(i32, Value)>> {
-        let mut result = Vec::new();
-        let root_page_id = *self.root_page_id.read().unwrap();
-        self.traverse(root_page_id, buffer_pool, &mut result)?;
-        Ok(result)
-    }
+/// Whether `child` can take one entry, or separator and child pointer, from its
+/// sibling `lender` through `parent.keys[separator_idx]`, with the lender staying
+/// at least `MIN_NODE_SIZE` and every node, the parent included, still fitting
+/// in a page.
+fn can_borrow(
+    parent: &BTreeNode,
+    separator_idx: usize,
+    lender: &BTreeNode,
+    child: &BTreeNode,
+    from_left: bool,
+) -> bool {
+    let (lent, gained, new_separator) = if lender.is_leaf {
+        let (moved, new_first) = if from_left {
+            (lender.entries.last(), lender.entries.last())
+        } else {
+            (lender.entries.first(), lender.entries.get(1))
+        };
+        let (Some(moved), Some(new_first)) = (moved, new_first) else {
+            return false;
+        };
+        (moved.size(), moved.size(), new_first.key.as_slice())
+    } else {
+        let moved_up = if from_left {
+            lender.keys.last()
+        } else {
+            lender.keys.first()
+        };
+        let Some(moved_up) = moved_up else {
+            return false;
+        };
+        let moved_down = &parent.keys[separator_idx];
+        (
+            key_size(moved_up) + CHILD_SIZE,
+            key_size(moved_down) + CHILD_SIZE,
+            moved_up.as_slice(),
+        )
+    };
+
+    let parent_size =
+        parent.size() - key_size(&parent.keys[separator_idx]) + key_size(new_separator);
+    lender.size() - lent >= MIN_NODE_SIZE
+        && child.size() + gained <= NODE_CAPACITY
+        && parent_size <= NODE_CAPACITY
 }
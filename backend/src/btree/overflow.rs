@@ -0,0 +1,97 @@
+// Overflow pages hold values too large to keep inline in a leaf. A value is cut
+// into chunks stored in a chain of pages, and the leaf entry keeps only the
+// value's length and the first page of the chain.
+
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::error::{DatabaseError, Result};
+use crate::storage::page::{PAGE_HEADER_SIZE, PAGE_SIZE};
+
+// Page 0 is the database header, so it marks the end of a chain
+const NO_NEXT_PAGE: u32 = 0;
+const CHUNK_HEADER_SIZE: usize = 6; // next page ID, chunk length
+const CHUNK_CAPACITY: usize = PAGE_SIZE - PAGE_HEADER_SIZE - CHUNK_HEADER_SIZE;
+
+/// Writes `bytes` to a new chain of overflow pages and returns the ID of the
+/// first page.
+pub fn write_chain(bytes: &[u8], buffer_pool: &BufferPool) -> Result<u32> {
+    let mut page_ids = Vec::new();
+    for _ in bytes.chunks(CHUNK_CAPACITY) {
+        page_ids.push(buffer_pool.new_page()?.page_id());
+    }
+
+    for (idx, chunk) in bytes.chunks(CHUNK_CAPACITY).enumerate() {
+        let next_page_id = page_ids.get(idx + 1).copied().unwrap_or(NO_NEXT_PAGE);
+        buffer_pool.modify_page(page_ids[idx], |page| {
+            page.data.fill(0);
+            page.data[0..4].copy_from_slice(&next_page_id.to_le_bytes());
+            page.data[4..6].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+            page.data[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            Ok(())
+        })?;
+    }
+
+    page_ids.first().copied().ok_or_else(|| {
+        DatabaseError::InvalidOperation("Cannot store an empty overflow value".to_string())
+    })
+}
+
+/// Reads back the `size` bytes stored in the chain starting at `first_page_id`.
+pub fn read_chain(first_page_id: u32, size: usize, buffer_pool: &BufferPool) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(size);
+    let mut page_id = first_page_id;
+
+    while page_id != NO_NEXT_PAGE && bytes.len() < size {
+        let frame = buffer_pool.get_page(page_id)?;
+        let page = frame.page();
+        let (next_page_id, chunk_len) = chunk_header(&page.data);
+        if chunk_len > CHUNK_CAPACITY {
+            return Err(DatabaseError::InvalidData(format!(
+                "Overflow page {} has a chunk of {} bytes",
+                page_id, chunk_len
+            )));
+        }
+        bytes.extend_from_slice(&page.data[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + chunk_len]);
+        page_id = next_page_id;
+    }
+
+    if bytes.len() != size {
+        return Err(DatabaseError::InvalidData(format!(
+            "Overflow chain at page {} holds {} bytes, expected {}",
+            first_page_id,
+            bytes.len(),
+            size
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Returns every page of the chain starting at `first_page_id` to the free list.
+pub fn free_chain(first_page_id: u32, buffer_pool: &BufferPool) -> Result<()> {
+    let mut page_id = first_page_id;
+    while page_id != NO_NEXT_PAGE {
+        // Freeing overwrites the start of the page, so read the link first
+        let (next_page_id, _) = chunk_header(&buffer_pool.get_page(page_id)?.page().data);
+        buffer_pool.free_page(page_id)?;
+        page_id = next_page_id;
+    }
+    Ok(())
+}
+
+/// Reads the link to the next page and the chunk length from one page of a
+/// chain, for integrity checks.
+pub fn read_link(page_id: u32, buffer_pool: &BufferPool) -> Result<(u32, usize)> {
+    let (next_page_id, chunk_len) = chunk_header(&buffer_pool.get_page(page_id)?.page().data);
+    if chunk_len > CHUNK_CAPACITY {
+        return Err(DatabaseError::InvalidData(format!(
+            "Overflow page {} has a chunk of {} bytes",
+            page_id, chunk_len
+        )));
+    }
+    Ok((next_page_id, chunk_len))
+}
+
+fn chunk_header(data: &[u8]) -> (u32, usize) {
+    let next_page_id = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let chunk_len = u16::from_le_bytes(data[4..6].try_into().unwrap()) as usize;
+    (next_page_id, chunk_len)
+}
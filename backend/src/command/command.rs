@@ -1,35 +1,59 @@
-use crate::storage::value::Value;
+use crate::storage::{key::Key, transaction::IsolationLevel, value::Value};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Command {
     Get {
-        key: i32,
+        key: Key,
     },
     Set {
-        key: i32,
+        key: Key,
         value: Value,
     },
     Delete {
-        key: i32,
+        key: Key,
     },
     Update {
-        key: i32,
+        key: Key,
         value: Value,
     },
     All,
+    Range {
+        start: Key,
+        end: Key,
+        limit: Option<usize>,
+    },
     Strlen {
-        key: i32,
+        key: Key,
     },
     Strcat {
-        key: i32,
+        key: Key,
         value: Value,
     },
     Substr {
-        key: i32,
+        key: Key,
         start: usize,
         length: usize,
     },
+    Import {
+        path: String,
+    },
+    Checkpoint,
+    Check,
+    Begin {
+        isolation: IsolationLevel,
+    },
+    Commit,
+    Rollback,
+    Savepoint {
+        name: String,
+    },
+    RollbackToSavepoint {
+        name: String,
+    },
+    ReleaseSavepoint {
+        name: String,
+    },
     Ping,
     Exit,
     Expression(String),
@@ -1,150 +1,619 @@
 use std::{
+    ops::RangeBounds,
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 
 use crate::{
-    btree::BTree,
-    storage::{buffer_pool::BufferPool, value::Value},
+    btree::{BTree, CheckReport},
     storage::{
+        buffer_pool::{BufferPool, BufferPoolStats},
+        key::Key,
+        value::Value,
+    },
+    storage::{
+        checkpoint,
         disk_manager::DiskManager,
-        error::DatabaseError,
-        operations,
-        transaction::{Transaction, TransactionManager},
-        wal::WriteAheadLog,
+        error::{self, DatabaseError},
+        lock_manager::{LockMode, DEFAULT_LOCK_TIMEOUT},
+        mvcc::{SnapshotCursor, VersionStore},
+        operations, recovery,
+        transaction::{IsolationLevel, Snapshot, Transaction, TransactionManager},
+        wal::{Durability, WalStats, WriteAheadLog},
     },
 };
 
+/// Settings used when opening a database.
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub durability: Durability,
+    /// How long a transaction waits for a lock another one holds.
+    pub lock_timeout: Duration,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            durability: Durability::default(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+}
+
+/// A database shared by every connection. Methods take `&self`: the B-tree
+/// latches its own pages, so readers and writers run side by side. Reads are
+/// served from a snapshot, so they never see a transaction half done.
 pub struct Database {
     buffer_pool: BufferPool,
     transaction_manager: TransactionManager,
-    index: Arc<Mutex<BTree>>,
+    index: BTree,
+    versions: VersionStore,
     wal: Arc<Mutex<WriteAheadLog>>,
+    // Transactions hold this shared; a checkpoint or check holds it exclusively,
+    // so it flushes or walks a tree no transaction is halfway through changing
+    write_gate: RwLock<()>,
 }
 
 impl Database {
     pub fn new(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open(path, DatabaseConfig::default())
+    }
+
+    pub fn open(
+        path: impl AsRef<Path>,
+        config: DatabaseConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let path = path.as_ref();
         let file_exists = path.exists();
         let disk_manager = DiskManager::new(path.to_str().unwrap())
             .map_err(|_| DatabaseError::IoError(std::io::Error::last_os_error()))?;
         let mut buffer_pool = BufferPool::new(1000, disk_manager);
-        let wal = WriteAheadLog::new(path.with_extension("wal"))
+        let wal = WriteAheadLog::with_durability(path.with_extension("wal"), config.durability)
             .map_err(|_| DatabaseError::IoError(std::io::Error::last_os_error()))?;
+        // Recovery logs the changes it undoes through the pool like any writer
+        let wal = Arc::new(Mutex::new(wal));
+        buffer_pool.set_wal(Arc::clone(&wal));
 
-        let root_page_id = if !file_exists {
-            // Create and initialize root page for index if this is a new database
-            let page_id = buffer_pool.new_page()?.header.page_id;
-            let btree = BTree::new(page_id);
-            // Initialize the B-tree with an empty root nodes
-            btree.init(&mut buffer_pool)?;
-            page_id
+        let next_txn_id = if !file_exists {
+            // A new database starts with a fresh log, whatever was left next to it
+            wal.lock().unwrap().reset()?;
+            1
         } else {
-            // For existing database, read the first page which contains the root
-            let page = buffer_pool.get_page(0)?;
-            page.header.page_id
+            // Replay the log before anything reads the tree, then trim it
+            let report = recovery::recover(&wal, &buffer_pool)?;
+            checkpoint::checkpoint(&mut wal.lock().unwrap(), &buffer_pool, Vec::new())?;
+            (report.max_txn_id + 1).max(buffer_pool.header().next_txn_id)
+        };
+
+        let root_page_id = match buffer_pool.root_page_id() {
+            Some(page_id) => page_id,
+            None => {
+                // Create and initialize root page for index if this is a new database
+                let page_id = buffer_pool.new_page()?.page_id();
+                let btree = BTree::new(page_id);
+                // Initialize the B-tree with an empty root nodes
+                btree.init(&buffer_pool)?;
+                buffer_pool.set_root_page_id(page_id)?;
+                buffer_pool.flush()?;
+                page_id
+            }
         };
 
         let btree = BTree::new(root_page_id);
 
         Ok(Self {
             buffer_pool,
-            transaction_manager: TransactionManager::new(),
-            index: Arc::new(Mutex::new(btree)),
-            wal: Arc::new(Mutex::new(wal)),
+            transaction_manager: TransactionManager::with_next_txn_id(next_txn_id)
+                .with_lock_timeout(config.lock_timeout),
+            index: btree,
+            versions: VersionStore::new(),
+            wal,
+            write_gate: RwLock::new(()),
         })
     }
 
-    pub fn begin_transaction(&mut self) -> Result<Transaction, Box<dyn std::error::Error>> {
+    /// Opens a session, which runs each statement in a transaction of its own
+    /// until `Session::begin` is called.
+    pub fn session(&self) -> Session<'_> {
+        Session {
+            db: self,
+            txn: None,
+        }
+    }
+
+    pub fn begin_transaction(&self) -> Result<Transaction, Box<dyn std::error::Error>> {
+        self.begin_transaction_with_isolation(IsolationLevel::default())
+    }
+
+    pub fn begin_transaction_with_isolation(
+        &self,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction, Box<dyn std::error::Error>> {
         Ok(self
             .transaction_manager
-            .begin_transaction(Arc::clone(&self.wal))
+            .begin_transaction_with_isolation(Arc::clone(&self.wal), isolation)
             .map_err(|_| DatabaseError::IoError(std::io::Error::last_os_error()))?)
     }
 
-    pub fn insert(&mut self, key: i32, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    /// Runs `op` inside a new transaction, committing on success and rolling back
+    /// on error.
+    fn run_in_transaction<F>(&self, op: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnOnce(
+            &mut Transaction,
+            &BTree,
+            &VersionStore,
+            &BufferPool,
+        ) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        let _gate = self.write_gate.read().unwrap();
         let mut txn = self.begin_transaction()?;
-        let result = operations::insert(&mut txn, &self.index, &mut self.buffer_pool, key, value);
+        let result = op(&mut txn, &self.index, &self.versions, &self.buffer_pool);
         if result.is_ok() {
-            txn.commit()?;
+            if let Err(e) = txn.commit() {
+                self.rollback(&mut txn)?;
+                return Err(e.into());
+            }
         } else {
-            txn.rollback()?;
+            self.rollback(&mut txn)?;
         }
         result
     }
 
-    pub fn delete(&mut self, key: i32) -> Result<(), Box<dyn std::error::Error>> {
-        let mut txn = self.begin_transaction()?;
-        let result = operations::delete(&mut txn, &self.index, &mut self.buffer_pool, key);
-        if result.is_ok() {
-            txn.commit()?;
-        } else {
-            txn.rollback()?;
-        }
-        result
+    /// Undoes the writes of `txn` and ends it. The caller holds the write gate.
+    fn rollback(&self, txn: &mut Transaction) -> Result<(), Box<dyn std::error::Error>> {
+        operations::rollback(txn, &self.index, &self.versions, &self.buffer_pool)
     }
 
-    pub fn update(&mut self, key: i32, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
-        let mut txn = self.begin_transaction()?;
-        let result = operations::update(&mut txn, &self.index, &mut self.buffer_pool, key, value);
-        if result.is_ok() {
-            txn.commit()?;
-        } else {
-            txn.rollback()?;
-        }
-        result
+    pub fn insert(
+        &self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.session().insert(key, value)
     }
 
-    pub fn get(&mut self, key: i32) -> Result<Option<Value>, Box<dyn std::error::Error>> {
-        operations::get(&self.index, &mut self.buffer_pool, key)
+    pub fn delete(&self, key: impl Into<Key>) -> Result<(), Box<dyn std::error::Error>> {
+        self.session().delete(key)
     }
 
-    pub fn all(&mut self) -> Result<Vec<(i32, Value)>, Box<dyn std::error::Error>> {
-        operations::all(&self.index, &mut self.buffer_pool)
+    pub fn update(
+        &self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.session().update(key, value)
     }
 
-    pub fn strlen(&mut self, key: i32) -> Result<Option<usize>, Box<dyn std::error::Error>> {
-        operations::strlen(&self.index, &mut self.buffer_pool, key)
+    /// Loads `entries`, which must be in strictly ascending key order, into an
+    /// empty database by building the B-tree bottom-up. Returns the number of
+    /// pairs loaded.
+    pub fn bulk_load(
+        &self,
+        entries: impl IntoIterator<Item = (Key, Value)>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut count = 0;
+        self.run_in_transaction(|txn, index, versions, buffer_pool| {
+            count = operations::bulk_load(txn, index, versions, buffer_pool, entries)?;
+            Ok(())
+        })?;
+        Ok(count)
     }
 
-    pub fn strcat(&mut self, key: i32, value: &Value) -> Result<(), Box<dyn std::error::Error>> {
-        let mut txn = self.begin_transaction()?;
-        let result = operations::strcat(&mut txn, &self.index, &mut self.buffer_pool, key, value);
-        if result.is_ok() {
-            txn.commit()?;
-        } else {
-            txn.rollback()?;
-        }
-        result
+    pub fn get(&self, key: impl Into<Key>) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+        self.session().get(key)
+    }
+
+    pub fn all(&self) -> Result<Vec<(Key, Value)>, Box<dyn std::error::Error>> {
+        self.session().all()
+    }
+
+    /// Returns a cursor over every key-value pair, in key order. Pairs are read
+    /// one leaf at a time, so large tables can be streamed.
+    pub fn iter(&self) -> SnapshotCursor<'_> {
+        self.range(..)
+    }
+
+    /// Returns a cursor over the key-value pairs with keys in `range`, in key
+    /// order, as they were when the cursor was opened.
+    pub fn range<R: RangeBounds<Key>>(&self, range: R) -> SnapshotCursor<'_> {
+        self.session().cursor(range)
+    }
+
+    /// Returns the key-value pairs with keys in `range` in descending key order.
+    pub fn rev_range<R: RangeBounds<Key>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = error::Result<(Key, Value)>> + '_ {
+        let mut cursor = self.range(range);
+        std::iter::from_fn(move || cursor.prev())
+    }
+
+    pub fn strlen(&self, key: impl Into<Key>) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        self.session().strlen(key)
+    }
+
+    pub fn strcat(
+        &self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.session().strcat(key, value)
     }
 
     pub fn substr(
-        &mut self,
-        key: i32,
+        &self,
+        key: impl Into<Key>,
         start: usize,
         length: usize,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut txn = self.begin_transaction()?;
-        let result = operations::substr(
-            &mut txn,
-            &self.index,
-            &mut self.buffer_pool,
-            key,
-            start,
-            length,
-        );
-        if result.is_ok() {
-            txn.commit()?;
-        } else {
-            txn.rollback()?;
-        }
-        result
+        self.session().substr(key, start, length)
+    }
+
+    /// Removes the row versions no open transaction or cursor can see any more:
+    /// versions replaced or deleted by a committed transaction, and those written
+    /// by a rolled-back one. Returns the number of versions removed.
+    pub fn vacuum(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let horizon = self.transaction_manager.horizon();
+        let tm = &self.transaction_manager;
+        let mut removed = 0;
+        self.run_in_transaction(|txn, index, versions, buffer_pool| {
+            removed = operations::vacuum(txn, index, versions, buffer_pool, horizon, |txn_id| {
+                tm.is_aborted(txn_id)
+            })?;
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Walks the whole B-tree and reports broken invariants and unreachable pages.
+    pub fn check(&self) -> Result<CheckReport, Box<dyn std::error::Error>> {
+        let _gate = self.write_gate.write().unwrap();
+        operations::check(&self.index, &self.buffer_pool)
+    }
+
+    /// Flushes dirty pages and truncates the write-ahead log up to a new checkpoint.
+    /// Returns the sequence number of the checkpoint record.
+    pub fn checkpoint(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let _gate = self.write_gate.write().unwrap();
+        let active_txns = self
+            .transaction_manager
+            .active_transactions()
+            .iter()
+            .map(|txn_id| txn_id.0)
+            .collect();
+        let mut wal = self.wal.lock().unwrap();
+        Ok(checkpoint::checkpoint(
+            &mut wal,
+            &self.buffer_pool,
+            active_txns,
+        )?)
+    }
+
+    /// Commit count, fsync count and commit latency since the database was opened.
+    /// Makes the next `count` writes to the log fail, to test how failures are
+    /// handled.
+    #[cfg(test)]
+    pub(crate) fn fail_log_appends(&self, count: usize) {
+        self.wal.lock().unwrap().fail_appends(count);
     }
 
-    pub fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn wal_stats(&self) -> WalStats {
+        self.wal.lock().unwrap().flusher().stats()
+    }
+
+    /// Page cache hits, misses and evictions since the database was opened.
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.stats()
+    }
+
+    pub fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.buffer_pool
             .flush()
             .map_err(|_| DatabaseError::IoError(std::io::Error::last_os_error()))?;
         Ok(())
     }
 }
+
+/// A client's connection to the database. Outside a transaction each statement
+/// commits on its own. Between `begin` and `commit` or `rollback` every statement
+/// runs in one transaction: its writes are seen by the session's reads but by no
+/// one else until it commits. A session dropped with a transaction open, as when
+/// its client disconnects, rolls it back. A serializable transaction chosen as a
+/// deadlock victim is rolled back as soon as the statement that found the
+/// deadlock fails.
+pub struct Session<'a> {
+    db: &'a Database,
+    txn: Option<Transaction>,
+}
+
+impl<'a> Session<'a> {
+    pub fn in_transaction(&self) -> bool {
+        self.txn.is_some()
+    }
+
+    pub fn begin(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.begin_with_isolation(IsolationLevel::default())
+    }
+
+    pub fn begin_with_isolation(
+        &mut self,
+        isolation: IsolationLevel,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.txn.is_some() {
+            return Err("A transaction is already open".into());
+        }
+        self.txn = Some(self.db.begin_transaction_with_isolation(isolation)?);
+        Ok(())
+    }
+
+    /// Commits the open transaction. If the commit cannot be logged, the
+    /// transaction is rolled back instead; should that fail too, dropping it
+    /// still ends it as aborted.
+    pub fn commit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut txn = self.txn.take().ok_or("No transaction is open")?;
+        if let Err(e) = txn.commit() {
+            let _gate = self.db.write_gate.read().unwrap();
+            if let Err(rollback_error) = self.db.rollback(&mut txn) {
+                eprintln!("Error rolling back transaction: {}", rollback_error);
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Undoes the open transaction's writes and ends it.
+    pub fn rollback(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut txn = self.txn.take().ok_or("No transaction is open")?;
+        let _gate = self.db.write_gate.read().unwrap();
+        self.db.rollback(&mut txn)
+    }
+
+    pub fn savepoint(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.open_transaction()?.savepoint(name);
+        Ok(())
+    }
+
+    /// Undoes the writes made since savepoint `name`; the transaction stays open.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let db = self.db;
+        let txn = self.open_transaction()?;
+        let _gate = db.write_gate.read().unwrap();
+        operations::rollback_to_savepoint(txn, name, &db.index, &db.versions, &db.buffer_pool)
+    }
+
+    /// Forgets savepoint `name` and those set after it, keeping their writes.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.open_transaction()?.release_savepoint(name)?)
+    }
+
+    fn open_transaction(&mut self) -> Result<&mut Transaction, Box<dyn std::error::Error>> {
+        Ok(self.txn.as_mut().ok_or("No transaction is open")?)
+    }
+
+    /// Runs a writing statement in the open transaction, or in one of its own.
+    /// Only the statement holds the write gate, so checkpoints can run between
+    /// the statements of a transaction.
+    fn write<F>(&mut self, key: &Key, op: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnOnce(
+            &mut Transaction,
+            &BTree,
+            &VersionStore,
+            &BufferPool,
+        ) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        self.lock(key, LockMode::Exclusive)?;
+        let db = self.db;
+        match &mut self.txn {
+            Some(txn) => {
+                let _gate = db.write_gate.read().unwrap();
+                db.transaction_manager.refresh_snapshot(txn, key);
+                op(txn, &db.index, &db.versions, &db.buffer_pool)
+            }
+            None => db.run_in_transaction(op),
+        }
+    }
+
+    /// The snapshot statements read from: the open transaction's, or a new one.
+    fn snapshot(&self) -> Snapshot {
+        let transaction_manager = &self.db.transaction_manager;
+        match &self.txn {
+            Some(txn) => transaction_manager.statement_snapshot(txn),
+            None => transaction_manager.snapshot(),
+        }
+    }
+
+    /// Locks `key` for reading and takes the snapshot to read it through. A
+    /// serializable transaction notes the snapshot, so a later write to the key
+    /// is checked against what it read.
+    fn read_snapshot(&mut self, key: &Key) -> Result<Arc<Snapshot>, Box<dyn std::error::Error>> {
+        self.lock(key, LockMode::Shared)?;
+        let snapshot = Arc::new(self.snapshot());
+        if self.is_serializable() {
+            self.open_transaction()?.note_read(key, &snapshot);
+        }
+        Ok(snapshot)
+    }
+
+    fn is_serializable(&self) -> bool {
+        self.txn
+            .as_ref()
+            .is_some_and(|txn| txn.isolation == IsolationLevel::Serializable)
+    }
+
+    /// Locks `key` for the open transaction if it is serializable. A deadlock
+    /// victim is rolled back here, releasing its locks for the others.
+    fn lock(&mut self, key: &Key, mode: LockMode) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(txn) = self.txn.as_ref().filter(|_| self.is_serializable()) else {
+            return Ok(());
+        };
+        let result = txn.lock(key, mode);
+        self.locked(result)
+    }
+
+    /// Locks every key in `range` for reading in a serializable transaction,
+    /// so no other serializable transaction can change or insert keys in it
+    /// until it ends.
+    fn lock_range<R: RangeBounds<Key>>(
+        &mut self,
+        range: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(txn) = self.txn.as_ref().filter(|_| self.is_serializable()) else {
+            return Ok(());
+        };
+        let result = txn.lock_range(range);
+        self.locked(result)
+    }
+
+    /// Rolls back the transaction if it was chosen as a deadlock victim while
+    /// taking a lock.
+    fn locked(&mut self, result: error::Result<()>) -> Result<(), Box<dyn std::error::Error>> {
+        match result {
+            Err(DatabaseError::Deadlock(txn_id)) => {
+                self.rollback()?;
+                Err(DatabaseError::Deadlock(txn_id).into())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key = key.into();
+        self.write(&key, |txn, index, versions, buffer_pool| {
+            operations::insert(txn, index, versions, buffer_pool, &key, value)
+        })
+    }
+
+    pub fn delete(&mut self, key: impl Into<Key>) -> Result<(), Box<dyn std::error::Error>> {
+        let key = key.into();
+        self.write(&key, |txn, index, versions, buffer_pool| {
+            operations::delete(txn, index, versions, buffer_pool, &key)
+        })
+    }
+
+    pub fn update(
+        &mut self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key = key.into();
+        self.write(&key, |txn, index, versions, buffer_pool| {
+            operations::update(txn, index, versions, buffer_pool, &key, value)
+        })
+    }
+
+    pub fn get(
+        &mut self,
+        key: impl Into<Key>,
+    ) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+        let key = key.into();
+        let snapshot = self.read_snapshot(&key)?;
+        let db = self.db;
+        operations::get(&db.index, &db.versions, &db.buffer_pool, &snapshot, &key)
+    }
+
+    /// Every key-value pair, in key order. A serializable transaction locks the
+    /// whole key space, so no other serializable transaction can change or
+    /// insert keys until it ends.
+    pub fn all(&mut self) -> Result<Vec<(Key, Value)>, Box<dyn std::error::Error>> {
+        let db = self.db;
+        let snapshot = self.scan_snapshot(..)?;
+        operations::all(&db.index, &db.versions, &db.buffer_pool, snapshot)
+    }
+
+    /// Returns a cursor over the key-value pairs with keys in `range`, in key
+    /// order, as the session saw them when the cursor was opened. A
+    /// serializable transaction locks the whole range first, so no other
+    /// serializable transaction can change or insert keys in it until this
+    /// one ends.
+    pub fn range<R: RangeBounds<Key> + Clone>(
+        &mut self,
+        range: R,
+    ) -> Result<SnapshotCursor<'a>, Box<dyn std::error::Error>> {
+        let db = self.db;
+        let snapshot = self.scan_snapshot(range.clone())?;
+        Ok(operations::cursor(
+            &db.index,
+            &db.versions,
+            &db.buffer_pool,
+            snapshot,
+            range,
+        ))
+    }
+
+    /// Locks `range` for reading and takes the snapshot to scan it through,
+    /// noted by a serializable transaction like `read_snapshot` does for a key.
+    fn scan_snapshot<R: RangeBounds<Key> + Clone>(
+        &mut self,
+        range: R,
+    ) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        self.lock_range(range.clone())?;
+        let snapshot = Arc::new(self.snapshot());
+        if self.is_serializable() {
+            self.open_transaction()?.note_range_read(range, &snapshot);
+        }
+        Ok(Snapshot::clone(&snapshot))
+    }
+
+    /// A cursor like `range`'s that takes no locks, for sessions outside a
+    /// transaction.
+    fn cursor<R: RangeBounds<Key>>(&self, range: R) -> SnapshotCursor<'a> {
+        let db = self.db;
+        operations::cursor(
+            &db.index,
+            &db.versions,
+            &db.buffer_pool,
+            self.snapshot(),
+            range,
+        )
+    }
+
+    pub fn strlen(
+        &mut self,
+        key: impl Into<Key>,
+    ) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        let key = key.into();
+        let snapshot = self.read_snapshot(&key)?;
+        let db = self.db;
+        operations::strlen(&db.index, &db.versions, &db.buffer_pool, &snapshot, &key)
+    }
+
+    pub fn strcat(
+        &mut self,
+        key: impl Into<Key>,
+        value: &Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key = key.into();
+        self.write(&key, |txn, index, versions, buffer_pool| {
+            operations::strcat(txn, index, versions, buffer_pool, &key, value)
+        })
+    }
+
+    pub fn substr(
+        &mut self,
+        key: impl Into<Key>,
+        start: usize,
+        length: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key = key.into();
+        self.write(&key, |txn, index, versions, buffer_pool| {
+            operations::substr(txn, index, versions, buffer_pool, &key, start, length)
+        })
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.txn.is_some() {
+            if let Err(e) = self.rollback() {
+                eprintln!("Error rolling back transaction: {}", e);
+            }
+        }
+    }
+}
@@ -8,7 +8,7 @@ pub mod storage;
 #[cfg(test)]
 pub mod tests;
 
-use database_handler::database_handler::Database;
+use database_handler::database_handler::{Database, DatabaseConfig};
 use server::Server;
 use std::error::Error;
 
@@ -32,8 +32,19 @@ pub fn run() -> Result<(), Box<dyn Error>> {
     env_logger::init();
     info!("Starting database server");
 
+    // Durability of commits: always (default), group, group:<delay ms> or os
+    let mut config = DatabaseConfig::default();
+    if let Ok(durability) = std::env::var("DB_DURABILITY") {
+        config.durability = durability.parse()?;
+    }
+    info!("Using {:?} commit durability", config.durability);
+    // Lock wait timeout in milliseconds
+    if let Ok(ms) = std::env::var("DB_LOCK_TIMEOUT") {
+        config.lock_timeout = std::time::Duration::from_millis(ms.parse()?);
+    }
+
     // Create or open database
-    let db = match Database::new("test.db") {
+    let db = match Database::open("test.db", config) {
         Ok(db) => {
             info!("Successfully opened database");
             db
@@ -45,7 +56,12 @@ pub fn run() -> Result<(), Box<dyn Error>> {
     };
 
     // Create and run server
-    let server = Server::new(db, 5432);
+    let mut server = Server::new(db, 5432);
+    // Directory IMPORT reads files from; IMPORT is refused without one
+    if let Ok(dir) = std::env::var("DB_IMPORT_DIR") {
+        info!("Importing files from {}", dir);
+        server = server.with_import_dir(dir);
+    }
     info!("Starting server on port 5432");
     match server.run() {
         Ok(_) => info!("Server stopped"),
@@ -1,12 +1,14 @@
-use crate::storage::value::Value;
+use crate::btree::CheckReport;
+use crate::storage::{key::Key, value::Value};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Ok,
     Value(Option<Value>),
-    Range(Vec<(i32, Value)>),
+    Range(Vec<(Key, Value)>),
     Error(String),
     Pong,
     Size(usize),
+    Check(CheckReport),
 }
@@ -1,38 +1,67 @@
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use threadpool::ThreadPool;
 
 use crate::command::Command;
 use crate::{
-    database_handler::database_handler::Database, protocol::connection::Connection,
-    protocol::error::ProtocolError, protocol::response::Response,
+    database_handler::database_handler::{Database, Session},
+    protocol::connection::Connection,
+    protocol::error::ProtocolError,
+    protocol::response::Response,
 };
 
-mod parser;
+pub(crate) mod parser;
+
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Server {
-    db: Arc<Mutex<Database>>,
+    db: Arc<Database>,
     port: u16,
+    checkpoint_interval: Duration,
+    import_dir: Option<PathBuf>,
 }
 
 impl Server {
     pub fn new(db: Database, port: u16) -> Self {
         Self {
-            db: Arc::new(Mutex::new(db)),
+            db: Arc::new(db),
             port,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            import_dir: None,
         }
     }
 
+    /// Lets IMPORT load files from `dir`. Clients name files relative to it and
+    /// cannot reach anything outside; without an import directory IMPORT is
+    /// refused.
+    pub fn with_import_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.import_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets how often the background checkpointer drops row versions no one can
+    /// see any more, flushes pages and trims the log.
+    pub fn with_checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+
     pub fn run(&self) -> std::io::Result<()> {
+        spawn_checkpointer(Arc::clone(&self.db), self.checkpoint_interval);
+
         let pool = ThreadPool::new(4);
         let listener = TcpListener::bind(format!("127.0.0.1:{}", self.port))?;
         println!("Server listening on port {}", self.port);
 
         for stream in listener.incoming() {
             let db = Arc::clone(&self.db);
+            let import_dir = self.import_dir.clone();
             if let Ok(stream) = stream {
                 pool.execute(move || {
-                    if let Err(e) = handle_client(stream, db) {
+                    if let Err(e) = handle_client(stream, db, import_dir.as_deref()) {
                         eprintln!("Error handling client: {}", e);
                     }
                 });
@@ -42,15 +71,35 @@ impl Server {
     }
 }
 
+fn spawn_checkpointer(db: Arc<Database>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = db.vacuum() {
+            eprintln!("Background vacuum failed: {}", e);
+        }
+        if let Err(e) = db.checkpoint() {
+            eprintln!("Background checkpoint failed: {}", e);
+        }
+    });
+}
+
+/// Serves one client. Its session, and any transaction it left open, ends when
+/// this returns.
 fn handle_client(
     stream: TcpStream,
-    mut db: Arc<Mutex<Database>>,
+    db: Arc<Database>,
+    import_dir: Option<&Path>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut conn = Connection::new(stream);
+    let mut session = db.session();
     loop {
         let raw_command = match conn.receive_raw_command() {
             Ok(cmd) => cmd.to_string(),
             Err(ProtocolError::ConnectionClosed) => {
+                if session.in_transaction() {
+                    println!("Client disconnected; rolling back its transaction");
+                    return session.rollback();
+                }
                 println!("Client disconnected");
                 return Ok(());
             }
@@ -64,7 +113,7 @@ fn handle_client(
             }
         };
 
-        let command = match parser::parse_raw_command(&raw_command, &mut db) {
+        let command = match parser::parse_raw_command(&raw_command, &mut session) {
             Ok(cmd) => cmd,
             Err(e) => {
                 let error_msg = e.to_string();
@@ -75,7 +124,7 @@ fn handle_client(
             }
         };
 
-        let response = match handle_command(command, &db) {
+        let response = match handle_command(command, &db, &mut session, import_dir) {
             Ok(resp) => resp,
             Err(e) => Response::Error(e.to_string()),
         };
@@ -89,45 +138,84 @@ fn handle_client(
 
 fn handle_command(
     command: Command,
-    db: &Arc<Mutex<Database>>,
+    db: &Arc<Database>,
+    session: &mut Session,
+    import_dir: Option<&Path>,
 ) -> Result<Response, Box<dyn std::error::Error>> {
     match command {
         Command::Get { key } => {
-            let mut db = lock_db(&db)?;
-            let value = db.get(key)?;
+            let value = session.get(key)?;
             Ok(Response::Value(value))
         }
         Command::Set { key, value } => {
-            let mut db = lock_db(&db)?;
-            db.insert(key, &value)?;
+            session.insert(key, &value)?;
             Ok(Response::Ok)
         }
         Command::Delete { key } => {
-            let mut db = lock_db(&db)?;
-            db.delete(key)?;
+            session.delete(key)?;
             Ok(Response::Ok)
         }
         Command::Update { key, value } => {
-            let mut db = lock_db(&db)?;
-            db.update(key, &value)?;
+            session.update(key, &value)?;
             Ok(Response::Ok)
         }
         Command::All => {
-            let mut db = lock_db(&db)?;
-            let results = db.all()?;
+            let results = session.all()?;
+            Ok(Response::Range(results))
+        }
+        Command::Range { start, end, limit } => {
+            let results = session
+                .range(start..end)?
+                .take(limit.unwrap_or(usize::MAX))
+                .collect::<Result<Vec<_>, _>>()?;
             Ok(Response::Range(results))
         }
         Command::Strlen { key } => {
-            let mut db = lock_db(&db)?;
-            let size = db.strlen(key)?.unwrap_or(0);
+            let size = session.strlen(key)?.unwrap_or(0);
             Ok(Response::Size(size))
         }
-        Command::Expression(expr) => {
-            let mut db = db.clone();
-            match parser::evaluate_expression(&expr, &mut db) {
-                Ok(value) => Ok(Response::Value(Some(value))),
-                Err(e) => Ok(Response::Error(e.to_string())),
-            }
+        Command::Expression(expr) => match parser::evaluate_expression(&expr, session) {
+            Ok(value) => Ok(Response::Value(Some(value))),
+            Err(e) => Ok(Response::Error(e.to_string())),
+        },
+        Command::Import { .. } if session.in_transaction() => {
+            Err("IMPORT cannot run inside a transaction".into())
+        }
+        Command::Import { path } => {
+            let file = import_file(import_dir, &path)?;
+            let contents = std::fs::read_to_string(file)
+                .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+            let pairs = parser::parse_import(&contents)?;
+            Ok(Response::Size(db.bulk_load(pairs)?))
+        }
+        Command::Checkpoint => {
+            db.checkpoint()?;
+            Ok(Response::Ok)
+        }
+        Command::Check => Ok(Response::Check(db.check()?)),
+        Command::Begin { isolation } => {
+            session.begin_with_isolation(isolation)?;
+            Ok(Response::Ok)
+        }
+        Command::Commit => {
+            session.commit()?;
+            Ok(Response::Ok)
+        }
+        Command::Rollback => {
+            session.rollback()?;
+            Ok(Response::Ok)
+        }
+        Command::Savepoint { name } => {
+            session.savepoint(&name)?;
+            Ok(Response::Ok)
+        }
+        Command::RollbackToSavepoint { name } => {
+            session.rollback_to_savepoint(&name)?;
+            Ok(Response::Ok)
+        }
+        Command::ReleaseSavepoint { name } => {
+            session.release_savepoint(&name)?;
+            Ok(Response::Ok)
         }
         Command::Ping => Ok(Response::Pong),
         Command::Exit => std::process::exit(0),
@@ -135,9 +223,33 @@ fn handle_command(
     }
 }
 
-fn lock_db(db: &Arc<Mutex<Database>>) -> Result<MutexGuard<Database>, Box<dyn std::error::Error>> {
-    db.lock().map_err(|e| Box::new(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        format!("Failed to acquire database lock: {}", e)
-    )) as Box<dyn std::error::Error>)
+/// Resolves the file an IMPORT names inside the import directory. Only plain
+/// relative paths are taken, and the file must still be inside the directory
+/// once symbolic links are followed.
+fn import_file(
+    import_dir: Option<&Path>,
+    path: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = import_dir.ok_or("IMPORT is disabled: the server has no import directory")?;
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(format!(
+            "Cannot import {}: name a file in the import directory",
+            path
+        )
+        .into());
+    }
+
+    let dir = dir.canonicalize()?;
+    let file = dir
+        .join(relative)
+        .canonicalize()
+        .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    if !file.starts_with(&dir) {
+        return Err(format!("Cannot import {}: it is outside the import directory", path).into());
+    }
+    Ok(file)
 }
@@ -1,17 +1,19 @@
 use crate::{
-    command::Command, database_handler::database_handler::Database, storage::value::Value,
+    btree::MAX_KEY_SIZE,
+    command::Command,
+    database_handler::database_handler::Session,
+    storage::{key::Key, transaction::IsolationLevel, value::Value},
 };
-use std::sync::{Arc, Mutex};
 
 const VALID_EXPRESSION_METHODS: &str = "-+*/%";
 
 pub fn evaluate_expression(
     expr: &str,
-    db: &mut Arc<Mutex<Database>>,
+    db: &mut Session,
 ) -> Result<Value, Box<dyn std::error::Error>> {
     for method in VALID_EXPRESSION_METHODS.chars() {
-        if expr.contains(method) {
-            let parts: Vec<&str> = expr.split(method).collect();
+        let parts = split_unquoted(expr, |c| c == method);
+        if parts.len() > 1 {
             return if parts.len() == 2 {
                 let left = evaluate_expression(parts[0].trim(), db)
                     .map_err(|e| format!("Error evaluating left operand: {}", e))?;
@@ -24,24 +26,14 @@ pub fn evaluate_expression(
         }
     }
 
-    if expr.starts_with("GET") {
-        let mut db = db
-            .lock()
-            .map_err(|e| format!("Database lock error: {}", e))?;
-        let key = expr[4..].trim().parse::<i32>()?;
-        return Ok(db
-            .get(key)
-            .unwrap_or(Some(Value::Integer(0)))
-            .expect("Error getting value"));
-    } else if expr.starts_with("STRLEN") {
-        let mut db = db
-            .lock()
-            .map_err(|e| format!("Database lock error: {}", e))?;
-        let key = expr[6..].trim().parse::<i32>()?;
+    if let Some(rest) = expr.strip_prefix("GET") {
+        let key = rest.trim().parse::<Key>()?;
+        return Ok(db.get(key)?.unwrap_or(Value::Integer(0)));
+    } else if let Some(rest) = expr.strip_prefix("STRLEN") {
+        let key = rest.trim().parse::<Key>()?;
         return Ok(Value::Integer(
-            db.get(key)
-                .unwrap_or(Some(Value::String("".to_string())))
-                .expect("Error getting value")
+            db.get(key)?
+                .unwrap_or(Value::String("".to_string()))
                 .to_string()
                 .len() as i64,
         ));
@@ -68,9 +60,10 @@ pub fn parse_value(s: &str) -> Result<Value, Box<dyn std::error::Error>> {
 
 pub fn parse_raw_command(
     raw_command: &str,
-    db: &mut Arc<Mutex<Database>>,
+    db: &mut Session,
 ) -> Result<Command, Box<dyn std::error::Error>> {
-    let parts: Vec<&str> = raw_command.trim().split_whitespace().collect();
+    let mut parts = split_unquoted(raw_command, char::is_whitespace);
+    parts.retain(|part| !part.is_empty());
     if parts.is_empty() {
         return Err("Empty command".into());
     }
@@ -139,6 +132,18 @@ pub fn parse_raw_command(
             })
         }
         "ALL" => Ok(Command::All),
+        "RANGE" => {
+            let limit = match parts.len() {
+                3 => None,
+                5 if parts[3].eq_ignore_ascii_case("LIMIT") => Some(parts[4].parse()?),
+                _ => return Err("Usage: RANGE <start> <end> [LIMIT <n>]".into()),
+            };
+            Ok(Command::Range {
+                start: parts[1].parse()?,
+                end: parts[2].parse()?,
+                limit,
+            })
+        }
         "STRLEN" => {
             if parts.len() != 2 {
                 return Err("Usage: STRLEN <key>".into());
@@ -166,10 +171,142 @@ pub fn parse_raw_command(
                 length: parts[3].parse()?,
             })
         }
+        "IMPORT" => {
+            if parts.len() != 2 {
+                return Err("Usage: IMPORT <file>".into());
+            }
+            Ok(Command::Import {
+                path: parts[1].trim_matches('"').to_string(),
+            })
+        }
+        "CHECKPOINT" => Ok(Command::Checkpoint),
+        "CHECK" => Ok(Command::Check),
+        "BEGIN" => {
+            let isolation = match &parts[1..] {
+                [] => IsolationLevel::default(),
+                [isolation, level, name @ ..]
+                    if isolation.eq_ignore_ascii_case("ISOLATION")
+                        && level.eq_ignore_ascii_case("LEVEL") =>
+                {
+                    name.join(" ").parse()?
+                }
+                _ => {
+                    return Err(
+                        "Usage: BEGIN [ISOLATION LEVEL {READ COMMITTED | REPEATABLE READ | SERIALIZABLE}]"
+                            .into(),
+                    )
+                }
+            };
+            Ok(Command::Begin { isolation })
+        }
+        "COMMIT" => Ok(Command::Commit),
+        "ROLLBACK" if parts.len() == 1 => Ok(Command::Rollback),
+        "ROLLBACK" => Ok(Command::RollbackToSavepoint {
+            name: savepoint_name(&parts, "ROLLBACK TO [SAVEPOINT] <name>", true)?,
+        }),
+        "SAVEPOINT" => {
+            if parts.len() != 2 {
+                return Err("Usage: SAVEPOINT <name>".into());
+            }
+            Ok(Command::Savepoint {
+                name: parts[1].to_string(),
+            })
+        }
+        "RELEASE" => Ok(Command::ReleaseSavepoint {
+            name: savepoint_name(&parts, "RELEASE [SAVEPOINT] <name>", false)?,
+        }),
         _ => Err("Unknown command".into()),
     }
 }
 
+/// Takes the savepoint name from `ROLLBACK TO [SAVEPOINT] <name>` (`after_to`)
+/// or `RELEASE [SAVEPOINT] <name>`.
+fn savepoint_name(
+    parts: &[&str],
+    usage: &str,
+    after_to: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut rest = &parts[1..];
+    if after_to {
+        match rest.split_first() {
+            Some((to, tail)) if to.eq_ignore_ascii_case("TO") => rest = tail,
+            _ => return Err(format!("Usage: {}", usage).into()),
+        }
+    }
+    if rest.len() == 2 && rest[0].eq_ignore_ascii_case("SAVEPOINT") {
+        rest = &rest[1..];
+    }
+    match rest {
+        [name] => Ok(name.to_string()),
+        _ => Err(format!("Usage: {}", usage).into()),
+    }
+}
+
+/// Parses the lines of an `IMPORT` file, each a key and a value as `SET` takes
+/// them, skipping blank lines. The pairs come back sorted by key, ready for bulk
+/// loading; of repeated keys, the last line wins. Keys too large to store are
+/// rejected here, before anything is loaded.
+pub fn parse_import(contents: &str) -> Result<Vec<(Key, Value)>, Box<dyn std::error::Error>> {
+    let mut pairs = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let mut parts = split_unquoted(line, char::is_whitespace);
+        parts.retain(|part| !part.is_empty());
+        if parts.is_empty() {
+            continue;
+        }
+        if parts.len() < 2 {
+            return Err(format!("Line {}: expected <key> <value>", idx + 1).into());
+        }
+        // Errors name the line only, so a file's contents are never sent back
+        let key = parts[0]
+            .parse::<Key>()
+            .map_err(|_| format!("Line {}: invalid key", idx + 1))?;
+        let size = key.encode().len();
+        if size > MAX_KEY_SIZE {
+            return Err(format!(
+                "Line {}: key of {} bytes is too large; keys are at most {} bytes once encoded",
+                idx + 1,
+                size,
+                MAX_KEY_SIZE
+            )
+            .into());
+        }
+        pairs.push((key, parse_value(&parts[1..].join(" "))?));
+    }
+
+    // Later lines go first among equal keys, so deduplicating keeps them
+    pairs.reverse();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    pairs.dedup_by(|a, b| a.0 == b.0);
+    Ok(pairs)
+}
+
+/// Splits `s` at characters matching `is_separator`, leaving separators inside
+/// double-quoted string keys alone.
+fn split_unquoted(s: &str, is_separator: impl Fn(char) -> bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (idx, c) in s.char_indices() {
+        if in_quotes {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if is_separator(c) {
+            parts.push(&s[start..idx]);
+            start = idx + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
 fn handle_expression(
     left: &Value,
     right: &Value,
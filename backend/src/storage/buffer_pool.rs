@@ -1,80 +1,568 @@
-use super::disk_manager::DiskManager;
+use super::disk_manager::{DatabaseHeader, DiskManager, HEADER_PAGE_ID, NO_PAGE};
 use super::error::{DatabaseError, Result};
-use super::page::Page;
+use super::latch::{Latch, LatchMode};
+use super::page::{Page, PAGE_SIZE};
+use super::wal::{LogFlusher, LogRecord, WriteAheadLog};
 use std::collections::HashMap;
-// use crate::storage::{WriteAheadLog};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, ThreadId};
 
+/// Cache hit, miss and eviction counters for the buffer pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Pages written back to the data file.
+    pub writes: u64,
+}
+
+/// A cached page. The page sits behind a lock that is only held while it is read
+/// or changed; the latch is held by B-tree operations for as long as they depend
+/// on what the page says. A frame that anyone holds a reference to stays cached.
+pub struct Frame {
+    page_id: u32,
+    page: RwLock<Page>,
+    latch: Latch,
+}
+
+impl Frame {
+    fn new(page_id: u32, page: Page) -> Self {
+        Self {
+            page_id,
+            page: RwLock::new(page),
+            latch: Latch::default(),
+        }
+    }
+
+    pub fn page_id(&self) -> u32 {
+        self.page_id
+    }
+
+    pub fn page(&self) -> RwLockReadGuard<'_, Page> {
+        self.page.read().unwrap()
+    }
+
+    pub fn page_mut(&self) -> RwLockWriteGuard<'_, Page> {
+        self.page.write().unwrap()
+    }
+}
+
+/// A latch held on a cached page, released when the guard is dropped.
+pub struct PageGuard {
+    frame: Arc<Frame>,
+    mode: LatchMode,
+}
+
+impl PageGuard {
+    pub fn page_id(&self) -> u32 {
+        self.frame.page_id
+    }
+
+    pub fn mode(&self) -> LatchMode {
+        self.mode
+    }
+
+    pub fn page(&self) -> RwLockReadGuard<'_, Page> {
+        self.frame.page()
+    }
+}
+
+impl Drop for PageGuard {
+    fn drop(&mut self) {
+        self.frame.latch.release(self.mode);
+    }
+}
+
+struct PoolState {
+    frames: HashMap<u32, Arc<Frame>>,
+    // Logical time of the last access to each cached page, for LRU eviction
+    last_used: HashMap<u32, u64>,
+    clock: u64,
+    stats: BufferPoolStats,
+}
+
+/// The cached header page. Like any other page it only reaches disk once the
+/// log records for its changes are durable.
+struct HeaderFrame {
+    header: DatabaseHeader,
+    dirty: bool,
+    // Sequence number of the last logged change
+    lsn: u64,
+}
+
+/// The page cache. Every method takes `&self`, so one pool is shared by all the
+/// threads working on a database. The page table is locked only while frames
+/// are looked up, loaded or evicted, never while a page is written back for
+/// eviction; lock order is page table, then the header, then a page, then the
+/// disk manager, then the log.
 pub struct BufferPool {
-    pages: HashMap<u32, Page>,
+    state: Mutex<PoolState>,
+    header: Mutex<HeaderFrame>,
     capacity: usize,
-    disk_manager: DiskManager,
-    // current_txn: Option<u64>,
-    // wal: WriteAheadLog
+    disk_manager: Mutex<DiskManager>,
+    wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    flusher: Option<Arc<LogFlusher>>,
+    // Transaction each thread is writing pages for
+    current_txns: Mutex<HashMap<ThreadId, u64>>,
 }
 
 impl BufferPool {
     pub fn new(capacity: usize, disk_manager: DiskManager) -> Self {
         Self {
-            pages: HashMap::new(),
+            state: Mutex::new(PoolState {
+                frames: HashMap::new(),
+                last_used: HashMap::new(),
+                clock: 0,
+                stats: BufferPoolStats::default(),
+            }),
+            header: Mutex::new(HeaderFrame {
+                header: disk_manager.header().clone(),
+                dirty: false,
+                lsn: 0,
+            }),
             capacity,
-            disk_manager,
-            // current_txn: None,
-            // wal: WriteAheadLog::new("db.wal").unwrap(),
+            disk_manager: Mutex::new(disk_manager),
+            wal: None,
+            flusher: None,
+            current_txns: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn get_page(&mut self, page_id: u32) -> Result<&mut Page> {
-        if self.pages.contains_key(&page_id) {
-            return Ok(self.pages.get_mut(&page_id).unwrap());
-        }
+    /// Attaches a write-ahead log. Page writes made while a transaction is set
+    /// through `set_current_txn` are logged with their before and after images,
+    /// and a dirty page only reaches disk once the log covering it is durable.
+    pub fn set_wal(&mut self, wal: Arc<Mutex<WriteAheadLog>>) {
+        self.flusher = Some(wal.lock().unwrap().flusher());
+        self.wal = Some(wal);
+    }
 
-        if self.pages.len() >= self.capacity {
-            return Err(DatabaseError::BufferPoolFull);
-        }
+    /// Sets the transaction that page writes from the calling thread are logged
+    /// under, so concurrent writers on other threads keep their own.
+    pub fn set_current_txn(&self, txn_id: Option<u64>) {
+        let mut current_txns = self.current_txns.lock().unwrap();
+        match txn_id {
+            Some(txn_id) => current_txns.insert(thread::current().id(), txn_id),
+            None => current_txns.remove(&thread::current().id()),
+        };
+    }
 
-        let page = self.disk_manager.read_page(page_id).map_err(|e| {
-            DatabaseError::InvalidOperation(format!("Failed to read page {}: {}", page_id, e))
-        })?;
-        self.pages.insert(page_id, page);
-        Ok(self.pages.get_mut(&page_id).unwrap())
+    pub fn header(&self) -> DatabaseHeader {
+        self.header.lock().unwrap().header.clone()
     }
 
-    pub fn new_page(&mut self) -> Result<&mut Page> {
-        if self.pages.len() >= self.capacity {
-            return Err(DatabaseError::BufferPoolFull);
+    /// Page ID of the B-tree root, or `None` if no tree has been created yet.
+    pub fn root_page_id(&self) -> Option<u32> {
+        Some(self.header.lock().unwrap().header.root_page_id).filter(|&page_id| page_id != NO_PAGE)
+    }
+
+    pub fn set_root_page_id(&self, root_page_id: u32) -> Result<()> {
+        let mut header = self.header.lock().unwrap();
+        let before = header.header.serialize();
+        header.header.root_page_id = root_page_id;
+        self.log_header_change(&mut header, before)
+    }
+
+    /// Stores the next transaction ID in the header, to be written with the next
+    /// `flush`. The change is not logged: it only ever grows, and is taken
+    /// together with the log on open.
+    pub fn set_next_txn_id(&self, next_txn_id: u64) -> Result<()> {
+        let mut header = self.header.lock().unwrap();
+        header.header.next_txn_id = next_txn_id;
+        header.dirty = true;
+        Ok(())
+    }
+
+    /// Replaces the header with `image`, as recovery does when it replays a
+    /// logged header change.
+    pub fn write_header_image(&self, image: &[u8]) -> Result<()> {
+        let mut header = self.header.lock().unwrap();
+        header.header = DatabaseHeader::deserialize(image)?;
+        header.dirty = true;
+        Ok(())
+    }
+
+    pub fn get_page(&self, page_id: u32) -> Result<Arc<Frame>> {
+        if page_id == HEADER_PAGE_ID {
+            return Err(DatabaseError::InvalidOperation(
+                "Page 0 is reserved for the database header".to_string(),
+            ));
         }
 
-        let page_id = self.disk_manager.allocate_page()?;
-        let page = Page::new(page_id);
-        self.pages.insert(page_id, page);
-        Ok(self.pages.get_mut(&page_id).unwrap())
+        self.load(page_id)
     }
 
-    pub fn write_page(&mut self, page_id: u32, page: Page) -> Result<()> {
-        self.pages.insert(page_id, page.clone());
-        self.disk_manager.write_page(&page).map_err(|e| {
-            DatabaseError::InvalidOperation(format!("Failed to write page {}: {}", page_id, e))
-        })?;
+    /// Waits for a latch on `page_id` in `mode`. The page stays cached until the
+    /// returned guard is dropped.
+    pub fn latch(&self, page_id: u32, mode: LatchMode) -> Result<PageGuard> {
+        let frame = self.get_page(page_id)?;
+        frame.latch.acquire(mode);
+        Ok(PageGuard { frame, mode })
+    }
+
+    /// Hands out a page, reusing the most recently freed one when possible.
+    pub fn new_page(&self) -> Result<Arc<Frame>> {
+        let mut state = self.lock_with_room()?;
+        let mut header = self.header.lock().unwrap();
+        let header_before = header.header.serialize();
+
+        let page_id = header.header.free_list_head;
+        let frame = if page_id != NO_PAGE {
+            // The freed page may be on disk with its link, so emptying it is a
+            // logged change like any other
+            let frame = self.load_locked(&mut state, page_id)?;
+            let mut page = frame.page_mut();
+            header.header.free_list_head = free_list_link(&page);
+            let before = page.serialize();
+            reset_page(&mut page, NO_PAGE);
+            if let Some(lsn) = self.log_delta(page_id, before, page.serialize())? {
+                page.set_lsn(lsn);
+            }
+            drop(page);
+            frame
+        } else {
+            let page_id = header.header.next_page_id;
+            header.header.next_page_id += 1;
+
+            // Extending the file with an empty page loses nothing in a crash, so
+            // it is done at once and the page can be read back after eviction
+            let page = Page::new(page_id);
+            self.disk_manager.lock().unwrap().write_page(&page)?;
+            state.insert(page_id, page)
+        };
+
+        self.log_header_change(&mut header, header_before)?;
+        Ok(frame)
+    }
+
+    /// Replaces the cached contents of a page and marks it dirty. The page is
+    /// written to disk later, by eviction or `flush`.
+    pub fn write_page(&self, page_id: u32, page: Page) -> Result<()> {
+        let frame = match self.get_page(page_id) {
+            Ok(frame) => frame,
+            Err(DatabaseError::BufferPoolFull) => return Err(DatabaseError::BufferPoolFull),
+            // Never written before; the new contents make it readable
+            Err(_) => self.lock_with_room()?.insert(page_id, Page::new(page_id)),
+        };
+
+        let mut cached = frame.page_mut();
+        let before = cached.serialize();
+        // Update the cached copy in place so pins held on it survive
+        cached.header = page.header;
+        cached.data = page.data;
+        cached.mark_dirty();
+        if let Some(lsn) = self.log_delta(page_id, before, cached.serialize())? {
+            cached.set_lsn(lsn);
+        }
         Ok(())
     }
 
-    pub fn free_page(&mut self, page_id: u32) -> Result<()> {
-        self.pages.remove(&page_id);
-        self.disk_manager.free_page(page_id)?;
+    /// Changes a page in place through `f`, marks it dirty and logs the change
+    /// for the current transaction.
+    pub fn modify_page<F>(&self, page_id: u32, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Page) -> Result<()>,
+    {
+        let frame = self.get_page(page_id)?;
+        let mut page = frame.page_mut();
+        let before = page.serialize();
+        f(&mut page)?;
+        page.mark_dirty();
+        let after = page.serialize();
+
+        if let Some(lsn) = self.log_delta(page_id, before, after)? {
+            page.set_lsn(lsn);
+        }
         Ok(())
     }
 
-    pub fn flush(&mut self) -> Result<()> {
-        for page in self.pages.values() {
+    /// Puts a page on the free list so `new_page` can hand it out again. The
+    /// freed page stores the ID of the next free page in its first bytes. The
+    /// page and the header stay cached as dirty pages, so neither reaches disk
+    /// before the log records that can undo the change.
+    pub fn free_page(&self, page_id: u32) -> Result<()> {
+        let mut state = self.lock_with_room()?;
+        let mut header = self.header.lock().unwrap();
+        if page_id == HEADER_PAGE_ID || page_id >= header.header.next_page_id {
+            return Err(DatabaseError::InvalidPage);
+        }
+        let header_before = header.header.serialize();
+
+        let frame = self.load_locked(&mut state, page_id)?;
+        let mut page = frame.page_mut();
+        let before = page.serialize();
+        reset_page(&mut page, header.header.free_list_head);
+        if let Some(lsn) = self.log_delta(page_id, before, page.serialize())? {
+            page.set_lsn(lsn);
+        }
+        drop(page);
+
+        header.header.free_list_head = page_id;
+        self.log_header_change(&mut header, header_before)
+    }
+
+    /// Lists the pages on the free list, most recently freed first.
+    pub fn free_page_ids(&self) -> Result<Vec<u32>> {
+        let header = self.header();
+        let mut page_ids = Vec::new();
+        let mut page_id = header.free_list_head;
+        while page_id != NO_PAGE {
+            // A list longer than the file has pages must loop
+            if page_id >= header.next_page_id || page_ids.len() >= header.next_page_id as usize {
+                return Err(DatabaseError::InvalidData(format!(
+                    "Free list is broken at page {}",
+                    page_id
+                )));
+            }
+            page_ids.push(page_id);
+            page_id = free_list_link(&self.get_page(page_id)?.page());
+        }
+        Ok(page_ids)
+    }
+
+    /// Writes every dirty page and the header back to the data file and syncs
+    /// it. The page table stays locked throughout, so no page is loaded or freed
+    /// meanwhile.
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut header = self.header.lock().unwrap();
+        let max_lsn = state
+            .frames
+            .values()
+            .map(|frame| frame.page())
+            .filter(|page| page.is_dirty())
+            .map(|page| page.lsn())
+            .chain(header.dirty.then_some(header.lsn))
+            .max();
+        if let Some(lsn) = max_lsn {
+            self.flush_log_to(lsn)?;
+        }
+
+        let mut writes = 0;
+        for frame in state.frames.values() {
+            let mut page = frame.page_mut();
             if page.is_dirty() {
-                self.disk_manager.write_page(page).map_err(|e| {
-                    DatabaseError::InvalidOperation(format!(
-                        "Failed to write page {}: {}",
-                        page.header.page_id, e
-                    ))
-                })?;
+                // The page may have changed since the log was forced above
+                self.flush_log_to(page.lsn())?;
+                self.disk_manager
+                    .lock()
+                    .unwrap()
+                    .write_page(&page)
+                    .map_err(|e| {
+                        DatabaseError::InvalidOperation(format!(
+                            "Failed to write page {}: {}",
+                            page.header.page_id, e
+                        ))
+                    })?;
+                page.mark_clean();
+                writes += 1;
             }
         }
+        state.stats.writes += writes;
+        let mut disk_manager = self.disk_manager.lock().unwrap();
+        if header.dirty {
+            disk_manager.write_header(&header.header)?;
+            header.dirty = false;
+        }
+        disk_manager.sync()
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Enforces the WAL rule: log records up to a page's LSN must be durable
+    /// before the page itself is written.
+    fn flush_log_to(&self, lsn: u64) -> Result<()> {
+        match &self.flusher {
+            Some(flusher) if lsn > 0 => flusher.flush_to(lsn),
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the page table with room for one more page. A dirty victim is
+    /// written back with the page table unlocked, so other threads keep using
+    /// the pool while the log is forced; the victim is chosen again afterwards.
+    fn lock_with_room(&self) -> Result<MutexGuard<'_, PoolState>> {
+        loop {
+            let mut state = self.state.lock().unwrap();
+            match self.make_room(&mut state)? {
+                None => return Ok(state),
+                Some(victim) => {
+                    drop(state);
+                    self.write_back(&victim)?;
+                }
+            }
+        }
+    }
+
+    /// Evicts the least recently used page nobody holds if the pool is at
+    /// capacity. A dirty victim is not evicted but returned, to be written back
+    /// first; the reference returned keeps others from picking it meanwhile.
+    /// Fails with `BufferPoolFull` only when every cached page is pinned, latched
+    /// or in use.
+    fn make_room(&self, state: &mut PoolState) -> Result<Option<Arc<Frame>>> {
+        if state.frames.len() < self.capacity {
+            return Ok(None);
+        }
+
+        // Only the page table refers to a frame nobody is using
+        let victim = state
+            .frames
+            .iter()
+            .filter(|(_, frame)| Arc::strong_count(frame) == 1)
+            .filter(|(_, frame)| matches!(frame.page.try_read(), Ok(page) if !page.is_pinned()))
+            .min_by_key(|(page_id, _)| state.last_used.get(page_id).copied().unwrap_or(0))
+            .map(|(page_id, _)| *page_id);
+        let Some(page_id) = victim else {
+            return Err(DatabaseError::BufferPoolFull);
+        };
+
+        if state.frames[&page_id].page().is_dirty() {
+            return Ok(Some(Arc::clone(&state.frames[&page_id])));
+        }
+        state.frames.remove(&page_id);
+        state.last_used.remove(&page_id);
+        state.stats.evictions += 1;
+        Ok(None)
+    }
+
+    /// Writes a dirty page back once the log covering it is durable, leaving it
+    /// cached and clean.
+    fn write_back(&self, frame: &Frame) -> Result<()> {
+        let mut page = frame.page_mut();
+        if !page.is_dirty() {
+            return Ok(());
+        }
+        self.flush_log_to(page.lsn())?;
+        self.disk_manager.lock().unwrap().write_page(&page)?;
+        page.mark_clean();
+        drop(page);
+
+        self.state.lock().unwrap().stats.writes += 1;
+        Ok(())
+    }
+
+    /// Looks up a page in the cache, loading it from disk on a miss.
+    fn load(&self, page_id: u32) -> Result<Arc<Frame>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(frame) = state.frames.get(&page_id).cloned() {
+                state.stats.hits += 1;
+                state.touch(page_id);
+                return Ok(frame);
+            }
+            state.stats.misses += 1;
+        }
+
+        let mut state = self.lock_with_room()?;
+        self.load_locked(&mut state, page_id)
+    }
+
+    /// Like `load`, for a caller that holds the page table with room to spare.
+    fn load_locked(&self, state: &mut PoolState, page_id: u32) -> Result<Arc<Frame>> {
+        if let Some(frame) = state.frames.get(&page_id).cloned() {
+            state.touch(page_id);
+            return Ok(frame);
+        }
+
+        let page = self
+            .disk_manager
+            .lock()
+            .unwrap()
+            .read_page(page_id)
+            .map_err(|e| match e {
+                DatabaseError::Corruption { .. } | DatabaseError::PageNotFound(_) => e,
+                e => DatabaseError::InvalidOperation(format!(
+                    "Failed to read page {}: {}",
+                    page_id, e
+                )),
+            })?;
+        Ok(state.insert(page_id, page))
+    }
+
+    /// Marks the cached header dirty and logs its change since `before`, so that
+    /// recovery keeps the free list consistent with the pages it restores.
+    fn log_header_change(&self, header: &mut HeaderFrame, before: Vec<u8>) -> Result<()> {
+        header.dirty = true;
+        if let Some(lsn) = self.log_delta(HEADER_PAGE_ID, before, header.header.serialize())? {
+            header.lsn = lsn;
+        }
         Ok(())
     }
+
+    /// Appends a physiological `Write` record for the current transaction before
+    /// the page reaches disk. Only the byte range that differs between the old and
+    /// new page images is logged, together with its previous contents for undo.
+    /// Returns the record's sequence number if one was written.
+    fn log_delta(
+        &self,
+        page_id: u32,
+        mut before: Vec<u8>,
+        mut after: Vec<u8>,
+    ) -> Result<Option<u64>> {
+        let Some(wal) = &self.wal else {
+            return Ok(None);
+        };
+        let Some(txn_id) = self
+            .current_txns
+            .lock()
+            .unwrap()
+            .get(&thread::current().id())
+            .copied()
+        else {
+            return Ok(None);
+        };
+
+        before.resize(PAGE_SIZE, 0);
+        after.resize(PAGE_SIZE, 0);
+
+        let Some(start) = (0..PAGE_SIZE).find(|&i| before[i] != after[i]) else {
+            return Ok(None);
+        };
+        let end = (start..PAGE_SIZE)
+            .rfind(|&i| before[i] != after[i])
+            .unwrap()
+            + 1;
+
+        let sequence = wal.lock().unwrap().log(LogRecord::Write {
+            txn_id,
+            page_id,
+            offset: start as u16,
+            before: before[start..end].to_vec(),
+            after: after[start..end].to_vec(),
+        })?;
+        Ok(Some(sequence))
+    }
+}
+
+impl PoolState {
+    fn touch(&mut self, page_id: u32) {
+        self.clock += 1;
+        self.last_used.insert(page_id, self.clock);
+    }
+
+    fn insert(&mut self, page_id: u32, page: Page) -> Arc<Frame> {
+        let frame = Arc::new(Frame::new(page_id, page));
+        self.frames.insert(page_id, Arc::clone(&frame));
+        self.touch(page_id);
+        frame
+    }
+}
+
+/// The next page on the free list after `page`.
+fn free_list_link(page: &Page) -> u32 {
+    u32::from_le_bytes(page.data[0..4].try_into().unwrap())
+}
+
+/// Empties a cached page in place and stores `next_free` as its free-list link.
+/// Pins and the page LSN are kept, so the write-back still waits for the log.
+fn reset_page(page: &mut Page, next_free: u32) {
+    let lsn = page.lsn();
+    let empty = Page::new(page.header.page_id);
+    page.header = empty.header;
+    page.data = empty.data;
+    page.data[0..4].copy_from_slice(&next_free.to_le_bytes());
+    page.set_lsn(lsn);
+    page.mark_dirty();
 }
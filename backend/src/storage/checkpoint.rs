@@ -0,0 +1,46 @@
+use super::buffer_pool::BufferPool;
+use super::error::Result;
+use super::wal::{LogRecord, WriteAheadLog};
+
+/// Flushes every dirty page, records a checkpoint listing the transactions that
+/// are still active, and drops the log records recovery no longer needs.
+///
+/// Records of active transactions are kept from their `Begin` onwards, since
+/// recovery may still have to undo them. The header is advanced past every
+/// transaction ID in the log and flushed with the pages before any record is
+/// dropped, so IDs are never reused once the log no longer names them. Returns
+/// the checkpoint's sequence number.
+pub fn checkpoint(
+    wal: &mut WriteAheadLog,
+    buffer_pool: &BufferPool,
+    active_txns: Vec<u64>,
+) -> Result<u64> {
+    let records = wal.read_records()?;
+    let max_txn_id = records
+        .iter()
+        .filter_map(|(_, record)| record.txn_id())
+        .chain(active_txns.iter().copied())
+        .max()
+        .unwrap_or(0);
+    let next_txn_id = buffer_pool.header().next_txn_id;
+    if max_txn_id >= next_txn_id {
+        buffer_pool.set_next_txn_id(max_txn_id + 1)?;
+    }
+    buffer_pool.flush()?;
+
+    let checkpoint_sequence = wal.log(LogRecord::Checkpoint {
+        active_txns: active_txns.clone(),
+    })?;
+
+    let keep_from = records
+        .iter()
+        .filter_map(|(sequence, record)| match record {
+            LogRecord::Begin(txn_id) if active_txns.contains(txn_id) => Some(*sequence),
+            _ => None,
+        })
+        .min()
+        .unwrap_or(checkpoint_sequence);
+    wal.truncate_before(keep_from)?;
+
+    Ok(checkpoint_sequence)
+}
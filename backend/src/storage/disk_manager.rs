@@ -1,56 +1,172 @@
 use super::error::{DatabaseError, Result};
 use super::page::{Page, PAGE_SIZE};
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+/// Page 0 holds the database header; it is never handed out by `BufferPool::new_page`.
+pub const HEADER_PAGE_ID: u32 = 0;
+/// Marks the end of the free-page list. Page 0 can never be free, so it doubles
+/// as the sentinel.
+pub const NO_PAGE: u32 = HEADER_PAGE_ID;
+
+const MAGIC: &[u8; 4] = b"RSDB";
+const FORMAT_VERSION: u32 = 5;
+
+/// Contents of the header page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseHeader {
+    pub page_size: u32,
+    pub next_page_id: u32,
+    pub root_page_id: u32,
+    pub free_list_head: u32,
+    /// Lowest transaction ID that may be handed out; kept at checkpoints, since
+    /// row versions outlive the log records that name their transactions.
+    pub next_txn_id: u64,
+}
+
+impl DatabaseHeader {
+    fn new() -> Self {
+        Self {
+            page_size: PAGE_SIZE as u32,
+            next_page_id: HEADER_PAGE_ID + 1,
+            root_page_id: NO_PAGE,
+            free_list_head: NO_PAGE,
+            next_txn_id: 1,
+        }
+    }
+
+    /// Serializes the header into a full page image.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(PAGE_SIZE);
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buffer.extend_from_slice(&self.page_size.to_le_bytes());
+        buffer.extend_from_slice(&self.next_page_id.to_le_bytes());
+        buffer.extend_from_slice(&self.root_page_id.to_le_bytes());
+        buffer.extend_from_slice(&self.free_list_head.to_le_bytes());
+        buffer.extend_from_slice(&self.next_txn_id.to_le_bytes());
+        buffer.resize(PAGE_SIZE, 0);
+        buffer
+    }
+
+    pub fn deserialize(buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < 32 || &buffer[0..4] != MAGIC {
+            return Err(DatabaseError::InvalidData(
+                "Not a database file: bad magic number".to_string(),
+            ));
+        }
+        let field = |i: usize| u32::from_le_bytes(buffer[i..i + 4].try_into().unwrap());
+
+        let version = field(4);
+        if version != FORMAT_VERSION {
+            return Err(DatabaseError::InvalidData(format!(
+                "Unsupported format version {}",
+                version
+            )));
+        }
+        let header = Self {
+            page_size: field(8),
+            next_page_id: field(12),
+            root_page_id: field(16),
+            free_list_head: field(20),
+            next_txn_id: u64::from_le_bytes(buffer[24..32].try_into().unwrap()),
+        };
+        if header.page_size as usize != PAGE_SIZE {
+            return Err(DatabaseError::InvalidData(format!(
+                "Database uses {} byte pages, expected {}",
+                header.page_size, PAGE_SIZE
+            )));
+        }
+        Ok(header)
+    }
+}
 
 pub struct DiskManager {
     heap_file: File,
-    next_page_id: u32,
+    header: DatabaseHeader,
 }
 
 impl DiskManager {
     pub fn new(file_path: &str) -> Result<Self> {
-        let heap_file = OpenOptions::new()
+        let mut heap_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(file_path)
-            .map_err(|e| DatabaseError::IoError(e))?;
+            .map_err(DatabaseError::IoError)?;
+
+        let header = if heap_file.metadata()?.len() == 0 {
+            let header = DatabaseHeader::new();
+            heap_file.write_all(&header.serialize())?;
+            header
+        } else {
+            let mut buffer = vec![0; PAGE_SIZE];
+            heap_file.seek(SeekFrom::Start(0))?;
+            heap_file.read_exact(&mut buffer)?;
+            DatabaseHeader::deserialize(&buffer)?
+        };
 
-        Ok(Self {
-            heap_file,
-            next_page_id: 0,
-        })
+        Ok(Self { heap_file, header })
     }
 
-    pub fn allocate_page(&mut self) -> Result<u32> {
-        let page_id = self.next_page_id;
-        self.next_page_id += 1;
+    /// Opens an existing database file for reading only, as the offline checker
+    /// does. Every write through it fails.
+    pub fn open_read_only(file_path: &str) -> Result<Self> {
+        let mut heap_file = File::open(file_path)?;
+        let mut buffer = vec![0; PAGE_SIZE];
+        heap_file.read_exact(&mut buffer).map_err(|_| {
+            DatabaseError::InvalidData("Not a database file: no header page".to_string())
+        })?;
+        let header = DatabaseHeader::deserialize(&buffer)?;
+        Ok(Self { heap_file, header })
+    }
 
-        let zeros = vec![0u8; PAGE_SIZE];
-        self.heap_file
-            .seek(SeekFrom::Start(page_id as u64 * PAGE_SIZE as u64))
-            .map_err(|_| DatabaseError::PageNotFound(page_id))?;
-        self.heap_file
-            .write_all(&zeros)
-            .map_err(|_| DatabaseError::PageNotFound(page_id))?;
+    /// The header as last read from or written to the file.
+    pub fn header(&self) -> &DatabaseHeader {
+        &self.header
+    }
 
-        Ok(page_id)
+    /// Writes `header` to the header page. The buffer pool caches the header and
+    /// calls this when writing it back, once the log covering it is durable.
+    pub fn write_header(&mut self, header: &DatabaseHeader) -> Result<()> {
+        self.heap_file.seek(SeekFrom::Start(0))?;
+        self.heap_file.write_all(&header.serialize())?;
+        self.heap_file.flush()?;
+        self.header = header.clone();
+        Ok(())
     }
 
+    /// Reads a page, failing with `PageNotFound` if it lies past the end of the
+    /// file and with `Corruption` if its checksum does not match or the file
+    /// ends part-way through it.
     pub fn read_page(&mut self, page_id: u32) -> Result<Page> {
-        let mut buffer = vec![0; PAGE_SIZE];
-        self.heap_file
-            .seek(SeekFrom::Start(page_id as u64 * PAGE_SIZE as u64))?;
+        let offset = page_id as u64 * PAGE_SIZE as u64;
+        if offset >= self.heap_file.metadata()?.len() {
+            return Err(DatabaseError::PageNotFound(page_id));
+        }
 
+        let mut buffer = vec![0; PAGE_SIZE];
+        self.heap_file.seek(SeekFrom::Start(offset))?;
         self.heap_file
             .read_exact(&mut buffer)
-            .map_err(|_| DatabaseError::PageNotFound(page_id))?;
+            .map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => DatabaseError::Corruption { page_id },
+                _ => DatabaseError::IoError(e),
+            })?;
+        if !Page::verify_checksum(&buffer) {
+            return Err(DatabaseError::Corruption { page_id });
+        }
         Page::deserialize(&buffer)
     }
 
     pub fn write_page(&mut self, page: &Page) -> Result<()> {
-        let buffer = page.serialize();
+        if page.header.page_id == HEADER_PAGE_ID {
+            return Err(DatabaseError::InvalidOperation(
+                "Page 0 is reserved for the database header".to_string(),
+            ));
+        }
+        let buffer = page.serialize_with_checksum();
         self.heap_file.seek(SeekFrom::Start(
             page.header.page_id as u64 * PAGE_SIZE as u64,
         ))?;
@@ -62,27 +178,9 @@ impl DiskManager {
             .map_err(|_| DatabaseError::PageNotFound(page.header.page_id))
     }
 
-    pub fn free_page(&mut self, page_id: u32) -> Result<()> {
-        // Check if the page_id is valid
-        if page_id >= self.next_page_id {
-            return Err(DatabaseError::InvalidPage);
-        }
-
-        // Seek to the page location
-        self.heap_file
-            .seek(SeekFrom::Start(page_id as u64 * PAGE_SIZE as u64))
-            .map_err(|_| DatabaseError::PageNotFound(page_id))?;
-
-        // Overwrite the page with zeros
-        let zeros = vec![0u8; PAGE_SIZE];
-        self.heap_file
-            .write_all(&zeros)
-            .map_err(|_| DatabaseError::PageNotFound(page_id))?;
-
-        // Flush changes to disk
-        self.heap_file.flush()?;
-
-        // Note: We don't decrement next_page_id to avoid reusing page IDs,
+    /// Forces all written pages to stable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        self.heap_file.sync_data()?;
         Ok(())
     }
 }
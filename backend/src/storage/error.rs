@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use super::key::Key;
+
 #[derive(Error, Debug)]
 pub enum DatabaseError {
     #[error("Page {0} not found")]
@@ -11,6 +13,9 @@ pub enum DatabaseError {
     #[error("Invalid page")]
     InvalidPage,
 
+    #[error("Page {page_id} is corrupted: checksum mismatch")]
+    Corruption { page_id: u32 },
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -23,6 +28,12 @@ pub enum DatabaseError {
     #[error("Page is full")]
     PageFull,
 
+    #[error("Value of {size} bytes is too large; at most {max} bytes fit in a page")]
+    ValueTooLarge { size: usize, max: usize },
+
+    #[error("Key of {size} bytes is too large; keys are at most {max} bytes once encoded")]
+    KeyTooLarge { size: usize, max: usize },
+
     #[error("Invalid slot")]
     InvalidSlot,
 
@@ -35,8 +46,20 @@ pub enum DatabaseError {
     #[error("Invalid data: {0}")]
     InvalidData(String),
 
-    #[error("Key not found")]
-    KeyNotFound(i32),
+    #[error("Key {0} not found")]
+    KeyNotFound(Key),
+
+    #[error("Key {0} is being changed by another transaction")]
+    WriteConflict(Key),
+
+    #[error("Deadlock detected; transaction {0} was chosen as the victim")]
+    Deadlock(u64),
+
+    #[error("Timed out waiting for a lock on key {0}")]
+    LockTimeout(Key),
+
+    #[error("Savepoint {0} does not exist")]
+    SavepointNotFound(String),
 
     #[error("Transaction not active")]
     TransactionNotActive,
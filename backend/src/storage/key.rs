@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::{DatabaseError, Result};
+
+const INT_TAG: u8 = 1;
+const STRING_TAG: u8 = 2;
+const BYTES_TAG: u8 = 3;
+
+// Strings and byte arrays end with 0x00 0x01, and a 0x00 inside them is written
+// as 0x00 0xFF, so a key sorts before every longer key it is a prefix of.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// A key in the index. Keys of different kinds order by kind first: integers,
+/// then strings, then byte arrays.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Key {
+    Int(i64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl Key {
+    /// Encodes the key so that comparing two encodings byte by byte orders them
+    /// the same way as comparing the keys.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            Key::Int(i) => {
+                buffer.push(INT_TAG);
+                // Flipping the sign bit makes negative numbers sort first
+                buffer.extend_from_slice(&((*i as u64) ^ (1 << 63)).to_be_bytes());
+            }
+            Key::String(s) => {
+                buffer.push(STRING_TAG);
+                encode_bytes(s.as_bytes(), &mut buffer);
+            }
+            Key::Bytes(bytes) => {
+                buffer.push(BYTES_TAG);
+                encode_bytes(bytes, &mut buffer);
+            }
+        }
+        buffer
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let invalid = || DatabaseError::InvalidData("Invalid key encoding".to_string());
+        match buffer.split_first() {
+            Some((&INT_TAG, rest)) => {
+                let bits = u64::from_be_bytes(rest.try_into().map_err(|_| invalid())?);
+                Ok(Key::Int((bits ^ (1 << 63)) as i64))
+            }
+            Some((&STRING_TAG, rest)) => {
+                let bytes = decode_bytes(rest).ok_or_else(invalid)?;
+                String::from_utf8(bytes)
+                    .map(Key::String)
+                    .map_err(|_| invalid())
+            }
+            Some((&BYTES_TAG, rest)) => decode_bytes(rest).map(Key::Bytes).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn encode_bytes(bytes: &[u8], buffer: &mut Vec<u8>) {
+    for &b in bytes {
+        buffer.push(b);
+        if b == ESCAPE {
+            buffer.push(ESCAPED_ZERO);
+        }
+    }
+    buffer.extend_from_slice(&[ESCAPE, TERMINATOR]);
+}
+
+fn decode_bytes(buffer: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut iter = buffer.iter();
+    while let Some(&b) = iter.next() {
+        if b != ESCAPE {
+            bytes.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(&ESCAPED_ZERO) => bytes.push(ESCAPE),
+            Some(&TERMINATOR) if iter.as_slice().is_empty() => return Some(bytes),
+            _ => return None,
+        }
+    }
+    None
+}
+
+impl From<i32> for Key {
+    fn from(i: i32) -> Self {
+        Key::Int(i as i64)
+    }
+}
+
+impl From<i64> for Key {
+    fn from(i: i64) -> Self {
+        Key::Int(i)
+    }
+}
+
+impl From<&str> for Key {
+    fn from(s: &str) -> Self {
+        Key::String(s.to_string())
+    }
+}
+
+impl From<String> for Key {
+    fn from(s: String) -> Self {
+        Key::String(s)
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(bytes: Vec<u8>) -> Self {
+        Key::Bytes(bytes)
+    }
+}
+
+/// Parses a key as written in a command: `"text"` is a string key (with `\"` and
+/// `\\` escapes), `0x` followed by hex digits is a byte key, and anything else
+/// must be an integer.
+impl FromStr for Key {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(quoted) = s.strip_prefix('"') {
+            let inner = quoted.strip_suffix('"').ok_or_else(|| {
+                DatabaseError::ParseError(format!("Unterminated string key: {}", s))
+            })?;
+            let mut text = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some(escaped @ ('"' | '\\')) => text.push(escaped),
+                        _ => {
+                            return Err(DatabaseError::ParseError(format!(
+                                "Invalid escape in string key: {}",
+                                s
+                            )))
+                        }
+                    },
+                    '"' => {
+                        return Err(DatabaseError::ParseError(format!(
+                            "Unescaped quote in string key: {}",
+                            s
+                        )))
+                    }
+                    c => text.push(c),
+                }
+            }
+            return Ok(Key::String(text));
+        }
+
+        if let Some(hex) = s.strip_prefix("0x") {
+            if hex.len() % 2 == 0 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                    .collect();
+                return Ok(Key::Bytes(bytes));
+            }
+            return Err(DatabaseError::ParseError(format!(
+                "Invalid byte key: {}",
+                s
+            )));
+        }
+
+        s.parse::<i64>().map(Key::Int).map_err(|_| {
+            DatabaseError::ParseError(format!(
+                "Invalid key: {}; quote string keys, e.g. \"{}\"",
+                s, s
+            ))
+        })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Int(i) => write!(f, "{}", i),
+            Key::String(s) => write!(f, "{:?}", s),
+            Key::Bytes(bytes) => {
+                write!(f, "0x")?;
+                for b in bytes {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// Page latches. Unlike a `RwLock` guard, a latch is taken and released through
+// explicit calls, so a B-tree operation can hold the latches of a whole path and
+// drop the ancestors it no longer needs while keeping the rest.
+
+use std::sync::{Condvar, Mutex};
+
+/// Shared latches admit any number of readers; an exclusive latch admits one
+/// writer and no readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatchMode {
+    Shared,
+    Exclusive,
+}
+
+#[derive(Default)]
+struct LatchState {
+    readers: usize,
+    writer: bool,
+    // Writers queued for the latch. New readers wait behind them, so a steady
+    // stream of readers cannot starve a writer.
+    waiting_writers: usize,
+}
+
+#[derive(Default)]
+pub struct Latch {
+    state: Mutex<LatchState>,
+    released: Condvar,
+}
+
+impl Latch {
+    pub fn acquire(&self, mode: LatchMode) {
+        let mut state = self.state.lock().unwrap();
+        match mode {
+            LatchMode::Shared => {
+                while state.writer || state.waiting_writers > 0 {
+                    state = self.released.wait(state).unwrap();
+                }
+                state.readers += 1;
+            }
+            LatchMode::Exclusive => {
+                state.waiting_writers += 1;
+                while state.writer || state.readers > 0 {
+                    state = self.released.wait(state).unwrap();
+                }
+                state.waiting_writers -= 1;
+                state.writer = true;
+            }
+        }
+    }
+
+    pub fn release(&self, mode: LatchMode) {
+        let mut state = self.state.lock().unwrap();
+        match mode {
+            LatchMode::Shared => state.readers -= 1,
+            LatchMode::Exclusive => state.writer = false,
+        }
+        self.released.notify_all();
+    }
+}
@@ -0,0 +1,253 @@
+// Key-level locks held by transactions until they commit or roll back. A
+// transaction that has to wait is recorded in a wait-for graph; when its wait
+// would close a cycle, the youngest transaction in the cycle is chosen as the
+// victim and its lock request fails with `Deadlock`, so the others can go on
+// once it rolls back. A scan takes a shared lock on the range it reads, which
+// keeps other transactions from writing any key in it, present or not, so
+// what the scan read stays true until the transaction ends.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::error::{DatabaseError, Result};
+use super::key::Key;
+use super::transaction::TransactionId;
+
+/// How long a lock request waits for other transactions before giving up.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shared locks on a key are held by any number of transactions at once; an
+/// exclusive lock by one, with no shared locks beside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+type KeyRange = (Bound<Key>, Bound<Key>);
+
+/// What a lock request is for: one key, or every key in a range.
+#[derive(Clone)]
+enum Request {
+    Key(Key, LockMode),
+    Range(KeyRange),
+}
+
+#[derive(Default)]
+struct LockTable {
+    // Transactions holding locks on each key
+    holders: HashMap<Key, HashMap<TransactionId, LockMode>>,
+    // Keys each transaction holds locks on
+    held: HashMap<TransactionId, HashSet<Key>>,
+    // Ranges each transaction holds shared locks on
+    ranges: HashMap<TransactionId, Vec<KeyRange>>,
+    // The request each blocked transaction is waiting for
+    waiting: HashMap<TransactionId, Request>,
+    // Blocked transactions chosen to break a deadlock, not yet told so
+    victims: HashSet<TransactionId>,
+}
+
+impl LockTable {
+    /// Other transactions whose locks keep `txn_id` from being granted
+    /// `request`, each with a key the two conflict on.
+    fn blockers(&self, txn_id: TransactionId, request: &Request) -> Vec<(TransactionId, Key)> {
+        match request {
+            Request::Key(key, mode) => {
+                let mut blockers: Vec<_> = self
+                    .holders
+                    .get(key)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(holder, held)| {
+                        **holder != txn_id
+                            && (*mode == LockMode::Exclusive || **held == LockMode::Exclusive)
+                    })
+                    .map(|(holder, _)| (*holder, key.clone()))
+                    .collect();
+                if *mode == LockMode::Exclusive {
+                    blockers.extend(
+                        self.ranges
+                            .iter()
+                            .filter(|(holder, ranges)| {
+                                **holder != txn_id && ranges.iter().any(|range| range.contains(key))
+                            })
+                            .map(|(holder, _)| (*holder, key.clone())),
+                    );
+                }
+                blockers
+            }
+            Request::Range(range) => self
+                .holders
+                .iter()
+                .filter(|(key, _)| range.contains(*key))
+                .flat_map(|(key, holders)| {
+                    holders
+                        .iter()
+                        .filter(|(holder, held)| {
+                            **holder != txn_id && **held == LockMode::Exclusive
+                        })
+                        .map(|(holder, _)| (*holder, key.clone()))
+                })
+                .collect(),
+        }
+    }
+
+    fn grant(&mut self, txn_id: TransactionId, request: Request) {
+        match request {
+            Request::Key(key, mode) => {
+                let held = self
+                    .holders
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(txn_id)
+                    .or_insert(mode);
+                *held = (*held).max(mode);
+                self.held.entry(txn_id).or_default().insert(key);
+            }
+            Request::Range(range) => self.ranges.entry(txn_id).or_default().push(range),
+        }
+    }
+
+    /// The transactions `txn_id` waits for, directly or not, if one of them
+    /// waits for `txn_id` in turn.
+    fn find_cycle(&self, txn_id: TransactionId) -> Option<Vec<TransactionId>> {
+        let mut path = vec![txn_id];
+        let mut visited = HashSet::new();
+        self.extend_path(&mut path, &mut visited).then_some(path)
+    }
+
+    fn extend_path(
+        &self,
+        path: &mut Vec<TransactionId>,
+        visited: &mut HashSet<TransactionId>,
+    ) -> bool {
+        let current = *path.last().unwrap();
+        let Some(request) = self.waiting.get(&current) else {
+            return false;
+        };
+        for (next, _) in self.blockers(current, request) {
+            if next == path[0] {
+                return true;
+            }
+            if visited.insert(next) {
+                path.push(next);
+                if self.extend_path(path, visited) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+
+    fn stop_waiting(&mut self, txn_id: TransactionId) {
+        self.waiting.remove(&txn_id);
+        self.victims.remove(&txn_id);
+    }
+}
+
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    released: Condvar,
+    timeout: Duration,
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOCK_TIMEOUT)
+    }
+}
+
+impl LockManager {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+            timeout,
+        }
+    }
+
+    /// Takes a lock on `key` for `txn_id`, waiting while other transactions hold
+    /// conflicting ones. A shared lock already held is upgraded in place. Fails
+    /// with `Deadlock` if `txn_id` is chosen as a deadlock victim, and with
+    /// `LockTimeout` if the lock is not granted within the timeout; either way
+    /// the locks it already holds stay until `release_all`. An exclusive lock
+    /// also waits for transactions holding a range lock that covers `key`.
+    pub fn acquire(&self, txn_id: TransactionId, key: &Key, mode: LockMode) -> Result<()> {
+        self.request(txn_id, Request::Key(key.clone(), mode))
+    }
+
+    /// Takes a shared lock on every key in `range` for `txn_id`, present or
+    /// not, waiting while other transactions hold exclusive locks on keys in
+    /// it. Until `release_all`, no other transaction gets an exclusive lock on
+    /// a key in the range, so none can insert into it either. Fails like
+    /// `acquire`.
+    pub fn acquire_range<R: RangeBounds<Key>>(
+        &self,
+        txn_id: TransactionId,
+        range: R,
+    ) -> Result<()> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.request(txn_id, Request::Range(range))
+    }
+
+    fn request(&self, txn_id: TransactionId, request: Request) -> Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let mut table = self.table.lock().unwrap();
+        loop {
+            if table.victims.contains(&txn_id) {
+                table.stop_waiting(txn_id);
+                return Err(DatabaseError::Deadlock(txn_id.0));
+            }
+            let blockers = table.blockers(txn_id, &request);
+            let Some((_, blocked_on)) = blockers.into_iter().next() else {
+                table.stop_waiting(txn_id);
+                table.grant(txn_id, request);
+                return Ok(());
+            };
+
+            table.waiting.insert(txn_id, request.clone());
+            if let Some(cycle) = table.find_cycle(txn_id) {
+                // The youngest transaction has likely done the least work
+                let victim = cycle.into_iter().max().unwrap();
+                if victim == txn_id {
+                    table.stop_waiting(txn_id);
+                    return Err(DatabaseError::Deadlock(txn_id.0));
+                }
+                table.victims.insert(victim);
+                self.released.notify_all();
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                table.stop_waiting(txn_id);
+                return Err(DatabaseError::LockTimeout(blocked_on));
+            }
+            table = self.released.wait_timeout(table, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Releases every lock `txn_id` holds and wakes the transactions waiting.
+    pub fn release_all(&self, txn_id: TransactionId) {
+        let mut table = self.table.lock().unwrap();
+        for key in table.held.remove(&txn_id).unwrap_or_default() {
+            if let Some(holders) = table.holders.get_mut(&key) {
+                holders.remove(&txn_id);
+                if holders.is_empty() {
+                    table.holders.remove(&key);
+                }
+            }
+        }
+        table.ranges.remove(&txn_id);
+        table.stop_waiting(txn_id);
+        self.released.notify_all();
+    }
+
+    /// The mode `txn_id` holds `key` in, if it holds a lock on it.
+    pub fn held_mode(&self, txn_id: TransactionId, key: &Key) -> Option<LockMode> {
+        let table = self.table.lock().unwrap();
+        table.holders.get(key)?.get(&txn_id).copied()
+    }
+}
@@ -1,8 +1,14 @@
 pub mod buffer_pool;
+pub mod checkpoint;
 pub mod disk_manager;
 pub mod error;
+pub mod key;
+pub mod latch;
+pub mod lock_manager;
+pub mod mvcc;
 pub mod operations;
 pub mod page;
+pub mod recovery;
 pub mod slotted_page;
 pub mod transaction;
 pub mod value;
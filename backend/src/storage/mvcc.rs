@@ -0,0 +1,546 @@
+// Multi-version concurrency control. The B-tree keeps the newest version of each
+// key, tagged with the transaction that wrote it (xmin) and the one that deleted
+// it (xmax). Versions a newer write replaced are kept in memory, in a chain per
+// key, for as long as some snapshot may still need them. A reader takes the
+// newest version its snapshot sees: the B-tree entry if it can, otherwise the
+// first one down the chain.
+//
+// A writer pushes the version it replaces onto the chain before it overwrites
+// the entry, so a reader that finds an entry too new for it always finds the
+// older version in the chain. Writers to the same key are serialized by a lock
+// striped over key hashes, and a writer that would replace a version its snapshot
+// does not see, written by another transaction that did not roll back, fails
+// with a write conflict instead of overwriting it.
+//
+// Each write also appends to its transaction's undo log what the entry and the
+// chain looked like before, so rolling back puts every key back as it was. The
+// entry is logged to the WAL too, before any page changes, so recovery can put
+// back the keys of a transaction that never finished without the chains, which
+// do not survive a restart.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::btree::{BTree, BTreeCursor};
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::error::{DatabaseError, Result};
+use crate::storage::key::Key;
+use crate::storage::transaction::{Snapshot, Transaction, TransactionId, VersionId, FROZEN_TXN_ID};
+use crate::storage::value::Value;
+
+const WRITE_STRIPES: usize = 64;
+
+#[derive(Debug, Clone)]
+struct OldVersion {
+    version: VersionId,
+    value: Value,
+}
+
+/// What one write changed, so it can be put back.
+pub struct UndoRecord {
+    key: Key,
+    // The B-tree entry before the write
+    entry: Option<(VersionId, Value)>,
+    chain: ChainChange,
+}
+
+enum ChainChange {
+    None,
+    Pushed,
+    Replaced(OldVersion),
+    Popped(OldVersion),
+}
+
+/// Logs the B-tree entry a write by `txn` to the encoded `key` replaces.
+fn log_undo(txn: &Transaction, key: &[u8], entry: &Option<(VersionId, Value)>) -> Result<()> {
+    txn.log_undo(
+        key,
+        entry
+            .as_ref()
+            .map(|(version, value)| encode_entry(*version, value)),
+    )
+}
+
+/// Encodes a B-tree entry for an `Undo` log record: xmin, xmax, then the value.
+fn encode_entry(version: VersionId, value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(&version.xmin().0.to_le_bytes());
+    buffer.extend_from_slice(&version.xmax().map_or(0, |xmax| xmax.0).to_le_bytes());
+    buffer.extend(value.serialize());
+    buffer
+}
+
+fn decode_entry(buffer: &[u8]) -> Result<(VersionId, Value)> {
+    let invalid = || DatabaseError::InvalidData("Invalid undo entry in the log".to_string());
+    if buffer.len() < 16 {
+        return Err(invalid());
+    }
+    let xmin = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
+    let xmax = u64::from_le_bytes(buffer[8..16].try_into().unwrap());
+    let mut version = VersionId::new(TransactionId(xmin));
+    if xmax != 0 {
+        version = version.deleted_by(TransactionId(xmax));
+    }
+    let (value, _) = Value::deserialize(&buffer[16..]).map_err(|_| invalid())?;
+    Ok((version, value))
+}
+
+/// Puts back the entry a write by `txn_id` to the encoded `key` replaced, as
+/// logged in an `Undo` record, if the key still holds a version that
+/// transaction wrote or deleted. For recovery, which has no chains to revert:
+/// a transaction that never finished keeps others from writing its keys, so a
+/// key that no longer holds its write was undone already. Returns whether the
+/// entry was put back.
+pub fn restore_logged_entry(
+    txn_id: TransactionId,
+    key: &[u8],
+    entry: Option<&[u8]>,
+    btree: &BTree,
+    buffer_pool: &BufferPool,
+) -> Result<bool> {
+    let key = Key::decode(key)?;
+    let Some((current, _)) = btree.search_version(key.clone(), buffer_pool)? else {
+        return Ok(false);
+    };
+    if current.xmin() != txn_id && current.xmax() != Some(txn_id) {
+        return Ok(false);
+    }
+    match entry {
+        Some(entry) => {
+            let (version, value) = decode_entry(entry)?;
+            btree.insert_version(key, version, value, buffer_pool)?;
+        }
+        None => btree.delete(key, buffer_pool)?,
+    }
+    Ok(true)
+}
+
+/// Versions replaced in the B-tree that snapshots may still read.
+pub struct VersionStore {
+    // Newest first, keyed by encoded key
+    chains: Mutex<HashMap<Vec<u8>, Vec<OldVersion>>>,
+    stripes: Vec<Mutex<()>>,
+}
+
+impl Default for VersionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VersionStore {
+    pub fn new() -> Self {
+        Self {
+            chains: Mutex::new(HashMap::new()),
+            stripes: (0..WRITE_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    /// Number of replaced versions kept for snapshots.
+    pub fn len(&self) -> usize {
+        self.chains.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the value of `key` as `snapshot` sees it.
+    pub fn read(
+        &self,
+        snapshot: &Snapshot,
+        key: &Key,
+        btree: &BTree,
+        buffer_pool: &BufferPool,
+    ) -> Result<Option<Value>> {
+        Ok(match btree.search_version(key.clone(), buffer_pool)? {
+            Some((version, value)) => self.resolve(snapshot, &key.encode(), version, value),
+            None => None,
+        })
+    }
+
+    /// Picks the value `snapshot` sees, given the B-tree entry of a key.
+    fn resolve(
+        &self,
+        snapshot: &Snapshot,
+        key: &[u8],
+        version: VersionId,
+        value: Value,
+    ) -> Option<Value> {
+        if snapshot.sees(version.xmin()) {
+            return version.is_visible_to(snapshot).then_some(value);
+        }
+        let chains = self.chains.lock().unwrap();
+        let old = chains
+            .get(key)?
+            .iter()
+            .find(|old| snapshot.sees(old.version.xmin()))?;
+        old.version
+            .is_visible_to(snapshot)
+            .then(|| old.value.clone())
+    }
+
+    /// Writes `value` to `key` as `txn`, or deletes the key if `value` is `None`.
+    /// Fails with `WriteConflict` if another transaction changed the key since
+    /// `txn` took its snapshot, and deleting fails with `KeyNotFound` if the key
+    /// is not visible to `txn`. A write that fails changes nothing; one that
+    /// succeeds is added to `txn`'s undo log.
+    pub fn write(
+        &self,
+        txn: &mut Transaction,
+        key: &Key,
+        value: Option<Value>,
+        btree: &BTree,
+        buffer_pool: &BufferPool,
+    ) -> Result<()> {
+        let encoded = key.encode();
+        let _stripe = self.stripe(&encoded);
+        let snapshot = &txn.snapshot;
+        let me = txn.id();
+
+        // The version this write replaces. An entry written by a transaction that
+        // rolled back stands in for the one before it, kept at the chain's front.
+        let record = btree.search_version(key.clone(), buffer_pool)?;
+        let (base, from_chain) = match record.clone() {
+            Some((version, _)) if snapshot.is_aborted(version.xmin()) => (
+                self.chains
+                    .lock()
+                    .unwrap()
+                    .get(&encoded)
+                    .and_then(|chain| chain.first().cloned()),
+                true,
+            ),
+            Some((version, value)) => (Some(OldVersion { version, value }), false),
+            None => (None, false),
+        };
+
+        if let Some(base) = &base {
+            let writer = base
+                .version
+                .xmax()
+                .filter(|xmax| !snapshot.is_aborted(*xmax))
+                .unwrap_or(base.version.xmin());
+            if writer != me
+                && writer != FROZEN_TXN_ID
+                && !snapshot.is_aborted(writer)
+                && !snapshot.sees(writer)
+            {
+                return Err(DatabaseError::WriteConflict(key.clone()));
+            }
+        }
+
+        let Some(value) = value else {
+            let base = match base {
+                Some(base) if base.version.is_visible_to(snapshot) => base,
+                _ => return Err(DatabaseError::KeyNotFound(key.clone())),
+            };
+            log_undo(txn, &encoded, &record)?;
+            let deleted = base.version.deleted_by(me);
+            let chain = if from_chain {
+                btree.insert_version(key.clone(), deleted, base.value, buffer_pool)?;
+                match self.pop_front(&encoded) {
+                    Some(front) => ChainChange::Popped(front),
+                    None => ChainChange::None,
+                }
+            } else {
+                btree.set_version(key.clone(), deleted, buffer_pool)?;
+                ChainChange::None
+            };
+            txn.undo_log.push(UndoRecord {
+                key: key.clone(),
+                entry: record,
+                chain,
+            });
+            return Ok(());
+        };
+
+        log_undo(txn, &encoded, &record)?;
+        // Our own earlier version needs no keeping: no other snapshot sees it
+        let chain = match base {
+            Some(base) if base.version.xmin() != me => {
+                // A deletion by a live or committed transaction stays recorded
+                let replaced_by = base
+                    .version
+                    .xmax()
+                    .filter(|xmax| !snapshot.is_aborted(*xmax))
+                    .unwrap_or(me);
+                let old = OldVersion {
+                    version: base.version.deleted_by(replaced_by),
+                    value: base.value,
+                };
+                let mut chains = self.chains.lock().unwrap();
+                let chain = chains.entry(encoded.clone()).or_default();
+                if from_chain {
+                    ChainChange::Replaced(std::mem::replace(&mut chain[0], old))
+                } else {
+                    chain.insert(0, old);
+                    ChainChange::Pushed
+                }
+            }
+            _ => ChainChange::None,
+        };
+
+        if let Err(e) = btree.insert_version(key.clone(), VersionId::new(me), value, buffer_pool) {
+            self.revert_chain(&encoded, chain);
+            return Err(e);
+        }
+        txn.undo_log.push(UndoRecord {
+            key: key.clone(),
+            entry: record,
+            chain,
+        });
+        Ok(())
+    }
+
+    /// Bulk loads `entries` into the empty `btree` as `txn`, stamped like any of
+    /// its writes so older snapshots miss them. Each key's undo is logged before
+    /// the load and recorded once it succeeds, so rolling back or recovery
+    /// removes the rows again. Returns the number of entries loaded.
+    pub fn bulk_load(
+        &self,
+        txn: &mut Transaction,
+        entries: impl IntoIterator<Item = (Key, Value)>,
+        btree: &BTree,
+        buffer_pool: &BufferPool,
+    ) -> Result<usize> {
+        let entries: Vec<_> = entries.into_iter().collect();
+        for (key, _) in &entries {
+            txn.log_undo(&key.encode(), None)?;
+        }
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.clone()).collect();
+        let count = btree.bulk_load(entries, VersionId::new(txn.id()), buffer_pool)?;
+        txn.undo_log.extend(keys.into_iter().map(|key| UndoRecord {
+            key,
+            entry: None,
+            chain: ChainChange::None,
+        }));
+        Ok(count)
+    }
+
+    /// Puts back every key `txn` wrote after its undo log was `to` records long,
+    /// newest write first, leaving the entries and chains as they were then.
+    /// Records that could not be undone stay in the undo log.
+    pub fn undo(
+        &self,
+        txn: &mut Transaction,
+        to: usize,
+        btree: &BTree,
+        buffer_pool: &BufferPool,
+    ) -> Result<()> {
+        while txn.undo_log.len() > to {
+            let record = txn.undo_log.pop().unwrap();
+            let encoded = record.key.encode();
+            let _stripe = self.stripe(&encoded);
+            let restored = match &record.entry {
+                Some((version, value)) => {
+                    btree.insert_version(record.key.clone(), *version, value.clone(), buffer_pool)
+                }
+                None => btree.delete(record.key.clone(), buffer_pool),
+            };
+            if let Err(e) = restored {
+                txn.undo_log.push(record);
+                return Err(e);
+            }
+            self.revert_chain(&encoded, record.chain);
+        }
+        Ok(())
+    }
+
+    fn revert_chain(&self, key: &[u8], change: ChainChange) {
+        match change {
+            ChainChange::None => {}
+            ChainChange::Pushed => {
+                self.pop_front(key);
+            }
+            ChainChange::Replaced(old) => {
+                if let Some(front) = self
+                    .chains
+                    .lock()
+                    .unwrap()
+                    .get_mut(key)
+                    .and_then(|chain| chain.first_mut())
+                {
+                    *front = old;
+                }
+            }
+            ChainChange::Popped(old) => {
+                self.chains
+                    .lock()
+                    .unwrap()
+                    .entry(key.to_vec())
+                    .or_default()
+                    .insert(0, old);
+            }
+        }
+    }
+
+    /// Returns a cursor that reads `cursor`'s entries as `snapshot` sees them.
+    pub fn cursor<'a>(&'a self, cursor: BTreeCursor<'a>, snapshot: Snapshot) -> SnapshotCursor<'a> {
+        SnapshotCursor {
+            cursor,
+            versions: self,
+            snapshot,
+        }
+    }
+
+    /// Drops the versions no snapshot can see any more. Transactions before
+    /// `horizon` have finished, and every snapshot sees those that did not roll
+    /// back, which `is_aborted` tells apart. Entries deleted by such a
+    /// transaction are removed from the B-tree, and entries written by a
+    /// transaction that rolled back are replaced by the version before them.
+    /// Returns the number of versions removed.
+    pub fn collect_garbage(
+        &self,
+        horizon: TransactionId,
+        is_aborted: impl Fn(TransactionId) -> bool,
+        btree: &BTree,
+        buffer_pool: &BufferPool,
+    ) -> Result<usize> {
+        let settled = |txn_id: TransactionId| txn_id < horizon && !is_aborted(txn_id);
+        let mut removed = 0;
+        let mut restored = HashSet::new();
+
+        // Entries to remove or restore. They are collected first, since the
+        // cursor cannot be used while the tree changes underneath it.
+        let mut candidates = Vec::new();
+        let mut cursor = btree.cursor(buffer_pool);
+        while let Some(entry) = cursor.next_version() {
+            let (key, version, _) = entry?;
+            if version.xmax().is_some_and(settled) || is_aborted(version.xmin()) {
+                candidates.push(key);
+            }
+        }
+
+        for key in candidates {
+            let encoded = key.encode();
+            let _stripe = self.stripe(&encoded);
+            let Some((version, _)) = btree.search_version(key.clone(), buffer_pool)? else {
+                continue;
+            };
+            if version.xmax().is_some_and(settled) {
+                btree.delete(key, buffer_pool)?;
+                let chain = self.chains.lock().unwrap().remove(&encoded);
+                removed += 1 + chain.map_or(0, |chain| chain.len());
+            } else if is_aborted(version.xmin()) {
+                let front = self
+                    .chains
+                    .lock()
+                    .unwrap()
+                    .get(&encoded)
+                    .and_then(|chain| chain.first().cloned());
+                match front {
+                    None => btree.delete(key, buffer_pool)?,
+                    // The front stays in the chain for readers that copied the
+                    // entry before it was restored, until the next pass drops it
+                    Some(front) => {
+                        let version = match front.version.xmax() {
+                            Some(xmax) if is_aborted(xmax) => VersionId::new(front.version.xmin()),
+                            _ => front.version,
+                        };
+                        btree.insert_version(key, version, front.value, buffer_pool)?;
+                        restored.insert(encoded);
+                    }
+                }
+                removed += 1;
+            }
+        }
+
+        // Versions older than one every snapshot sees are never read again
+        let keys: Vec<Vec<u8>> = self.chains.lock().unwrap().keys().cloned().collect();
+        for encoded in keys.into_iter().filter(|key| !restored.contains(key)) {
+            let _stripe = self.stripe(&encoded);
+            let record = btree.search_version(Key::decode(&encoded)?, buffer_pool)?;
+            let mut chains = self.chains.lock().unwrap();
+            let Some(chain) = chains.get_mut(&encoded) else {
+                continue;
+            };
+            let keep = match record {
+                Some((version, _)) if settled(version.xmin()) => 0,
+                Some(_) => chain
+                    .iter()
+                    .position(|old| settled(old.version.xmin()))
+                    .map_or(chain.len(), |idx| idx + 1),
+                None => 0,
+            };
+            removed += chain.len() - keep;
+            chain.truncate(keep);
+            if chain.is_empty() {
+                chains.remove(&encoded);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn pop_front(&self, key: &[u8]) -> Option<OldVersion> {
+        let mut chains = self.chains.lock().unwrap();
+        let chain = chains.get_mut(key)?;
+        let front = (!chain.is_empty()).then(|| chain.remove(0));
+        if chain.is_empty() {
+            chains.remove(key);
+        }
+        front
+    }
+
+    fn stripe(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.stripes[hasher.finish() as usize % self.stripes.len()]
+            .lock()
+            .unwrap()
+    }
+}
+
+/// A B-tree cursor that skips and substitutes entries so it returns what its
+/// snapshot sees. The snapshot stays registered while the cursor is open, so the
+/// versions it reads are not collected meanwhile.
+pub struct SnapshotCursor<'a> {
+    cursor: BTreeCursor<'a>,
+    versions: &'a VersionStore,
+    snapshot: Snapshot,
+}
+
+impl SnapshotCursor<'_> {
+    /// Moves the cursor just before the first entry at or after `key`.
+    pub fn seek(&mut self, key: impl Into<Key>) -> Result<()> {
+        self.cursor.seek(key)
+    }
+
+    /// Moves back over the previous visible entry and returns it.
+    pub fn prev(&mut self) -> Option<Result<(Key, Value)>> {
+        loop {
+            match self.cursor.prev_version()? {
+                Ok((key, version, value)) => {
+                    if let Some(value) = self.visible(&key, version, value) {
+                        return Some(Ok((key, value)));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn visible(&self, key: &Key, version: VersionId, value: Value) -> Option<Value> {
+        self.versions
+            .resolve(&self.snapshot, &key.encode(), version, value)
+    }
+}
+
+impl Iterator for SnapshotCursor<'_> {
+    type Item = Result<(Key, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.cursor.next_version()? {
+                Ok((key, version, value)) => {
+                    if let Some(value) = self.visible(&key, version, value) {
+                        return Some(Ok((key, value)));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
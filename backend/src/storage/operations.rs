@@ -1,32 +1,37 @@
-use std::sync::{Arc, Mutex};
+use std::ops::RangeBounds;
 
-use crate::btree::BTree;
-use crate::storage::error::DatabaseError;
-use crate::storage::{buffer_pool::BufferPool, value::Value};
+use crate::btree::{BTree, CheckReport};
+use crate::storage::error::{self, DatabaseError};
+use crate::storage::{buffer_pool::BufferPool, key::Key, value::Value};
 
-use super::transaction::Transaction;
-use super::wal::LogRecord;
+use super::mvcc::{SnapshotCursor, VersionStore};
+use super::transaction::{Snapshot, Transaction, TransactionId};
+
+/// Runs a B-tree mutation with `txn_id` as the calling thread's current
+/// transaction, so every page it writes is logged under that transaction.
+fn logged<T>(
+    txn_id: TransactionId,
+    buffer_pool: &BufferPool,
+    op: impl FnOnce(&BufferPool) -> error::Result<T>,
+) -> error::Result<T> {
+    buffer_pool.set_current_txn(Some(txn_id.0));
+    let result = op(buffer_pool);
+    buffer_pool.set_current_txn(None);
+    result
+}
 
 pub fn insert(
     txn: &mut Transaction,
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    key: &Key,
     value: &Value,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut btree = btree.lock().unwrap();
-    match btree.insert(key, value.clone(), buffer_pool) {
-        Ok(()) => {
-            if let Some(wal) = &txn.wal {
-                wal.lock().unwrap().log(LogRecord::Write {
-                    txn_id: txn.id.0,
-                    page_id: btree.root_page_id(),
-                    offset: 0,
-                    data: value.serialize(),
-                })?;
-            }
-            Ok(())
-        }
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.write(txn, key, Some(value.clone()), btree, bp)
+    }) {
+        Ok(()) => Ok(()),
         Err(e) => {
             eprintln!("Error inserting key {}: {}", key, e);
             Err(Box::new(e))
@@ -36,23 +41,15 @@ pub fn insert(
 
 pub fn delete(
     txn: &mut Transaction,
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    key: &Key,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut btree = btree.lock().unwrap();
-    match btree.delete(key, buffer_pool) {
-        Ok(()) => {
-            if let Some(wal) = &txn.wal {
-                wal.lock().unwrap().log(LogRecord::Write {
-                    txn_id: txn.id.0,
-                    page_id: btree.root_page_id(),
-                    offset: 0,
-                    data: vec![],
-                })?;
-            }
-            Ok(())
-        }
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.write(txn, key, None, btree, bp)
+    }) {
+        Ok(()) => Ok(()),
         Err(e) => {
             eprintln!("Error deleting key {}: {}", key, e);
             Err(Box::new(e))
@@ -60,13 +57,73 @@ pub fn delete(
     }
 }
 
+/// Undoes every write of `txn` and ends it as rolled back. The undo is logged
+/// under `txn` like its writes, so recovery replays it too. If undoing fails,
+/// the transaction still ends, its remaining writes hidden from readers.
+pub fn rollback(
+    txn: &mut Transaction,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let undone = logged(txn.id, buffer_pool, |bp| versions.undo(txn, 0, btree, bp));
+    txn.rollback()?;
+    match undone {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            eprintln!("Error undoing transaction {}: {}", txn.id.0, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Undoes the writes `txn` made since savepoint `name`, keeping the transaction
+/// open. If undoing fails, the writes not yet undone are kept too.
+pub fn rollback_to_savepoint(
+    txn: &mut Transaction,
+    name: &str,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let position = txn.rollback_to_savepoint(name)?;
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.undo(txn, position, btree, bp)
+    }) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            eprintln!("Error rolling back to savepoint {}: {}", name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+pub fn bulk_load(
+    txn: &mut Transaction,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    entries: impl IntoIterator<Item = (Key, Value)>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.bulk_load(txn, entries, btree, bp)
+    }) {
+        Ok(count) => Ok(count),
+        Err(e) => {
+            eprintln!("Error bulk loading: {}", e);
+            Err(Box::new(e))
+        }
+    }
+}
+
 pub fn get(
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    snapshot: &Snapshot,
+    key: &Key,
 ) -> Result<Option<Value>, Box<dyn std::error::Error>> {
-    let btree = btree.lock().unwrap();
-    match btree.search(key, buffer_pool) {
+    match versions.read(snapshot, key, btree, buffer_pool) {
         Ok(result) => Ok(result),
         Err(e) => {
             eprintln!("Error searching for key {}: {}", key, e);
@@ -77,24 +134,16 @@ pub fn get(
 
 pub fn update(
     txn: &mut Transaction,
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    key: &Key,
     value: &Value,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut btree = btree.lock().unwrap();
-    match btree.update(key, value.clone(), buffer_pool) {
-        Ok(()) => {
-            if let Some(wal) = &txn.wal {
-                wal.lock().unwrap().log(LogRecord::Write {
-                    txn_id: txn.id.0,
-                    page_id: btree.root_page_id(),
-                    offset: 0,
-                    data: value.serialize(),
-                })?;
-            }
-            Ok(())
-        }
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.write(txn, key, Some(value.clone()), btree, bp)
+    }) {
+        Ok(()) => Ok(()),
         Err(e) => {
             eprintln!("Error updating key {}: {}", key, e);
             Err(Box::new(e))
@@ -103,65 +152,112 @@ pub fn update(
 }
 
 pub fn all(
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-) -> Result<Vec<(i32, Value)>, Box<dyn std::error::Error>> {
-    let btree = btree.lock().unwrap();
-    btree.all(buffer_pool).map_err(|e| e.into())
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    snapshot: Snapshot,
+) -> Result<Vec<(Key, Value)>, Box<dyn std::error::Error>> {
+    cursor(btree, versions, buffer_pool, snapshot, ..)
+        .collect::<error::Result<_>>()
+        .map_err(|e| e.into())
+}
+
+/// Opens a cursor over the keys in `range` as `snapshot` sees them. It takes no
+/// locks between steps, yet writes made while it is open are never seen.
+pub fn cursor<'a, R: RangeBounds<Key>>(
+    btree: &BTree,
+    versions: &'a VersionStore,
+    buffer_pool: &'a BufferPool,
+    snapshot: Snapshot,
+    range: R,
+) -> SnapshotCursor<'a> {
+    versions.cursor(btree.range_cursor(range, buffer_pool), snapshot)
+}
+
+/// Removes the versions no snapshot can see any more. Returns how many were
+/// removed.
+pub fn vacuum(
+    txn: &mut Transaction,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    horizon: TransactionId,
+    is_aborted: impl Fn(TransactionId) -> bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    match logged(txn.id, buffer_pool, |bp| {
+        versions.collect_garbage(horizon, is_aborted, btree, bp)
+    }) {
+        Ok(removed) => Ok(removed),
+        Err(e) => {
+            eprintln!("Error collecting old versions: {}", e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+pub fn check(
+    btree: &BTree,
+    buffer_pool: &BufferPool,
+) -> Result<CheckReport, Box<dyn std::error::Error>> {
+    btree.check(buffer_pool).map_err(|e| e.into())
 }
 
 pub fn strlen(
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    snapshot: &Snapshot,
+    key: &Key,
 ) -> Result<Option<usize>, Box<dyn std::error::Error>> {
-    let btree = btree.lock().unwrap();
-    match btree.search(key, buffer_pool) {
+    match versions.read(snapshot, key, btree, buffer_pool) {
         Ok(Some(value)) => Ok(Some(value.to_string().len())),
-        _ => Err(DatabaseError::KeyNotFound(key).into()),
+        _ => Err(DatabaseError::KeyNotFound(key.clone()).into()),
     }
 }
 
 pub fn strcat(
     txn: &mut Transaction,
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    key: &Key,
     value: &Value,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let btree_unlocked = btree.lock().unwrap();
-    match btree_unlocked.search(key, buffer_pool) {
+    let current = versions.read(&txn.snapshot, key, btree, buffer_pool);
+    match current {
         Ok(Some(old_value)) => {
             let concatenated = old_value.add(value)?;
-            update(txn, btree, buffer_pool, key, &concatenated)?;
+            update(txn, btree, versions, buffer_pool, key, &concatenated)?;
             Ok(())
         }
-        _ => Err(DatabaseError::KeyNotFound(key).into()),
+        _ => Err(DatabaseError::KeyNotFound(key.clone()).into()),
     }
 }
 
 pub fn substr(
     txn: &mut Transaction,
-    btree: &Arc<Mutex<BTree>>,
-    buffer_pool: &mut BufferPool,
-    key: i32,
+    btree: &BTree,
+    versions: &VersionStore,
+    buffer_pool: &BufferPool,
+    key: &Key,
     start: usize,
     length: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let btree_unlocked = btree.lock().unwrap();
-    match btree_unlocked.search(key, buffer_pool) {
+    let current = versions.read(&txn.snapshot, key, btree, buffer_pool);
+    match current {
         Ok(Some(value)) => {
             let substr = value.to_string();
             let substr = substr.get(start..start + length).unwrap_or("");
             update(
                 txn,
                 btree,
+                versions,
                 buffer_pool,
                 key,
                 &Value::String(substr.to_string()),
             )?;
             Ok(())
         }
-        _ => Err(DatabaseError::KeyNotFound(key).into()),
+        _ => Err(DatabaseError::KeyNotFound(key.clone()).into()),
     }
 }
@@ -1,12 +1,20 @@
 use super::error::{DatabaseError, Result};
+use crc32fast::Hasher;
 
 pub const PAGE_SIZE: usize = 4096; // Standard page size (4KB)
-const PAGE_HEADER_SIZE: usize = 8; // 4 bytes for page_id, 4 bytes for record count
+                                   // 4 bytes page_id, 4 bytes record count, 8 bytes LSN, 4 bytes checksum
+pub const PAGE_HEADER_SIZE: usize = 20;
+const CHECKSUM_OFFSET: usize = 16;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PageHeader {
     pub page_id: u32,
     pub record_count: u32,
+    /// Sequence number of the last log record that changed the page.
+    pub lsn: u64,
+    /// CRC32 of the page image on disk, computed with this field zeroed. It is
+    /// recomputed every time the page is written.
+    pub checksum: u32,
 }
 
 impl PageHeader {
@@ -14,6 +22,8 @@ impl PageHeader {
         Self {
             page_id,
             record_count: 0,
+            lsn: 0,
+            checksum: 0,
         }
     }
 
@@ -24,6 +34,8 @@ impl PageHeader {
         Ok(Self {
             page_id: u32::from_le_bytes(buffer[0..4].try_into().unwrap()),
             record_count: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+            lsn: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+            checksum: u32::from_le_bytes(buffer[16..20].try_into().unwrap()),
         })
     }
 
@@ -31,18 +43,13 @@ impl PageHeader {
         let mut buffer = Vec::with_capacity(PAGE_HEADER_SIZE);
         buffer.extend_from_slice(&self.page_id.to_le_bytes());
         buffer.extend_from_slice(&self.record_count.to_le_bytes());
+        buffer.extend_from_slice(&self.lsn.to_le_bytes());
+        buffer.extend_from_slice(&self.checksum.to_le_bytes());
         buffer
     }
-
-    pub fn clone(&self) -> Self {
-        Self {
-            page_id: self.page_id,
-            record_count: self.record_count,
-        }
-    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Page {
     pub header: PageHeader,
     pub data: Vec<u8>,
@@ -68,10 +75,26 @@ impl Page {
         self.is_dirty = true;
     }
 
+    pub fn mark_clean(&mut self) {
+        self.is_dirty = false;
+    }
+
+    pub fn lsn(&self) -> u64 {
+        self.header.lsn
+    }
+
+    pub fn set_lsn(&mut self, lsn: u64) {
+        self.header.lsn = lsn;
+    }
+
     pub fn pin(&mut self) {
         self.pin_count += 1;
     }
 
+    pub fn is_pinned(&self) -> bool {
+        self.pin_count > 0
+    }
+
     pub fn unpin(&mut self) -> Result<()> {
         if self.pin_count == 0 {
             return Err(DatabaseError::InvalidOperation(
@@ -82,21 +105,42 @@ impl Page {
         Ok(())
     }
 
-    pub fn clone(&self) -> Self {
-        Self {
-            header: self.header.clone(),
-            data: self.data.clone(),
-            is_dirty: self.is_dirty,
-            pin_count: self.pin_count,
-        }
-    }
-
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer = self.header.serialize();
         buffer.extend_from_slice(&self.data);
         buffer
     }
 
+    /// Serializes the page as it is stored on disk: padded to `PAGE_SIZE`, with a
+    /// fresh checksum.
+    pub fn serialize_with_checksum(&self) -> Vec<u8> {
+        let mut buffer = self.serialize();
+        buffer.resize(PAGE_SIZE, 0);
+        buffer[CHECKSUM_OFFSET..PAGE_HEADER_SIZE].fill(0);
+        let checksum = checksum(&buffer);
+        buffer[CHECKSUM_OFFSET..PAGE_HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        buffer
+    }
+
+    /// Checks the checksum of a page image read from disk. An all-zero image is a
+    /// page that was allocated but never written, and is accepted as is.
+    pub fn verify_checksum(buffer: &[u8]) -> bool {
+        if buffer.len() < PAGE_HEADER_SIZE {
+            return false;
+        }
+        if buffer.iter().all(|&b| b == 0) {
+            return true;
+        }
+        let stored = u32::from_le_bytes(
+            buffer[CHECKSUM_OFFSET..PAGE_HEADER_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+        let mut copy = buffer.to_vec();
+        copy[CHECKSUM_OFFSET..PAGE_HEADER_SIZE].fill(0);
+        checksum(&copy) == stored
+    }
+
     pub fn deserialize(buffer: &[u8]) -> Result<Self> {
         let header = PageHeader::deserialize(&buffer[0..PAGE_HEADER_SIZE])?;
         let data = buffer[PAGE_HEADER_SIZE..].to_vec();
@@ -113,3 +157,9 @@ impl Page {
         Ok(())
     }
 }
+
+fn checksum(buffer: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(buffer);
+    hasher.finalize()
+}
@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use log::info;
+
+use super::buffer_pool::BufferPool;
+use super::disk_manager::HEADER_PAGE_ID;
+use super::error::{DatabaseError, Result};
+use super::mvcc;
+use super::page::{Page, PAGE_SIZE};
+use super::transaction::TransactionId;
+use super::wal::{LogRecord, WriteAheadLog};
+use crate::btree::BTree;
+
+/// Summary of a recovery pass.
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// Number of page writes replayed from the log.
+    pub redone: usize,
+    /// Number of page writes and key writes rolled back for transactions that
+    /// never finished.
+    pub undone: usize,
+    /// Transactions that were still in flight when the log ended.
+    pub losers: Vec<u64>,
+    /// Highest transaction ID found in the log.
+    pub max_txn_id: u64,
+}
+
+/// Brings the data file back to a consistent state using the write-ahead log.
+///
+/// Recovery repeats history: every intact `Write` record after the last checkpoint
+/// is redone in log order, then the writes of transactions without a `Commit` or
+/// `Rollback` record are undone newest-first. A transaction whose pages no one
+/// else wrote after it is undone byte for byte from its `Write` records. Any
+/// other is undone key by key from its `Undo` records, through the B-tree, since
+/// copying its before images back would erase the changes others committed to
+/// the same pages since. Each undo is logged, as a compensating `Write` or the
+/// page writes of the B-tree change, and followed by a `Rollback`, so running
+/// recovery again is a no-op. `buffer_pool` must log to `wal`.
+pub fn recover(wal: &Mutex<WriteAheadLog>, buffer_pool: &BufferPool) -> Result<RecoveryReport> {
+    let records = wal.lock().unwrap().read_records()?;
+    let mut report = RecoveryReport::default();
+
+    // Analysis: find transactions that reached an end record, and the last
+    // checkpoint. Pages were flushed at the checkpoint, so redo starts there.
+    let mut seen = Vec::new();
+    let mut finished = HashSet::new();
+    let mut redo_start = 0;
+    for (index, (_, record)) in records.iter().enumerate() {
+        match record {
+            LogRecord::Commit(txn_id) | LogRecord::Rollback(txn_id) => {
+                finished.insert(*txn_id);
+            }
+            LogRecord::Begin(txn_id)
+            | LogRecord::Write { txn_id, .. }
+            | LogRecord::Undo { txn_id, .. } => {
+                if !seen.contains(txn_id) {
+                    seen.push(*txn_id);
+                }
+            }
+            LogRecord::Checkpoint { active_txns } => {
+                redo_start = index;
+                for txn_id in active_txns {
+                    if !seen.contains(txn_id) {
+                        seen.push(*txn_id);
+                    }
+                }
+            }
+        }
+        if let Some(txn_id) = record.txn_id() {
+            report.max_txn_id = report.max_txn_id.max(txn_id);
+        }
+    }
+    report.losers = seen
+        .into_iter()
+        .filter(|txn_id| !finished.contains(txn_id))
+        .collect();
+
+    // Redo: replay every page write since the checkpoint, including those of losers.
+    for (_, record) in &records[redo_start..] {
+        if let LogRecord::Write {
+            page_id,
+            offset,
+            after,
+            ..
+        } = record
+        {
+            apply_image(buffer_pool, *page_id, *offset, after)?;
+            report.redone += 1;
+        }
+    }
+
+    // Undo: roll back losers newest-first, logging compensation records. Pages
+    // are put back first, since a key-by-key undo may change pages a loser
+    // undone in place wrote before.
+    let (in_place, by_key): (Vec<u64>, Vec<u64>) = report
+        .losers
+        .iter()
+        .partition(|txn_id| no_later_writers(&records, **txn_id));
+    for (_, record) in records.iter().rev() {
+        if let LogRecord::Write {
+            txn_id,
+            page_id,
+            offset,
+            before,
+            after,
+        } = record
+        {
+            if !in_place.contains(txn_id) {
+                continue;
+            }
+            wal.lock().unwrap().log(LogRecord::Write {
+                txn_id: *txn_id,
+                page_id: *page_id,
+                offset: *offset,
+                before: after.clone(),
+                after: before.clone(),
+            })?;
+            apply_image(buffer_pool, *page_id, *offset, before)?;
+            report.undone += 1;
+        }
+    }
+    report.undone += undo_by_key(&records, &by_key, buffer_pool)?;
+    for txn_id in &report.losers {
+        wal.lock().unwrap().log(LogRecord::Rollback(*txn_id))?;
+    }
+
+    buffer_pool.flush()?;
+
+    if !records.is_empty() {
+        info!(
+            "Recovery replayed {} page writes and undid {} for {} unfinished transactions",
+            report.redone,
+            report.undone,
+            report.losers.len()
+        );
+    }
+
+    Ok(report)
+}
+
+/// Puts back, newest first, the entries the `Undo` records of `losers` logged,
+/// and returns how many keys changed.
+fn undo_by_key(
+    records: &[(u64, LogRecord)],
+    losers: &[u64],
+    buffer_pool: &BufferPool,
+) -> Result<usize> {
+    let Some(root_page_id) = buffer_pool.root_page_id().filter(|_| !losers.is_empty()) else {
+        return Ok(0);
+    };
+    let btree = BTree::new(root_page_id);
+    let mut undone = 0;
+    for (_, record) in records.iter().rev() {
+        let LogRecord::Undo { txn_id, key, entry } = record else {
+            continue;
+        };
+        if !losers.contains(txn_id) {
+            continue;
+        }
+        // Logged under the loser, like a rollback while it was running
+        buffer_pool.set_current_txn(Some(*txn_id));
+        let restored = mvcc::restore_logged_entry(
+            TransactionId(*txn_id),
+            key,
+            entry.as_deref(),
+            &btree,
+            buffer_pool,
+        );
+        buffer_pool.set_current_txn(None);
+        if restored? {
+            undone += 1;
+        }
+    }
+    Ok(undone)
+}
+
+/// Whether no other transaction wrote a page `txn_id` wrote after it first did,
+/// so putting its before images back cannot erase anyone else's change.
+fn no_later_writers(records: &[(u64, LogRecord)], txn_id: u64) -> bool {
+    let mut written = HashSet::new();
+    for (_, record) in records {
+        if let LogRecord::Write {
+            txn_id: writer,
+            page_id,
+            ..
+        } = record
+        {
+            if *writer == txn_id {
+                written.insert(*page_id);
+            } else if written.contains(page_id) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Number of page writes after the last checkpoint in `records`: the writes
+/// `recover` would replay, which the data file may not reflect yet.
+pub fn pending_writes(records: &[(u64, LogRecord)]) -> usize {
+    let start = records
+        .iter()
+        .rposition(|(_, record)| matches!(record, LogRecord::Checkpoint { .. }))
+        .unwrap_or(0);
+    records[start..]
+        .iter()
+        .filter(|(_, record)| matches!(record, LogRecord::Write { .. }))
+        .count()
+}
+
+/// Overwrites `bytes.len()` bytes of the serialized page at `offset`.
+fn apply_image(buffer_pool: &BufferPool, page_id: u32, offset: u16, bytes: &[u8]) -> Result<()> {
+    if page_id == HEADER_PAGE_ID {
+        let mut image = buffer_pool.header().serialize();
+        splice(&mut image, page_id, offset, bytes)?;
+        return buffer_pool.write_header_image(&image);
+    }
+
+    let mut image = match buffer_pool.get_page(page_id) {
+        Ok(frame) => frame.page().serialize(),
+        // The file was never extended to the page before the crash. A torn or
+        // otherwise corrupted page fails recovery instead: a delta cannot rebuild
+        // the rest of it, and writing it back would hide the damage.
+        Err(DatabaseError::PageNotFound(_)) => Page::new(page_id).serialize(),
+        Err(e) => return Err(e),
+    };
+    splice(&mut image, page_id, offset, bytes)?;
+
+    let mut page = Page::deserialize(&image)?;
+    page.header.page_id = page_id;
+    buffer_pool.write_page(page_id, page)
+}
+
+fn splice(image: &mut Vec<u8>, page_id: u32, offset: u16, bytes: &[u8]) -> Result<()> {
+    image.resize(PAGE_SIZE, 0);
+
+    let start = offset as usize;
+    let end = start + bytes.len();
+    if end > image.len() {
+        return Err(DatabaseError::InvalidData(format!(
+            "Log record for page {} exceeds the page size",
+            page_id
+        )));
+    }
+    image[start..end].copy_from_slice(bytes);
+    Ok(())
+}
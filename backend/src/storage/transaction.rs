@@ -1,11 +1,23 @@
-use super::error::Result;
+use super::error::{DatabaseError, Result};
+use super::key::Key;
+use super::lock_manager::{LockManager, LockMode};
+use super::mvcc::UndoRecord;
 use crate::storage::wal::{LogRecord, WriteAheadLog};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TransactionId(pub u64);
 
+/// Creator of rows written outside any transaction, such as by a bulk load.
+/// Every snapshot sees its writes.
+pub const FROZEN_TXN_ID: TransactionId = TransactionId(0);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VersionId {
     xmin: TransactionId, // Transaction that created this version
@@ -20,10 +32,158 @@ impl VersionId {
         }
     }
 
-    pub fn is_visible_to(&self, txn_id: TransactionId) -> bool {
-        // Version created by an earlier transaction and not deleted
-        // or deleted by a later transaction
-        self.xmin < txn_id && (self.xmax.0 == 0 || self.xmax > txn_id)
+    /// A version no snapshot can be hidden from.
+    pub fn frozen() -> Self {
+        Self::new(FROZEN_TXN_ID)
+    }
+
+    pub fn xmin(&self) -> TransactionId {
+        self.xmin
+    }
+
+    pub fn xmax(&self) -> Option<TransactionId> {
+        Some(self.xmax).filter(|xmax| xmax.0 != 0)
+    }
+
+    /// The same version, deleted by `txn_id`.
+    pub fn deleted_by(self, txn_id: TransactionId) -> Self {
+        Self {
+            xmin: self.xmin,
+            xmax: txn_id,
+        }
+    }
+
+    /// Whether `snapshot` sees the transaction that created this version but not
+    /// one that deleted it.
+    pub fn is_visible_to(&self, snapshot: &Snapshot) -> bool {
+        snapshot.sees(self.xmin) && !self.xmax().is_some_and(|xmax| snapshot.sees(xmax))
+    }
+}
+
+/// How much a transaction sees of the transactions running beside it. Dirty
+/// reads are prevented at every level, since readers only see committed versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IsolationLevel {
+    /// Each statement reads what was committed when it started, and may write
+    /// over versions committed since the transaction began.
+    ReadCommitted,
+    /// Every statement reads from the snapshot taken at begin; writing a key
+    /// another transaction changed since then fails with `WriteConflict`.
+    #[default]
+    RepeatableRead,
+    /// Like `ReadCommitted`, but reads take shared key locks, scans shared range
+    /// locks and writes exclusive ones, held until the transaction ends (strict
+    /// two-phase locking). Only serializable transactions take locks, so the
+    /// level is serializable among them alone: a writer in another one can
+    /// still insert into a range it scanned. A write to a key read before is
+    /// checked against the snapshot it was read through, though, so such a
+    /// writer's changes to the key are not lost.
+    Serializable,
+}
+
+impl FromStr for IsolationLevel {
+    type Err = DatabaseError;
+
+    /// Parses `READ COMMITTED`, `REPEATABLE READ` or `SERIALIZABLE`, in any case.
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        match words.join(" ").to_uppercase().as_str() {
+            "READ COMMITTED" => Ok(IsolationLevel::ReadCommitted),
+            "REPEATABLE READ" => Ok(IsolationLevel::RepeatableRead),
+            "SERIALIZABLE" => Ok(IsolationLevel::Serializable),
+            _ => Err(DatabaseError::ParseError(format!(
+                "Unknown isolation level: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// The set of transactions whose writes a reader sees: those that committed
+/// before the snapshot was taken, and the reader's own. The snapshot stays
+/// registered with its `TransactionManager` until dropped or its transaction
+/// finishes, which keeps the versions it may need from being garbage collected.
+pub struct Snapshot {
+    txn_id: Option<TransactionId>,
+    // First transaction ID not yet handed out when the snapshot was taken
+    xmax: TransactionId,
+    // Transactions that had begun but not finished
+    active: Vec<TransactionId>,
+    aborted: Arc<RwLock<HashSet<TransactionId>>>,
+    registration: u64,
+    registry: Arc<SnapshotRegistry>,
+}
+
+/// Horizons of the snapshots in use, by registration number.
+#[derive(Default)]
+struct SnapshotRegistry {
+    next: AtomicU64,
+    horizons: Mutex<HashMap<u64, TransactionId>>,
+}
+
+impl SnapshotRegistry {
+    fn register(&self, horizon: TransactionId) -> u64 {
+        let registration = self.next.fetch_add(1, Ordering::SeqCst);
+        self.horizons.lock().unwrap().insert(registration, horizon);
+        registration
+    }
+}
+
+impl Snapshot {
+    /// Whether the writes of `txn_id` are visible in this snapshot.
+    pub fn sees(&self, txn_id: TransactionId) -> bool {
+        if txn_id == FROZEN_TXN_ID || Some(txn_id) == self.txn_id {
+            return true;
+        }
+        txn_id < self.xmax && !self.active.contains(&txn_id) && !self.is_aborted(txn_id)
+    }
+
+    pub fn is_aborted(&self, txn_id: TransactionId) -> bool {
+        self.aborted.read().unwrap().contains(&txn_id)
+    }
+
+    /// The transaction reading through this snapshot, if any.
+    pub fn txn_id(&self) -> Option<TransactionId> {
+        self.txn_id
+    }
+
+    fn release(&self) {
+        self.registry
+            .horizons
+            .lock()
+            .unwrap()
+            .remove(&self.registration);
+    }
+
+    /// Oldest transaction this snapshot may not see; everything before it is
+    /// either committed and visible or rolled back.
+    fn horizon(&self) -> TransactionId {
+        self.active
+            .iter()
+            .chain(&self.txn_id)
+            .copied()
+            .fold(self.xmax, TransactionId::min)
+    }
+}
+
+/// A clone sees the same transactions and is registered on its own, so it keeps
+/// its versions after the original is dropped.
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Self {
+            txn_id: self.txn_id,
+            xmax: self.xmax,
+            active: self.active.clone(),
+            aborted: Arc::clone(&self.aborted),
+            registration: self.registry.register(self.horizon()),
+            registry: Arc::clone(&self.registry),
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.release();
     }
 }
 
@@ -31,35 +191,173 @@ pub struct Transaction {
     pub id: TransactionId,
     pub is_active: bool,
     pub wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    pub snapshot: Snapshot,
+    pub isolation: IsolationLevel,
+    /// Writes made so far, oldest first, for `VersionStore::undo`.
+    pub undo_log: Vec<UndoRecord>,
+    // Savepoints in the order they were set, with the undo log length at each
+    savepoints: Vec<(String, usize)>,
+    // Snapshot each key was first read through, noted by serializable
+    // transactions to check their writes against
+    read_snapshots: HashMap<Vec<u8>, Arc<Snapshot>>,
+    // Ranges serializable transactions scanned, with the snapshot each was
+    // scanned through
+    read_ranges: Vec<(Bound<Key>, Bound<Key>, Arc<Snapshot>)>,
+    active_txns: Arc<Mutex<Vec<TransactionId>>>,
+    locks: Arc<LockManager>,
 }
 
 pub struct TransactionManager {
     next_txn_id: AtomicU64,
-    active_txns: Mutex<Vec<TransactionId>>,
+    // Also held while snapshots are taken, so a new transaction is never seen
+    // as finished by a snapshot taken while it begins
+    active_txns: Arc<Mutex<Vec<TransactionId>>>,
+    aborted_txns: Arc<RwLock<HashSet<TransactionId>>>,
+    snapshots: Arc<SnapshotRegistry>,
+    locks: Arc<LockManager>,
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TransactionManager {
     pub fn new() -> Self {
+        Self::with_next_txn_id(1)
+    }
+
+    /// Creates a manager that hands out IDs starting at `next_txn_id`, so IDs
+    /// stay unique across restarts that share a write-ahead log.
+    pub fn with_next_txn_id(next_txn_id: u64) -> Self {
         Self {
-            next_txn_id: AtomicU64::new(1),
-            active_txns: Mutex::new(Vec::new()),
+            next_txn_id: AtomicU64::new(next_txn_id),
+            active_txns: Arc::new(Mutex::new(Vec::new())),
+            aborted_txns: Arc::new(RwLock::new(HashSet::new())),
+            snapshots: Arc::new(SnapshotRegistry::default()),
+            locks: Arc::new(LockManager::default()),
         }
     }
 
+    /// Makes lock requests of this manager's transactions give up after
+    /// `timeout`.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.locks = Arc::new(LockManager::new(timeout));
+        self
+    }
+
+    /// Begins a transaction at the default isolation level and takes its
+    /// snapshot.
     pub fn begin_transaction(&self, wal: Arc<Mutex<WriteAheadLog>>) -> Result<Transaction> {
-        let txn_id = TransactionId(self.next_txn_id.fetch_add(1, Ordering::SeqCst));
+        self.begin_transaction_with_isolation(wal, IsolationLevel::default())
+    }
+
+    pub fn begin_transaction_with_isolation(
+        &self,
+        wal: Arc<Mutex<WriteAheadLog>>,
+        isolation: IsolationLevel,
+    ) -> Result<Transaction> {
+        let (txn_id, snapshot) = {
+            let mut active_txns = self.active_txns.lock().unwrap();
+            let txn_id = TransactionId(self.next_txn_id.fetch_add(1, Ordering::SeqCst));
+            let snapshot = self.take_snapshot(Some(txn_id), &active_txns);
+            active_txns.push(txn_id);
+            (txn_id, snapshot)
+        };
+        let mut txn = Transaction {
+            id: txn_id,
+            is_active: true,
+            wal: Some(Arc::clone(&wal)),
+            snapshot,
+            isolation,
+            undo_log: Vec::new(),
+            savepoints: Vec::new(),
+            read_snapshots: HashMap::new(),
+            read_ranges: Vec::new(),
+            active_txns: Arc::clone(&self.active_txns),
+            locks: Arc::clone(&self.locks),
+        };
 
         // Log the begin transaction record
-        wal.lock().unwrap().log(LogRecord::Begin(txn_id.0))?;
+        if let Err(e) = wal.lock().unwrap().log(LogRecord::Begin(txn_id.0)) {
+            txn.finish();
+            return Err(e);
+        }
+        Ok(txn)
+    }
 
-        let mut active_txns = self.active_txns.lock().unwrap();
-        active_txns.push(txn_id);
+    /// Takes a snapshot for reading outside any transaction.
+    pub fn snapshot(&self) -> Snapshot {
+        let active_txns = self.active_txns.lock().unwrap();
+        self.take_snapshot(None, &active_txns)
+    }
 
-        Ok(Transaction {
-            id: txn_id,
-            is_active: true,
-            wal: Some(wal),
-        })
+    /// The snapshot a statement of `txn` reads from: the one taken at begin
+    /// under `RepeatableRead`, otherwise a new one seeing everything committed
+    /// so far.
+    pub fn statement_snapshot(&self, txn: &Transaction) -> Snapshot {
+        match txn.isolation {
+            IsolationLevel::RepeatableRead => txn.snapshot.clone(),
+            IsolationLevel::ReadCommitted | IsolationLevel::Serializable => {
+                let active_txns = self.active_txns.lock().unwrap();
+                self.take_snapshot(Some(txn.id), &active_txns)
+            }
+        }
+    }
+
+    /// Gives `txn` the snapshot its statement writing `key` goes through, so
+    /// write conflicts are checked against what the transaction saw of the key:
+    /// the snapshot taken at begin under `RepeatableRead`, the one `key` was
+    /// first read or scanned through if it was, and otherwise a new one. A
+    /// serializable transaction that read the key thus fails with
+    /// `WriteConflict` rather than overwrite a version committed since by a
+    /// writer that takes no locks.
+    pub fn refresh_snapshot(&self, txn: &mut Transaction, key: &Key) {
+        if txn.isolation == IsolationLevel::RepeatableRead {
+            return;
+        }
+        txn.snapshot = match txn.read_snapshot(key) {
+            Some(read) => Snapshot::clone(read),
+            None => self.statement_snapshot(txn),
+        };
+    }
+
+    fn take_snapshot(&self, txn_id: Option<TransactionId>, active: &[TransactionId]) -> Snapshot {
+        let mut snapshot = Snapshot {
+            txn_id,
+            xmax: TransactionId(self.next_txn_id.load(Ordering::SeqCst)),
+            active: active.to_vec(),
+            aborted: Arc::clone(&self.aborted_txns),
+            registration: 0,
+            registry: Arc::clone(&self.snapshots),
+        };
+        snapshot.registration = self.snapshots.register(snapshot.horizon());
+        snapshot
+    }
+
+    /// Transactions that have begun but not yet committed or rolled back.
+    pub fn active_transactions(&self) -> Vec<TransactionId> {
+        self.active_txns.lock().unwrap().clone()
+    }
+
+    /// Oldest transaction some snapshot in use may not see. Every transaction
+    /// before it has finished, and unless it rolled back, every snapshot sees it.
+    pub fn horizon(&self) -> TransactionId {
+        let active_txns = self.active_txns.lock().unwrap();
+        let next = TransactionId(self.next_txn_id.load(Ordering::SeqCst));
+        self.snapshots
+            .horizons
+            .lock()
+            .unwrap()
+            .values()
+            .chain(active_txns.iter())
+            .copied()
+            .fold(next, TransactionId::min)
+    }
+
+    pub fn is_aborted(&self, txn_id: TransactionId) -> bool {
+        self.aborted_txns.read().unwrap().contains(&txn_id)
     }
 }
 
@@ -69,25 +367,147 @@ impl Transaction {
             return Ok(());
         }
         if let Some(wal) = &self.wal {
-            wal.lock().unwrap().log(LogRecord::Commit(self.id.0))?;
+            let started = Instant::now();
+            let (sequence, flusher) = {
+                let mut wal = wal.lock().unwrap();
+                (wal.log(LogRecord::Commit(self.id.0))?, wal.flusher())
+            };
+            // Wait outside the log lock so concurrent commits can share an fsync
+            flusher.wait_durable(sequence)?;
+            flusher.record_commit(started.elapsed());
         }
-        self.is_active = false;
-        // Remove from active transactions list would go here
+        self.finish();
         Ok(())
     }
 
+    /// Ends the transaction as rolled back. Its writes must already have been
+    /// undone with `VersionStore::undo`; any left are only hidden from readers,
+    /// until a restart forgets the transaction.
     pub fn rollback(&mut self) -> Result<()> {
         if !self.is_active {
             return Ok(());
         }
+        // Hidden from snapshots before it stops being active, so it is never
+        // mistaken for committed
+        self.snapshot.aborted.write().unwrap().insert(self.id);
         if let Some(wal) = &self.wal {
             wal.lock().unwrap().log(LogRecord::Rollback(self.id.0))?;
         }
+        self.finish();
+        Ok(())
+    }
+
+    fn finish(&mut self) {
         self.is_active = false;
+        self.active_txns.lock().unwrap().retain(|id| *id != self.id);
+        self.undo_log.clear();
+        self.savepoints.clear();
+        self.read_snapshots.clear();
+        self.read_ranges.clear();
+        self.locks.release_all(self.id);
+        // A finished transaction reads nothing more, so its snapshot holds back
+        // no versions
+        self.snapshot.release();
+    }
+
+    /// Locks `key` in `mode` until the transaction commits or rolls back. If this
+    /// fails with `Deadlock`, the transaction should be rolled back so the others
+    /// in the deadlock can go on.
+    pub fn lock(&self, key: &Key, mode: LockMode) -> Result<()> {
+        self.locks.acquire(self.id, key, mode)
+    }
+
+    /// Locks every key in `range` for reading, present or not, until the
+    /// transaction commits or rolls back. Fails like `lock`.
+    pub fn lock_range<R: RangeBounds<Key>>(&self, range: R) -> Result<()> {
+        self.locks.acquire_range(self.id, range)
+    }
+
+    /// Logs the entry a write to the encoded `key` is about to replace, before
+    /// the write changes any page, so recovery can undo the write key by key.
+    pub fn log_undo(&self, key: &[u8], entry: Option<Vec<u8>>) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.lock().unwrap().log(LogRecord::Undo {
+                txn_id: self.id.0,
+                key: key.to_vec(),
+                entry,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Notes that `key` was read through `snapshot`. A key read again keeps the
+    /// snapshot it was first read through.
+    pub fn note_read(&mut self, key: &Key, snapshot: &Arc<Snapshot>) {
+        self.read_snapshots
+            .entry(key.encode())
+            .or_insert_with(|| Arc::clone(snapshot));
+    }
+
+    /// Notes that the keys in `range` were scanned through `snapshot`.
+    pub fn note_range_read<R: RangeBounds<Key>>(&mut self, range: R, snapshot: &Arc<Snapshot>) {
+        self.read_ranges.push((
+            range.start_bound().cloned(),
+            range.end_bound().cloned(),
+            Arc::clone(snapshot),
+        ));
+    }
+
+    /// The snapshot `key` was first read through, or else that of the first
+    /// scan that covered it.
+    fn read_snapshot(&self, key: &Key) -> Option<&Arc<Snapshot>> {
+        self.read_snapshots.get(&key.encode()).or_else(|| {
+            self.read_ranges
+                .iter()
+                .find(|(start, end, _)| (start.as_ref(), end.as_ref()).contains(key))
+                .map(|(_, _, snapshot)| snapshot)
+        })
+    }
+
+    /// Marks the current point of the transaction as `name`. Savepoints nest; a
+    /// name set again refers to the newest one until that is released.
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints
+            .push((name.to_string(), self.undo_log.len()));
+    }
+
+    /// Discards the savepoints set after `name` and returns how long the undo log
+    /// was when `name` was set; the writes after that are for the caller to undo
+    /// with `VersionStore::undo`. The savepoint itself stays, so the transaction
+    /// can roll back to it again.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> Result<usize> {
+        let idx = self.find_savepoint(name)?;
+        self.savepoints.truncate(idx + 1);
+        Ok(self.savepoints[idx].1)
+    }
+
+    /// Forgets savepoint `name` and those set after it, keeping their writes.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let idx = self.find_savepoint(name)?;
+        self.savepoints.truncate(idx);
         Ok(())
     }
 
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+            .ok_or_else(|| DatabaseError::SavepointNotFound(name.to_string()))
+    }
+
     pub fn id(&self) -> TransactionId {
         self.id
     }
 }
+
+impl Drop for Transaction {
+    /// A transaction dropped while still active, say because committing and
+    /// then rolling it back failed, ends as aborted: its leftover writes are
+    /// hidden and conflict with no one, and its locks are released.
+    fn drop(&mut self) {
+        if self.is_active {
+            self.snapshot.aborted.write().unwrap().insert(self.id);
+            self.finish();
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Represents a database value that can be stored and manipulated
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -44,6 +45,16 @@ impl Value {
         buffer
     }
 
+    /// Length of the encoding produced by `serialize`.
+    pub fn serialized_size(&self) -> usize {
+        match self {
+            Value::Integer(_) | Value::Float(_) => 9,
+            Value::String(s) => 5 + s.len(),
+            Value::Boolean(_) => 2,
+            Value::Null => 1,
+        }
+    }
+
     pub fn deserialize(buffer: &[u8]) -> Result<(Self, usize), Box<dyn std::error::Error>> {
         if buffer.is_empty() {
             return Err("Empty buffer".into());
@@ -78,7 +89,7 @@ impl Value {
             (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a + b)),
             (Value::Integer(a), Value::Float(b)) => Ok(Value::Float(*a as f64 + b)),
             (Value::Float(a), Value::Integer(b)) => Ok(Value::Float(a + *b as f64)),
-            (Value::String(a), Value::String(b)) => Ok(Value::String(a.clone() + &b)),
+            (Value::String(a), Value::String(b)) => Ok(Value::String(a.clone() + b)),
             (Value::Boolean(a), Value::Boolean(b)) => Ok(Value::Boolean(*a && *b)),
             _ => Err("Invalid types for addition".into()),
         }
@@ -125,13 +136,15 @@ impl Value {
             _ => Err("Invalid types for division".into()),
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Integer(i) => i.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::String(s) => s.clone(),
-            _ => "".to_string(),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(fl) => write!(f, "{}", fl),
+            Value::String(s) => write!(f, "{}", s),
+            _ => Ok(()),
         }
     }
 }
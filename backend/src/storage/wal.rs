@@ -1,37 +1,470 @@
 use super::error::{DatabaseError, Result};
 use crc32fast::Hasher;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::path::Path;
+use log::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 
+// Sequence number (8 bytes) + record type (1 byte)
+const RECORD_HEADER_SIZE: usize = 9;
+const CHECKSUM_SIZE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum LogRecord {
     Begin(u64),    // Transaction ID
     Commit(u64),   // Transaction ID
     Rollback(u64), // Transaction ID
+    /// A change to one page: the byte range starting at `offset` in the serialized
+    /// page went from `before` to `after`.
     Write {
         txn_id: u64,
         page_id: u32,
         offset: u16,
-        data: Vec<u8>,
+        before: Vec<u8>, // Used for undo
+        after: Vec<u8>,  // Used for redo
+    },
+    /// Written once every dirty page has been flushed. Recovery only has to redo
+    /// records after the latest checkpoint.
+    Checkpoint {
+        active_txns: Vec<u64>,
     },
+    /// Logged before a transaction writes a key: the encoded key and the B-tree
+    /// entry the write replaces, or `None` if the key had none. Recovery puts
+    /// the entry back through the B-tree if the transaction never finished.
+    Undo {
+        txn_id: u64,
+        key: Vec<u8>,
+        entry: Option<Vec<u8>>,
+    },
+}
+
+impl LogRecord {
+    fn type_tag(&self) -> u8 {
+        match self {
+            LogRecord::Begin(_) => 1,
+            LogRecord::Commit(_) => 2,
+            LogRecord::Rollback(_) => 3,
+            LogRecord::Write { .. } => 4,
+            LogRecord::Checkpoint { .. } => 5,
+            LogRecord::Undo { .. } => 6,
+        }
+    }
+
+    /// The transaction a record belongs to, or `None` for checkpoints.
+    pub fn txn_id(&self) -> Option<u64> {
+        match self {
+            LogRecord::Begin(txn_id) | LogRecord::Commit(txn_id) | LogRecord::Rollback(txn_id) => {
+                Some(*txn_id)
+            }
+            LogRecord::Write { txn_id, .. } | LogRecord::Undo { txn_id, .. } => Some(*txn_id),
+            LogRecord::Checkpoint { .. } => None,
+        }
+    }
+
+    fn serialize_payload(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            LogRecord::Begin(txn_id) | LogRecord::Commit(txn_id) | LogRecord::Rollback(txn_id) => {
+                buffer.extend_from_slice(&txn_id.to_le_bytes());
+            }
+            LogRecord::Write {
+                txn_id,
+                page_id,
+                offset,
+                before,
+                after,
+            } => {
+                buffer.extend_from_slice(&txn_id.to_le_bytes());
+                buffer.extend_from_slice(&page_id.to_le_bytes());
+                buffer.extend_from_slice(&offset.to_le_bytes());
+                buffer.extend_from_slice(&(before.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(before);
+                buffer.extend_from_slice(&(after.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(after);
+            }
+            LogRecord::Checkpoint { active_txns } => {
+                buffer.extend_from_slice(&(active_txns.len() as u32).to_le_bytes());
+                for txn_id in active_txns {
+                    buffer.extend_from_slice(&txn_id.to_le_bytes());
+                }
+            }
+            LogRecord::Undo { txn_id, key, entry } => {
+                buffer.extend_from_slice(&txn_id.to_le_bytes());
+                buffer.extend_from_slice(&(key.len() as u32).to_le_bytes());
+                buffer.extend_from_slice(key);
+                match entry {
+                    Some(entry) => {
+                        buffer.push(1);
+                        buffer.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+                        buffer.extend_from_slice(entry);
+                    }
+                    None => buffer.push(0),
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Encodes a complete log entry: sequence number, type, payload and checksum.
+    fn encode(&self, sequence: u64) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&sequence.to_le_bytes());
+        buffer.push(self.type_tag());
+        buffer.extend(self.serialize_payload());
+
+        let mut hasher = Hasher::new();
+        hasher.update(&buffer);
+        buffer.extend_from_slice(&hasher.finalize().to_le_bytes());
+        buffer
+    }
+
+    /// Decodes a record payload, returning `None` if the buffer is too short.
+    fn deserialize_payload(type_tag: u8, buffer: &[u8]) -> Result<Option<(Self, usize)>> {
+        let mut reader = ByteReader::new(buffer);
+        let record = match type_tag {
+            1..=3 => {
+                let Some(txn_id) = reader.u64() else {
+                    return Ok(None);
+                };
+                match type_tag {
+                    1 => LogRecord::Begin(txn_id),
+                    2 => LogRecord::Commit(txn_id),
+                    _ => LogRecord::Rollback(txn_id),
+                }
+            }
+            4 => {
+                let (Some(txn_id), Some(page_id), Some(offset)) =
+                    (reader.u64(), reader.u32(), reader.u16())
+                else {
+                    return Ok(None);
+                };
+                let Some(before) = reader.bytes() else {
+                    return Ok(None);
+                };
+                let Some(after) = reader.bytes() else {
+                    return Ok(None);
+                };
+                LogRecord::Write {
+                    txn_id,
+                    page_id,
+                    offset,
+                    before,
+                    after,
+                }
+            }
+            5 => {
+                let Some(count) = reader.u32() else {
+                    return Ok(None);
+                };
+                let mut active_txns = Vec::new();
+                for _ in 0..count {
+                    let Some(txn_id) = reader.u64() else {
+                        return Ok(None);
+                    };
+                    active_txns.push(txn_id);
+                }
+                LogRecord::Checkpoint { active_txns }
+            }
+            6 => {
+                let (Some(txn_id), Some(key), Some(has_entry)) =
+                    (reader.u64(), reader.bytes(), reader.u8())
+                else {
+                    return Ok(None);
+                };
+                let entry = match has_entry {
+                    0 => None,
+                    _ => match reader.bytes() {
+                        Some(entry) => Some(entry),
+                        None => return Ok(None),
+                    },
+                };
+                LogRecord::Undo { txn_id, key, entry }
+            }
+            tag => {
+                return Err(DatabaseError::InvalidData(format!(
+                    "Unknown log record type {}",
+                    tag
+                )))
+            }
+        };
+        Ok(Some((record, reader.position)))
+    }
+}
+
+struct ByteReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(len)?;
+        let slice = self.buffer.get(self.position..end)?;
+        self.position = end;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.take(len).map(|b| b.to_vec())
+    }
+}
+
+/// When a committing transaction's log records are forced to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// fsync the log before every commit returns.
+    #[default]
+    Always,
+    /// Commits arriving within `delay` of each other share a single fsync.
+    Group { delay: Duration },
+    /// Leave writing the log to disk to the operating system.
+    Os,
+}
+
+impl FromStr for Durability {
+    type Err = DatabaseError;
+
+    /// Parses `always`, `os`, `group` or `group:<delay in ms>`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "always" => Ok(Durability::Always),
+            "os" => Ok(Durability::Os),
+            "group" => Ok(Durability::Group {
+                delay: Duration::from_millis(2),
+            }),
+            other => match other.strip_prefix("group:") {
+                Some(ms) => ms
+                    .parse()
+                    .map(|ms| Durability::Group {
+                        delay: Duration::from_millis(ms),
+                    })
+                    .map_err(|_| DatabaseError::ParseError(format!("Invalid delay: {}", ms))),
+                None => Err(DatabaseError::ParseError(format!(
+                    "Unknown durability mode: {}",
+                    other
+                ))),
+            },
+        }
+    }
+}
+
+/// Commit and fsync counters for the log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalStats {
+    pub commits: u64,
+    pub fsyncs: u64,
+    pub total_commit_latency: Duration,
+}
+
+impl WalStats {
+    pub fn average_commit_latency(&self) -> Duration {
+        if self.commits == 0 {
+            return Duration::ZERO;
+        }
+        self.total_commit_latency / self.commits as u32
+    }
+}
+
+struct FlushState {
+    file: File,
+    flushed_sequence: u64,
+    syncing: bool,
+}
+
+/// Makes committed log records durable according to the configured `Durability`.
+///
+/// Shared between the log and committing transactions so that waiting for an
+/// fsync does not hold the log's lock. In group mode the first committer to
+/// arrive becomes the leader: it sleeps for the configured delay, then issues
+/// one fsync covering every record written so far and wakes the others.
+pub struct LogFlusher {
+    durability: Durability,
+    written_sequence: AtomicU64,
+    state: Mutex<FlushState>,
+    flushed: Condvar,
+    commits: AtomicU64,
+    fsyncs: AtomicU64,
+    commit_latency_nanos: AtomicU64,
+}
+
+impl LogFlusher {
+    fn new(file: File, durability: Durability, sequence: u64) -> Self {
+        Self {
+            durability,
+            written_sequence: AtomicU64::new(sequence),
+            state: Mutex::new(FlushState {
+                file,
+                flushed_sequence: sequence,
+                syncing: false,
+            }),
+            flushed: Condvar::new(),
+            commits: AtomicU64::new(0),
+            fsyncs: AtomicU64::new(0),
+            commit_latency_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Blocks until the record with `sequence` is on stable storage. Returns
+    /// immediately in `Os` mode.
+    pub fn wait_durable(&self, sequence: u64) -> Result<()> {
+        if self.durability == Durability::Os {
+            return Ok(());
+        }
+        self.flush_to(sequence)
+    }
+
+    /// Forces every record up to `sequence` to stable storage, whatever the mode.
+    pub fn flush_to(&self, sequence: u64) -> Result<()> {
+        // Nothing past the last written record can become durable
+        let sequence = sequence.min(self.written_sequence.load(Ordering::SeqCst));
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.flushed_sequence >= sequence {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self.flushed.wait(state).unwrap();
+                continue;
+            }
+
+            state.syncing = true;
+            if let Durability::Group { delay } = self.durability {
+                // Give concurrent committers a chance to join this fsync
+                drop(state);
+                thread::sleep(delay);
+                state = self.state.lock().unwrap();
+            }
+
+            let target = self.written_sequence.load(Ordering::SeqCst);
+            let result = state.file.sync_data();
+            state.syncing = false;
+            if result.is_ok() {
+                self.fsyncs.fetch_add(1, Ordering::Relaxed);
+                state.flushed_sequence = state.flushed_sequence.max(target);
+            }
+            self.flushed.notify_all();
+            result?;
+        }
+    }
+
+    pub fn flushed_sequence(&self) -> u64 {
+        self.state.lock().unwrap().flushed_sequence
+    }
+
+    pub fn record_commit(&self, latency: Duration) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        self.commit_latency_nanos
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> WalStats {
+        WalStats {
+            commits: self.commits.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+            total_commit_latency: Duration::from_nanos(
+                self.commit_latency_nanos.load(Ordering::Relaxed),
+            ),
+        }
+    }
+
+    fn note_written(&self, sequence: u64) {
+        self.written_sequence.store(sequence, Ordering::SeqCst);
+    }
+
+    fn reset(&self) {
+        self.written_sequence.store(0, Ordering::SeqCst);
+        self.state.lock().unwrap().flushed_sequence = 0;
+    }
+
+    /// Points the flusher at a replacement log file whose contents are already
+    /// durable.
+    fn replace_file(&self, file: File, sequence: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.file = file;
+        state.flushed_sequence = state.flushed_sequence.max(sequence);
+    }
 }
 
 pub struct WriteAheadLog {
     log_file: File,
+    path: PathBuf,
     sequence: u64,
+    flusher: Arc<LogFlusher>,
+    // Number of appends still to fail, to test how failed writes are handled
+    #[cfg(test)]
+    failing_appends: usize,
 }
 
 impl WriteAheadLog {
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_durability(path, Durability::default())
+    }
+
+    pub fn with_durability(path: impl AsRef<Path>, durability: Durability) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
         let log_file = OpenOptions::new()
             .create(true)
             .append(true)
-            .open(path)
-            .map_err(|e| DatabaseError::IoError(e))?;
+            .open(&path)
+            .map_err(DatabaseError::IoError)?;
 
+        // Resume numbering after the last intact record and drop any torn tail,
+        // so new records are never appended behind garbage.
+        let (records, valid_len) = Self::scan(&path)?;
+        let sequence = records.last().map_or(0, |(sequence, _)| *sequence);
+        if valid_len < log_file.metadata()?.len() {
+            warn!(
+                "Discarding torn tail of write-ahead log {} after byte {}",
+                path.display(),
+                valid_len
+            );
+            log_file.set_len(valid_len)?;
+        }
+
+        let flusher = Arc::new(LogFlusher::new(log_file.try_clone()?, durability, sequence));
         Ok(Self {
             log_file,
-            sequence: 0,
+            path,
+            sequence,
+            flusher,
+            #[cfg(test)]
+            failing_appends: 0,
         })
     }
 
@@ -39,54 +472,112 @@ impl WriteAheadLog {
         self.sequence
     }
 
-    pub fn log(&mut self, record: LogRecord) -> Result<()> {
+    /// Appends a record and returns its sequence number. The record is handed to
+    /// the operating system; use the `LogFlusher` to wait until it is durable.
+    pub fn log(&mut self, record: LogRecord) -> Result<u64> {
+        #[cfg(test)]
+        if self.failing_appends > 0 {
+            self.failing_appends -= 1;
+            return Err(std::io::Error::other("injected log write failure").into());
+        }
         self.sequence += 1;
-        let mut hasher = Hasher::new();
+        self.log_file.write_all(&record.encode(self.sequence))?;
+        self.flusher.note_written(self.sequence);
+        Ok(self.sequence)
+    }
 
-        // Write sequence number
-        let seq_bytes = self.sequence.to_le_bytes();
-        self.log_file.write_all(&seq_bytes)?;
-        hasher.update(&seq_bytes);
-
-        // Write record
-        match record {
-            LogRecord::Begin(txn_id) => {
-                self.log_file.write_all(&[1])?;
-                self.log_file.write_all(&txn_id.to_le_bytes())?;
-                hasher.update(&[1]);
-                hasher.update(&txn_id.to_le_bytes());
-            }
-            LogRecord::Commit(txn_id) => {
-                self.log_file.write_all(&[2])?;
-                self.log_file.write_all(&txn_id.to_le_bytes())?;
-                hasher.update(&[2]);
-                hasher.update(&txn_id.to_le_bytes());
-            }
-            LogRecord::Rollback(txn_id) => {
-                self.log_file.write_all(&[3])?;
-                self.log_file.write_all(&txn_id.to_le_bytes())?;
-                hasher.update(&[3]);
-                hasher.update(&txn_id.to_le_bytes());
-            }
-            LogRecord::Write {
-                txn_id,
-                page_id,
-                offset,
-                data,
-            } => {
-                self.log_file.write_all(&[4])?;
-                self.log_file.write_all(&txn_id.to_le_bytes())?;
-                self.log_file.write_all(&page_id.to_le_bytes())?;
-                self.log_file.write_all(&offset.to_le_bytes())?;
-                self.log_file
-                    .write_all(&(data.len() as u32).to_le_bytes())?;
-                self.log_file.write_all(&data)?;
-            }
+    /// Makes the next `count` calls to `log` fail without writing anything.
+    #[cfg(test)]
+    pub fn fail_appends(&mut self, count: usize) {
+        self.failing_appends = count;
+    }
+
+    pub fn flusher(&self) -> Arc<LogFlusher> {
+        Arc::clone(&self.flusher)
+    }
+
+    /// Discards every record in the log. Used when a brand-new database is created
+    /// next to a stale log file.
+    pub fn reset(&mut self) -> Result<()> {
+        self.log_file.set_len(0)?;
+        self.sequence = 0;
+        self.flusher.reset();
+        Ok(())
+    }
+
+    /// Drops every record with a sequence number below `sequence`. The remaining
+    /// records are written to a new file which then replaces the log, so a crash
+    /// part-way through leaves either the old or the new log intact.
+    pub fn truncate_before(&mut self, sequence: u64) -> Result<()> {
+        let records = self.read_records()?;
+        let temp_path = self.path.with_extension("wal.tmp");
+
+        let mut temp_file = File::create(&temp_path)?;
+        for (record_sequence, record) in records.iter().filter(|(seq, _)| *seq >= sequence) {
+            temp_file.write_all(&record.encode(*record_sequence))?;
         }
-        // Write checksum
-        let checksum = hasher.finalize();
-        self.log_file.write_all(&checksum.to_le_bytes())?;
-        self.log_file.flush()?;
+        temp_file.sync_all()?;
+        drop(temp_file);
+
+        fs::rename(&temp_path, &self.path)?;
+        self.log_file = OpenOptions::new().append(true).open(&self.path)?;
+        self.flusher
+            .replace_file(self.log_file.try_clone()?, self.sequence);
         Ok(())
     }
+
+    /// Current size of the log file in bytes.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.log_file.metadata()?.len())
+    }
+
+    /// Reads back every intact record in log order as `(sequence, record)` pairs.
+    pub fn read_records(&self) -> Result<Vec<(u64, LogRecord)>> {
+        Ok(Self::scan(&self.path)?.0)
+    }
+
+    /// Reads the intact records of the log at `path` without opening it for
+    /// writing, so a torn tail is left in place.
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Vec<(u64, LogRecord)>> {
+        Ok(Self::scan(path.as_ref())?.0)
+    }
+
+    /// Parses the log file from the start, stopping at the first record that is
+    /// truncated or fails its checksum. Returns the records and the byte length
+    /// of the intact prefix.
+    fn scan(path: &Path) -> Result<(Vec<(u64, LogRecord)>, u64)> {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+
+        let mut records = Vec::new();
+        let mut position = 0;
+
+        while contents.len() - position >= RECORD_HEADER_SIZE + CHECKSUM_SIZE {
+            let header = &contents[position..position + RECORD_HEADER_SIZE];
+            let sequence = u64::from_le_bytes(header[0..8].try_into().unwrap());
+            let type_tag = header[8];
+
+            let payload_start = position + RECORD_HEADER_SIZE;
+            let (record, payload_len) =
+                match LogRecord::deserialize_payload(type_tag, &contents[payload_start..]) {
+                    Ok(Some(decoded)) => decoded,
+                    Ok(None) | Err(_) => break,
+                };
+
+            let checksum_start = payload_start + payload_len;
+            let Some(stored) = contents.get(checksum_start..checksum_start + CHECKSUM_SIZE) else {
+                break;
+            };
+            let mut hasher = Hasher::new();
+            hasher.update(&contents[position..checksum_start]);
+            if hasher.finalize() != u32::from_le_bytes(stored.try_into().unwrap()) {
+                break;
+            }
+
+            records.push((sequence, record));
+            position = checksum_start + CHECKSUM_SIZE;
+        }
+
+        Ok((records, position as u64))
+    }
 }
@@ -1,34 +1,34 @@
 #[cfg(test)]
 mod tests {
-    use crate::btree::BTree;
+    use crate::btree::{BTree, BTreeNode, MAX_INLINE_VALUE_SIZE, MAX_KEY_SIZE};
     use crate::storage::buffer_pool::BufferPool;
     use crate::storage::disk_manager::DiskManager;
-    use crate::storage::error::Result;
+    use crate::storage::error::{DatabaseError, Result};
+    use crate::storage::key::Key;
+    use crate::storage::transaction::VersionId;
     use crate::storage::value::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_btree_operations() -> Result<()> {
         let disk_manager = DiskManager::new("test_btree.db")?;
-        let mut buffer_pool = BufferPool::new(100, disk_manager);
-        let root_page_id = buffer_pool.new_page()?.header.page_id;
-        let mut btree = BTree::new(root_page_id);
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
 
         // Initialize root node
-        btree.init(&mut buffer_pool)?;
+        btree.init(&buffer_pool)?;
 
         // Insert test data
-        btree.insert(5, Value::Integer(50), &mut buffer_pool)?;
-        btree.insert(3, Value::Boolean(false), &mut buffer_pool)?;
-        btree.insert(7, Value::String("Test".to_string()), &mut buffer_pool)?;
+        btree.insert(5, Value::Integer(50), &buffer_pool)?;
+        btree.insert(3, Value::Boolean(false), &buffer_pool)?;
+        btree.insert(7, Value::String("Test".to_string()), &buffer_pool)?;
 
         // Verify insertions
-        assert_eq!(btree.search(5, &mut buffer_pool)?, Some(Value::Integer(50)));
+        assert_eq!(btree.search(5, &buffer_pool)?, Some(Value::Integer(50)));
+        assert_eq!(btree.search(3, &buffer_pool)?, Some(Value::Boolean(false)));
         assert_eq!(
-            btree.search(3, &mut buffer_pool)?,
-            Some(Value::Boolean(false))
-        );
-        assert_eq!(
-            btree.search(7, &mut buffer_pool)?,
+            btree.search(7, &buffer_pool)?,
             Some(Value::String("Test".to_string()))
         );
 
@@ -41,30 +41,29 @@ mod tests {
     #[test]
     fn test_btree_delete() -> Result<()> {
         let disk_manager = DiskManager::new("test_btree_delete.db")?;
-        let mut buffer_pool = BufferPool::new(100, disk_manager);
-        let root_page_id = buffer_pool.new_page()?.header.page_id;
-        let mut btree = BTree::new(root_page_id);
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
 
-        btree.init(&mut buffer_pool)?;
+        btree.init(&buffer_pool)?;
 
         // Insert test data
-        btree.insert(10, Value::Integer(100), &mut buffer_pool)?;
-        btree.insert(20, Value::Integer(200), &mut buffer_pool)?;
-        btree.insert(30, Value::Integer(300), &mut buffer_pool)?;
+        btree.insert(10, Value::Integer(100), &buffer_pool)?;
+        btree.insert(20, Value::Integer(200), &buffer_pool)?;
+        btree.insert(30, Value::Integer(300), &buffer_pool)?;
 
         // Delete a key
-        btree.delete(20, &mut buffer_pool)?;
+        btree.delete(20, &buffer_pool)?;
+        assert!(matches!(
+            btree.delete(20, &buffer_pool),
+            Err(DatabaseError::KeyNotFound(Key::Int(20)))
+        ));
+        btree.verify(&buffer_pool)?;
 
         // Verify deletion
-        assert_eq!(btree.search(20, &mut buffer_pool)?, None);
-        assert_eq!(
-            btree.search(10, &mut buffer_pool)?,
-            Some(Value::Integer(100))
-        );
-        assert_eq!(
-            btree.search(30, &mut buffer_pool)?,
-            Some(Value::Integer(300))
-        );
+        assert_eq!(btree.search(20, &buffer_pool)?, None);
+        assert_eq!(btree.search(10, &buffer_pool)?, Some(Value::Integer(100)));
+        assert_eq!(btree.search(30, &buffer_pool)?, Some(Value::Integer(300)));
 
         // Delete the file again
         std::fs::remove_file("test_btree_delete.db")?;
@@ -75,23 +74,23 @@ mod tests {
     #[test]
     fn test_btree_update() -> Result<()> {
         let disk_manager = DiskManager::new("test_btree_update.db")?;
-        let mut buffer_pool = BufferPool::new(100, disk_manager);
-        let root_page_id = buffer_pool.new_page()?.header.page_id;
-        let mut btree = BTree::new(root_page_id);
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
 
-        btree.init(&mut buffer_pool)?;
+        btree.init(&buffer_pool)?;
 
         // Insert initial data
-        btree.insert(5, Value::String("five".to_string()), &mut buffer_pool)?;
+        btree.insert(5, Value::String("five".to_string()), &buffer_pool)?;
 
-        btree.delete(5, &mut buffer_pool)?;
+        btree.delete(5, &buffer_pool)?;
 
         // Update the value
-        btree.insert(5, Value::String("FIVE".to_string()), &mut buffer_pool)?;
+        btree.insert(5, Value::String("FIVE".to_string()), &buffer_pool)?;
 
         // Verify update
         assert_eq!(
-            btree.search(5, &mut buffer_pool)?,
+            btree.search(5, &buffer_pool)?,
             Some(Value::String("FIVE".to_string()))
         );
 
@@ -101,22 +100,279 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_root_changes_reach_header() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_root.db");
+        let disk_manager = DiskManager::new("test_btree_root.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // Four of these values fill a page, so the fifth key splits the root
+        for key in 1..=5 {
+            btree.insert(key, padded(key), &buffer_pool)?;
+        }
+        let split_root = btree.root_page_id();
+        assert_ne!(split_root, root_page_id);
+        assert_eq!(buffer_pool.root_page_id(), Some(split_root));
+
+        // Leaves are [1, 2] and [3, 4, 5]. Emptying the right leaf borrows from
+        // the left one, then merges with it, which empties the root
+        for key in [3, 4, 5, 2] {
+            btree.delete(key, &buffer_pool)?;
+        }
+        assert_eq!(btree.root_page_id(), root_page_id);
+        assert_eq!(buffer_pool.root_page_id(), Some(root_page_id));
+        assert_eq!(btree.search(1, &buffer_pool)?, Some(padded(1)));
+        assert_eq!(btree.search(2, &buffer_pool)?, None);
+
+        // The old root went back on the free list
+        assert_eq!(buffer_pool.new_page()?.page_id(), split_root);
+
+        std::fs::remove_file("test_btree_root.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_larger_than_buffer_pool() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_small_pool.db");
+        let disk_manager = DiskManager::new("test_btree_small_pool.db")?;
+        let buffer_pool = BufferPool::new(8, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for i in 0..5000 {
+            btree.insert(i, Value::Integer(i as i64), &buffer_pool)?;
+        }
+        for i in 0..5000 {
+            assert_eq!(
+                btree.search(i, &buffer_pool)?,
+                Some(Value::Integer(i as i64))
+            );
+        }
+        assert!(buffer_pool.stats().evictions > 0);
+
+        // Nothing is written until pages are evicted or flushed
+        let buffer_pool = BufferPool::new(100, DiskManager::new("test_btree_small_pool.db")?);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+        for i in 0..50 {
+            btree.insert(i, Value::Integer(i as i64), &buffer_pool)?;
+        }
+        assert_eq!(buffer_pool.stats().writes, 0);
+        buffer_pool.flush()?;
+        assert!(buffer_pool.stats().writes < 50);
+
+        std::fs::remove_file("test_btree_small_pool.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_range() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_range.db");
+        let disk_manager = DiskManager::new("test_btree_range.db")?;
+        let buffer_pool = BufferPool::new(1000, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for i in (0..10000).rev() {
+            btree.insert(i * 2, Value::Integer(i as i64), &buffer_pool)?;
+        }
+        let keys = |entries: Vec<(Key, Value)>| -> Vec<Key> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+        let ints = |keys: &[i64]| -> Vec<Key> { keys.iter().map(|&key| Key::Int(key)).collect() };
+
+        assert_eq!(
+            keys(btree.range(Key::from(10)..Key::from(20), None, &buffer_pool)?),
+            ints(&[10, 12, 14, 16, 18])
+        );
+        assert_eq!(
+            keys(btree.range(..=Key::from(4), None, &buffer_pool)?),
+            ints(&[0, 2, 4])
+        );
+        assert_eq!(
+            keys(btree.range(Key::from(19995).., None, &buffer_pool)?),
+            ints(&[19996, 19998])
+        );
+        assert_eq!(
+            keys(btree.range(Key::from(101)..Key::from(1000), Some(3), &buffer_pool)?),
+            ints(&[102, 104, 106])
+        );
+        assert!(btree
+            .range(Key::from(5)..Key::from(5), None, &buffer_pool)?
+            .is_empty());
+        assert_eq!(
+            btree.range(.., None, &buffer_pool)?,
+            btree.all(&buffer_pool)?
+        );
+
+        // A narrow range reads far fewer pages than a full scan
+        let before = buffer_pool.stats();
+        btree.range(Key::from(100)..Key::from(104), None, &buffer_pool)?;
+        let after = buffer_pool.stats();
+        let range_reads = after.hits + after.misses - before.hits - before.misses;
+        btree.all(&buffer_pool)?;
+        let all_reads =
+            buffer_pool.stats().hits + buffer_pool.stats().misses - after.hits - after.misses;
+        assert!(range_reads * 10 < all_reads);
+
+        std::fs::remove_file("test_btree_range.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_leaf_chain() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_leaf_chain.db");
+        let disk_manager = DiskManager::new("test_btree_leaf_chain.db")?;
+        let buffer_pool = BufferPool::new(1000, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for i in 0..3000 {
+            btree.insert((i * 7) % 3000, Value::Integer(i as i64), &buffer_pool)?;
+        }
+        for i in (0..3000).step_by(3) {
+            btree.delete(i, &buffer_pool)?;
+        }
+
+        // Descend to the leftmost leaf, then follow the chain to the end
+        let mut node =
+            BTreeNode::deserialize(&buffer_pool.get_page(btree.root_page_id())?.page().data)?;
+        while !node.is_leaf {
+            node = BTreeNode::deserialize(&buffer_pool.get_page(node.children[0])?.page().data)?;
+        }
+        assert_eq!(node.prev_leaf, None);
+
+        let mut keys = Vec::new();
+        loop {
+            assert!(node.keys.is_empty());
+            for entry in &node.entries {
+                keys.push(Key::decode(&entry.key)?);
+            }
+            let Some(next_page_id) = node.next_leaf else {
+                break;
+            };
+            let next = BTreeNode::deserialize(&buffer_pool.get_page(next_page_id)?.page().data)?;
+            assert_eq!(next.prev_leaf, Some(node.page_id));
+            node = next;
+        }
+
+        btree.verify(&buffer_pool)?;
+        let expected: Vec<Key> = (0..3000).filter(|key| key % 3 != 0).map(Key::Int).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(
+            btree
+                .all(&buffer_pool)?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        std::fs::remove_file("test_btree_leaf_chain.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_splits_by_size() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_sizes.db");
+        let disk_manager = DiskManager::new("test_btree_sizes.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // Small values pack a hundred keys into a single page
+        for i in 0..100 {
+            btree.insert(i, Value::Integer(i as i64), &buffer_pool)?;
+        }
+        assert_eq!(btree.root_page_id(), root_page_id);
+
+        // A few values at the inline limit fill a page on their own
+        let largest = Value::String("x".repeat(MAX_INLINE_VALUE_SIZE - 5));
+        for key in 50..54 {
+            btree.insert(key, largest.clone(), &buffer_pool)?;
+        }
+        assert_ne!(btree.root_page_id(), root_page_id);
+        for key in 50..54 {
+            assert_eq!(btree.search(key, &buffer_pool)?, Some(largest.clone()));
+        }
+        assert_eq!(btree.all(&buffer_pool)?.len(), 100);
+
+        std::fs::remove_file("test_btree_sizes.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_overflow_values() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_overflow.db");
+        let disk_manager = DiskManager::new("test_btree_overflow.db")?;
+        let buffer_pool = BufferPool::new(16, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // Far larger than the buffer pool, so the chain is written out as it grows
+        let blob = |tag: char| {
+            Value::String(format!(
+                "{{\"data\": \"{}\"}}",
+                tag.to_string().repeat(300_000)
+            ))
+        };
+        btree.insert(1, blob('a'), &buffer_pool)?;
+        btree.insert(2, Value::Integer(2), &buffer_pool)?;
+        btree.insert(3, blob('b'), &buffer_pool)?;
+        assert_eq!(btree.root_page_id(), root_page_id);
+        assert_eq!(btree.search(1, &buffer_pool)?, Some(blob('a')));
+        assert_eq!(btree.search(3, &buffer_pool)?, Some(blob('b')));
+        assert_eq!(
+            btree.range(Key::from(2)..Key::from(4), None, &buffer_pool)?,
+            vec![(Key::Int(2), Value::Integer(2)), (Key::Int(3), blob('b'))]
+        );
+
+        // Replaced and deleted values give their pages back for reuse
+        let next_page_id = buffer_pool.header().next_page_id;
+        btree.update(1, Value::Integer(1), &buffer_pool)?;
+        btree.delete(3, &buffer_pool)?;
+        btree.insert(4, blob('c'), &buffer_pool)?;
+        btree.insert(5, blob('d'), &buffer_pool)?;
+        assert_eq!(buffer_pool.header().next_page_id, next_page_id);
+
+        buffer_pool.flush()?;
+        let buffer_pool = BufferPool::new(16, DiskManager::new("test_btree_overflow.db")?);
+        let btree = BTree::new(root_page_id);
+        assert_eq!(btree.search(1, &buffer_pool)?, Some(Value::Integer(1)));
+        assert_eq!(btree.search(3, &buffer_pool)?, None);
+        assert_eq!(btree.search(4, &buffer_pool)?, Some(blob('c')));
+        assert_eq!(btree.search(5, &buffer_pool)?, Some(blob('d')));
+
+        std::fs::remove_file("test_btree_overflow.db")?;
+        Ok(())
+    }
+
     #[test]
     fn test_big_btree() -> Result<()> {
+        let _ = std::fs::remove_file("test_big_btree.db");
         let disk_manager = DiskManager::new("test_big_btree.db")?;
-        let mut buffer_pool = BufferPool::new(1000, disk_manager);
-        let root_page_id = buffer_pool.new_page()?.header.page_id;
-        let mut btree = BTree::new(root_page_id);
+        let buffer_pool = BufferPool::new(1000, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
 
-        btree.init(&mut buffer_pool)?;
+        btree.init(&buffer_pool)?;
 
         for i in 0..1000 {
-            btree.insert(i, Value::Integer(i as i64), &mut buffer_pool)?;
+            btree.insert(i, Value::Integer(i as i64), &buffer_pool)?;
         }
 
         for i in 0..1000 {
             assert_eq!(
-                btree.search(i, &mut buffer_pool)?,
+                btree.search(i, &buffer_pool)?,
                 Some(Value::Integer(i as i64)),
                 "Testing search with value {}",
                 i
@@ -124,14 +380,515 @@ mod tests {
         }
 
         for i in 0..1000 {
-            btree.delete(i, &mut buffer_pool)?;
+            btree.delete(i, &buffer_pool)?;
+        }
+
+        assert!(btree.search(0, &buffer_pool)?.is_none(), "Testing deletion");
+
+        std::fs::remove_file("test_big_btree.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_string_keys() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_string_keys.db");
+        let disk_manager = DiskManager::new("test_btree_string_keys.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // UUID-like keys in no particular order, plus a few long ones so that
+        // separators differ in size
+        let uuid = |i: u64| {
+            let h = i.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            format!(
+                "{:08x}-{:04x}-{:04x}-{:012x}",
+                h >> 32,
+                (h >> 16) & 0xffff,
+                h & 0xffff,
+                i
+            )
+        };
+        let mut expected = Vec::new();
+        for i in 0..3000 {
+            let key = if i % 100 == 0 {
+                format!("{}{}", uuid(i), "z".repeat(MAX_KEY_SIZE - 60))
+            } else {
+                uuid(i)
+            };
+            btree.insert(key.as_str(), Value::Integer(i as i64), &buffer_pool)?;
+            expected.push(Key::from(key));
+        }
+        btree.insert(Key::Bytes(vec![0, 1]), Value::Null, &buffer_pool)?;
+        btree.insert(-7i64, Value::Null, &buffer_pool)?;
+        expected.push(Key::Bytes(vec![0, 1]));
+        expected.push(Key::Int(-7));
+        expected.sort();
+
+        let keys = |entries: Vec<(Key, Value)>| -> Vec<Key> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(keys(btree.all(&buffer_pool)?), expected);
+        assert_eq!(
+            btree.search(uuid(42).as_str(), &buffer_pool)?,
+            Some(Value::Integer(42))
+        );
+        assert_eq!(
+            keys(btree.range(Key::from("8")..Key::from("9"), None, &buffer_pool)?),
+            expected
+                .iter()
+                .filter(|key| matches!(key, Key::String(s) if s.starts_with('8')))
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+
+        let too_long = "k".repeat(MAX_KEY_SIZE);
+        assert!(btree
+            .insert(too_long.as_str(), Value::Null, &buffer_pool)
+            .is_err());
+
+        for key in expected.iter().step_by(2) {
+            btree.delete(key.clone(), &buffer_pool)?;
+        }
+        btree.verify(&buffer_pool)?;
+        let remaining: Vec<Key> = expected.iter().skip(1).step_by(2).cloned().collect();
+        assert_eq!(keys(btree.all(&buffer_pool)?), remaining);
+        assert!(btree.search(expected[0].clone(), &buffer_pool)?.is_none());
+
+        std::fs::remove_file("test_btree_string_keys.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_cursor() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_cursor.db");
+        let disk_manager = DiskManager::new("test_btree_cursor.db")?;
+        // Far fewer frames than leaves, so a cursor that kept the pages it had
+        // visited pinned would run out of room
+        let buffer_pool = BufferPool::new(8, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for i in 0..1000 {
+            btree.insert(i * 2, padded(i * 2), &buffer_pool)?;
+        }
+
+        let mut cursor = btree.cursor(&buffer_pool);
+        cursor.seek(501)?;
+        let key = |entry: Option<Result<(Key, Value)>>| entry.map(|entry| entry.unwrap().0);
+        assert_eq!(key(cursor.current()), Some(Key::Int(502)));
+        assert_eq!(key(cursor.next()), Some(Key::Int(502)));
+        assert_eq!(key(cursor.next()), Some(Key::Int(504)));
+        assert_eq!(key(cursor.prev()), Some(Key::Int(504)));
+        assert_eq!(key(cursor.prev()), Some(Key::Int(502)));
+        assert_eq!(key(cursor.prev()), Some(Key::Int(500)));
+
+        // Walks the whole leaf chain in both directions
+        cursor.seek(0)?;
+        assert_eq!(cursor.prev().transpose()?, None);
+        let forward = cursor.by_ref().collect::<Result<Vec<_>>>()?;
+        assert_eq!(forward.len(), 1000);
+        assert_eq!(forward[999], (Key::Int(1998), padded(1998)));
+        assert_eq!(cursor.next().transpose()?, None);
+        let mut backward = Vec::new();
+        while let Some(entry) = cursor.prev() {
+            backward.push(entry?.0);
+        }
+        assert_eq!(backward.len(), 1000);
+        assert_eq!(backward[0], Key::Int(1998));
+        drop(cursor);
+
+        // A range cursor stops at both ends of its range
+        let mut cursor = btree.range_cursor(Key::from(100)..Key::from(110), &buffer_pool);
+        cursor.seek(0)?;
+        assert_eq!(key(cursor.prev()), None);
+        let keys: Vec<Key> = cursor.map(|entry| entry.unwrap().0).collect();
+        assert_eq!(
+            keys,
+            (100..110).step_by(2).map(Key::Int).collect::<Vec<_>>()
+        );
+
+        // Dropped cursors leave nothing pinned
+        for i in 0..100 {
+            btree.insert(i * 2 + 1, padded(i), &buffer_pool)?;
+        }
+
+        std::fs::remove_file("test_btree_cursor.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_verify_after_every_operation() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_verify.db");
+        let disk_manager = DiskManager::new("test_btree_verify.db")?;
+        let buffer_pool = BufferPool::new(200, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // A fixed pseudo-random mix of inserts and deletes with values of varying
+        // size, so nodes split, borrow and merge at every level
+        let mut state: u64 = 42;
+        let mut random = move |bound: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        let mut present = std::collections::BTreeSet::new();
+        for _ in 0..3000 {
+            let key = random(600) as i32;
+            if random(3) == 0 {
+                match btree.delete(key, &buffer_pool) {
+                    Ok(()) => assert!(present.remove(&key)),
+                    Err(DatabaseError::KeyNotFound(_)) => assert!(!present.contains(&key)),
+                    Err(e) => return Err(e),
+                }
+            } else {
+                let size = random(400) as usize;
+                btree.insert(key, Value::String("v".repeat(size)), &buffer_pool)?;
+                present.insert(key);
+            }
+            btree.verify(&buffer_pool)?;
+        }
+        assert_eq!(btree.all(&buffer_pool)?.len(), present.len());
+
+        // Emptying the tree shrinks it back to a single leaf and frees every
+        // other page for reuse
+        for key in present {
+            btree.delete(key, &buffer_pool)?;
+            btree.verify(&buffer_pool)?;
+        }
+        let root =
+            BTreeNode::deserialize(&buffer_pool.get_page(btree.root_page_id())?.page().data)?;
+        assert!(root.is_leaf && root.entries.is_empty());
+        let next_page_id = buffer_pool.header().next_page_id;
+        for key in 0..100 {
+            btree.insert(key, Value::String("v".repeat(200)), &buffer_pool)?;
+        }
+        btree.verify(&buffer_pool)?;
+        assert_eq!(buffer_pool.header().next_page_id, next_page_id);
+
+        std::fs::remove_file("test_btree_verify.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_delete_replaces_separators() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_separators.db");
+        let disk_manager = DiskManager::new("test_btree_separators.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for key in 0..6000 {
+            btree.insert(key, Value::Integer(key as i64), &buffer_pool)?;
+        }
+        let separators = |btree: &BTree, buffer_pool: &BufferPool| -> Result<Vec<Vec<u8>>> {
+            let mut separators = Vec::new();
+            let mut pending = vec![btree.root_page_id()];
+            while let Some(page_id) = pending.pop() {
+                let node = BTreeNode::deserialize(&buffer_pool.get_page(page_id)?.page().data)?;
+                if !node.is_leaf {
+                    separators.extend(node.keys);
+                    pending.extend(node.children);
+                }
+            }
+            Ok(separators)
+        };
+
+        // Deleting keys that are separators replaces them with their successors
+        let deleted = separators(&btree, &buffer_pool)?;
+        assert!(deleted.len() > 10);
+        for key in &deleted {
+            btree.delete(Key::decode(key)?, &buffer_pool)?;
+            btree.verify(&buffer_pool)?;
+        }
+        let remaining = separators(&btree, &buffer_pool)?;
+        assert!(remaining.iter().all(|key| !deleted.contains(key)));
+        for key in &remaining {
+            assert!(btree.search(Key::decode(key)?, &buffer_pool)?.is_some());
         }
 
-        assert!(
-            btree.search(0, &mut buffer_pool)?.is_none(),
-            "Testing deletion"
+        std::fs::remove_file("test_btree_separators.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_check_reports_corruption() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_check.db");
+        let disk_manager = DiskManager::new("test_btree_check.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        for key in 0..3000 {
+            btree.insert(key, Value::Integer(key as i64), &buffer_pool)?;
+        }
+        btree.insert(3000, Value::String("x".repeat(10_000)), &buffer_pool)?;
+        btree.delete(1500, &buffer_pool)?;
+        let report = btree.check(&buffer_pool)?;
+        assert!(report.is_consistent(), "{}", report);
+        assert!(report.unreachable_pages.is_empty());
+        assert_eq!(report.depth, 2);
+        assert_eq!(report.entries, 3000);
+        assert_eq!(report.overflow_pages, 3);
+        assert_eq!(
+            report.internal_nodes + report.leaves + report.overflow_pages + report.free_pages + 1,
+            buffer_pool.header().next_page_id as usize
+        );
+
+        let write = |node: &BTreeNode, buffer_pool: &BufferPool| {
+            let bytes = node.serialize();
+            buffer_pool.modify_page(node.page_id, |page| {
+                page.data.fill(0);
+                page.data[..bytes.len()].copy_from_slice(&bytes);
+                Ok(())
+            })
+        };
+        let read = |page_id: u32, buffer_pool: &BufferPool| {
+            BTreeNode::deserialize(&buffer_pool.get_page(page_id)?.page().data)
+        };
+
+        // Point the root's second child at its first, unsort the last leaf and
+        // leak a page
+        let mut root = read(btree.root_page_id(), &buffer_pool)?;
+        let lost_leaf = root.children[1];
+        root.children[1] = root.children[0];
+        write(&root, &buffer_pool)?;
+        let mut last = read(*root.children.last().unwrap(), &buffer_pool)?;
+        last.entries.swap(0, 1);
+        write(&last, &buffer_pool)?;
+        let leaked = buffer_pool.new_page()?.page_id();
+
+        let report = btree.check(&buffer_pool)?;
+        assert!(!report.is_consistent());
+        let problems_at = |page_id: u32| -> Vec<&str> {
+            report
+                .violations
+                .iter()
+                .filter(|violation| violation.page_id == page_id)
+                .map(|violation| violation.problem.as_str())
+                .collect()
+        };
+        assert!(problems_at(root.children[0]).contains(&"page is reachable more than once"));
+        assert!(problems_at(last.page_id).contains(&"keys are not in ascending order"));
+        assert!(report.unreachable_pages.contains(&lost_leaf));
+        assert!(report.unreachable_pages.contains(&leaked));
+        assert!(btree.verify(&buffer_pool).is_err());
+
+        std::fs::remove_file("test_btree_check.db")?;
+        Ok(())
+    }
+
+    fn padded(key: i32) -> Value {
+        Value::String(format!("{:0>1000}", key))
+    }
+
+    #[test]
+    fn test_btree_bulk_load() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_bulk_load.db");
+        let disk_manager = DiskManager::new("test_btree_bulk_load.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        let value = |key: i64| match key % 1000 {
+            0 => Value::String("x".repeat(10_000)),
+            _ => Value::Integer(key),
+        };
+        let loaded = btree.bulk_load(
+            (0..20_000).map(|key| (Key::Int(key), value(key))),
+            VersionId::frozen(),
+            &buffer_pool,
+        )?;
+        assert_eq!(loaded, 20_000);
+        btree.verify(&buffer_pool)?;
+        let report = btree.check(&buffer_pool)?;
+        assert_eq!(report.entries, 20_000);
+        assert_eq!(report.overflow_pages, 60);
+        assert_eq!(report.depth, 2);
+        // The empty root the tree started with is the only free page
+        assert_eq!(report.free_pages, 1);
+
+        // Inserting the same keys one at a time leaves leaves half empty
+        let _ = std::fs::remove_file("test_btree_bulk_insert.db");
+        let insert_pool = BufferPool::new(100, DiskManager::new("test_btree_bulk_insert.db")?);
+        let inserted = BTree::new(insert_pool.new_page()?.page_id());
+        inserted.init(&insert_pool)?;
+        for key in 0..20_000 {
+            inserted.insert(key, value(key), &insert_pool)?;
+        }
+        assert!(report.leaves < inserted.check(&insert_pool)?.leaves * 3 / 4);
+
+        assert_eq!(
+            btree.search(12_345, &buffer_pool)?,
+            Some(Value::Integer(12_345))
         );
+        assert_eq!(btree.search(7000, &buffer_pool)?, Some(value(7000)));
+        let keys: Vec<Key> = btree
+            .range(Key::Int(9998)..Key::Int(10_002), None, &buffer_pool)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, (9998..10_002).map(Key::Int).collect::<Vec<_>>());
+
+        // The loaded tree takes ordinary inserts and deletes
+        for key in (0..20_000).step_by(3) {
+            btree.delete(key, &buffer_pool)?;
+        }
+        btree.insert(-1, Value::Integer(-1), &buffer_pool)?;
+        btree.verify(&buffer_pool)?;
+        assert_eq!(btree.search(-1, &buffer_pool)?, Some(Value::Integer(-1)));
+
+        // Only an empty tree can be bulk loaded
+        assert!(matches!(
+            btree.bulk_load(
+                [(Key::Int(30_000), Value::Null)],
+                VersionId::frozen(),
+                &buffer_pool
+            ),
+            Err(DatabaseError::InvalidOperation(_))
+        ));
+
+        std::fs::remove_file("test_btree_bulk_load.db")?;
+        std::fs::remove_file("test_btree_bulk_insert.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_bulk_load_rejects_unsorted_input() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_bulk_unsorted.db");
+        let disk_manager = DiskManager::new("test_btree_bulk_unsorted.db")?;
+        let buffer_pool = BufferPool::new(100, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        let entries = (0..5000)
+            .chain([4999])
+            .map(|key| (Key::Int(key), Value::Integer(key)));
+        assert!(matches!(
+            btree.bulk_load(entries, VersionId::frozen(), &buffer_pool),
+            Err(DatabaseError::InvalidOperation(_))
+        ));
+        // The tree stays empty, with the pages written so far freed again
+        assert_eq!(btree.search(0, &buffer_pool)?, None);
+        assert!(btree.check(&buffer_pool)?.unreachable_pages.is_empty());
+
+        // Likewise for an oversized key after values in overflow chains
+        let entries = (0..3000)
+            .map(|key| {
+                (
+                    Key::Int(key),
+                    Value::String("x".repeat(key as usize % 7 * 1000)),
+                )
+            })
+            .chain([(Key::from("k".repeat(MAX_KEY_SIZE + 1)), Value::Null)]);
+        assert!(matches!(
+            btree.bulk_load(entries, VersionId::frozen(), &buffer_pool),
+            Err(DatabaseError::KeyTooLarge { .. })
+        ));
+        let report = btree.check(&buffer_pool)?;
+        assert!(report.unreachable_pages.is_empty(), "{}", report);
+        assert_eq!(report.entries, 0);
+
+        // It can still be loaded
+        assert_eq!(
+            btree.bulk_load(std::iter::empty(), VersionId::frozen(), &buffer_pool)?,
+            0
+        );
+        let loaded = btree.bulk_load(
+            [(Key::Int(1), Value::Null), (Key::from("a"), Value::Null)],
+            VersionId::frozen(),
+            &buffer_pool,
+        )?;
+        assert_eq!(loaded, 2);
+        assert_eq!(btree.search("a", &buffer_pool)?, Some(Value::Null));
+
+        std::fs::remove_file("test_btree_bulk_unsorted.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_concurrent_access() -> Result<()> {
+        let _ = std::fs::remove_file("test_btree_concurrent.db");
+        let disk_manager = DiskManager::new("test_btree_concurrent.db")?;
+        // Small enough that pages are evicted while other threads hold latches
+        let buffer_pool = BufferPool::new(64, disk_manager);
+        let root_page_id = buffer_pool.new_page()?.page_id();
+        let btree = BTree::new(root_page_id);
+        btree.init(&buffer_pool)?;
+
+        // Keys no writer touches, which readers must always find
+        let stable = 100_000..100_200;
+        for key in stable.clone() {
+            btree.insert(key, Value::Integer(key), &buffer_pool)?;
+        }
+
+        let value = |key: i64| Value::String(format!("{:0>200}", key));
+        let writers_done = AtomicUsize::new(0);
+        std::thread::scope(|scope| -> Result<()> {
+            let mut handles = Vec::new();
+            // Each writer owns the keys congruent to its number, so the writers
+            // split and merge leaves next to each other
+            for writer in 0..4 {
+                let (btree, buffer_pool, writers_done) = (&btree, &buffer_pool, &writers_done);
+                handles.push(scope.spawn(move || -> Result<()> {
+                    let keys = (writer..4000).step_by(4);
+                    for key in keys.clone() {
+                        btree.insert(key, value(key), buffer_pool)?;
+                    }
+                    for key in keys.filter(|key| key % 8 >= 4) {
+                        btree.delete(key, buffer_pool)?;
+                    }
+                    writers_done.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }));
+            }
+            for _ in 0..2 {
+                let (btree, buffer_pool, writers_done) = (&btree, &buffer_pool, &writers_done);
+                let stable = stable.clone();
+                handles.push(scope.spawn(move || -> Result<()> {
+                    while writers_done.load(Ordering::SeqCst) < 4 {
+                        let keys = btree
+                            .cursor(buffer_pool)
+                            .map(|entry| entry.map(|(key, _)| key))
+                            .collect::<Result<Vec<_>>>()?;
+                        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                        let found = keys
+                            .iter()
+                            .filter(|key| matches!(key, Key::Int(key) if stable.contains(key)))
+                            .count();
+                        assert_eq!(found, stable.clone().count());
+                        assert_eq!(
+                            btree.search(stable.start, buffer_pool)?,
+                            Some(Value::Integer(stable.start))
+                        );
+                    }
+                    Ok(())
+                }));
+            }
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            Ok(())
+        })?;
+
+        btree.verify(&buffer_pool)?;
+        let expected: Vec<_> = (0..4000)
+            .filter(|key| key % 8 < 4)
+            .map(|key| (Key::Int(key), value(key)))
+            .chain(stable.map(|key| (Key::Int(key), Value::Integer(key))))
+            .collect();
+        assert_eq!(btree.all(&buffer_pool)?, expected);
 
+        std::fs::remove_file("test_btree_concurrent.db")?;
         Ok(())
     }
 }
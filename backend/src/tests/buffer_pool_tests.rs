@@ -1,26 +1,33 @@
 #[cfg(test)]
 mod tests {
-    use crate::storage::buffer_pool::BufferPool;
+    use crate::storage::buffer_pool::{BufferPool, BufferPoolStats};
     use crate::storage::disk_manager::DiskManager;
-    use crate::storage::error::Result;
+    use crate::storage::error::{DatabaseError, Result};
+    use crate::storage::latch::LatchMode;
     use crate::storage::page::Page;
+    use crate::storage::wal::{Durability, LogRecord, WriteAheadLog};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_buffer_pool_basic_operations() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool.db");
         let disk_manager = DiskManager::new("test_buffer_pool.db")?;
-        let mut buffer_pool = BufferPool::new(15, disk_manager);
+        let buffer_pool = BufferPool::new(15, disk_manager);
 
-        // Test new page creation
+        // Test new page creation; page 0 holds the database header
         let page1 = buffer_pool.new_page()?;
-        assert_eq!(page1.header.page_id, 0);
+        assert_eq!(page1.page_id(), 1);
+        assert!(buffer_pool.get_page(0).is_err());
 
         // Test writing and reading a page
-        let mut write_page = Page::new(0);
+        let mut write_page = Page::new(1);
         write_page.data = vec![1, 2, 3, 4];
-        buffer_pool.write_page(0, write_page)?;
+        buffer_pool.write_page(1, write_page)?;
 
-        let read_page = buffer_pool.get_page(0)?;
-        assert_eq!(read_page.data, vec![1, 2, 3, 4]);
+        let read_page = buffer_pool.get_page(1)?;
+        assert_eq!(read_page.page().data, vec![1, 2, 3, 4]);
 
         // Clean up
         std::fs::remove_file("test_buffer_pool.db")?;
@@ -29,16 +36,264 @@ mod tests {
 
     #[test]
     fn test_buffer_pool_full() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_full.db");
         let disk_manager = DiskManager::new("test_buffer_pool_full.db")?;
-        let mut buffer_pool = BufferPool::new(2, disk_manager);
+        let buffer_pool = BufferPool::new(2, disk_manager);
 
-        let _page1 = buffer_pool.new_page()?;
-        let _page2 = buffer_pool.new_page()?;
+        // Pinned pages cannot be evicted
+        buffer_pool.new_page()?.page_mut().pin();
+        let page2_id = buffer_pool.new_page()?.page_id();
+        buffer_pool.get_page(page2_id)?.page_mut().pin();
         let page3 = buffer_pool.new_page();
 
         assert!(page3.is_err());
 
+        buffer_pool.get_page(page2_id)?.page_mut().unpin()?;
+        assert!(buffer_pool.new_page().is_ok());
+
         std::fs::remove_file("test_buffer_pool_full.db")?;
         Ok(())
     }
+
+    #[test]
+    fn test_buffer_pool_lru_eviction() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_lru.db");
+        let disk_manager = DiskManager::new("test_buffer_pool_lru.db")?;
+        let buffer_pool = BufferPool::new(2, disk_manager);
+
+        let page1 = buffer_pool.new_page()?.page_id();
+        let page2 = buffer_pool.new_page()?.page_id();
+
+        // Dirty page 1 in the pool only, leaving page 2 the least recently used
+        {
+            let frame = buffer_pool.get_page(page1)?;
+            let mut page = frame.page_mut();
+            page.data[0] = 42;
+            page.mark_dirty();
+        }
+        buffer_pool.get_page(page1)?;
+
+        // Allocating page 3 evicts page 2, reading page 2 back evicts page 1,
+        // which has to be written back first
+        let page3 = buffer_pool.new_page()?.page_id();
+        buffer_pool.get_page(page3)?;
+        buffer_pool.get_page(page2)?;
+        assert_eq!(buffer_pool.get_page(page1)?.page().data[0], 42);
+
+        assert_eq!(
+            buffer_pool.stats(),
+            BufferPoolStats {
+                hits: 3,
+                misses: 2,
+                evictions: 3,
+                writes: 1,
+            }
+        );
+
+        std::fs::remove_file("test_buffer_pool_lru.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirty_pages_wait_for_the_log() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_wal_rule.db");
+        let _ = std::fs::remove_file("test_buffer_pool_wal_rule.wal");
+        let disk_manager = DiskManager::new("test_buffer_pool_wal_rule.db")?;
+        let mut buffer_pool = BufferPool::new(1, disk_manager);
+        let wal = WriteAheadLog::with_durability("test_buffer_pool_wal_rule.wal", Durability::Os)?;
+        let wal = Arc::new(Mutex::new(wal));
+        let flusher = wal.lock().unwrap().flusher();
+        buffer_pool.set_wal(Arc::clone(&wal));
+
+        let page_id = buffer_pool.new_page()?.page_id();
+        wal.lock().unwrap().log(LogRecord::Begin(1))?;
+        buffer_pool.set_current_txn(Some(1));
+        buffer_pool.modify_page(page_id, |page| {
+            page.data[0] = 7;
+            Ok(())
+        })?;
+        let lsn = buffer_pool.get_page(page_id)?.page().lsn();
+
+        // The change stays in the pool and its log record is not yet durable
+        assert_eq!(buffer_pool.stats().writes, 0);
+        assert!(flusher.flushed_sequence() < lsn);
+
+        // Evicting the page forces the log first, then writes the page back
+        buffer_pool.new_page()?;
+        assert!(flusher.flushed_sequence() >= lsn);
+        assert_eq!(buffer_pool.stats().writes, 1);
+        assert_eq!(buffer_pool.get_page(page_id)?.page().data[0], 7);
+
+        // The page LSN is stored in the page header on disk
+        let mut disk_manager = DiskManager::new("test_buffer_pool_wal_rule.db")?;
+        assert_eq!(disk_manager.read_page(page_id)?.lsn(), lsn);
+
+        std::fs::remove_file("test_buffer_pool_wal_rule.db")?;
+        std::fs::remove_file("test_buffer_pool_wal_rule.wal")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_eviction_does_not_block_the_pool() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_evict_unlocked.db");
+        let _ = std::fs::remove_file("test_buffer_pool_evict_unlocked.wal");
+        let disk_manager = DiskManager::new("test_buffer_pool_evict_unlocked.db")?;
+        let mut buffer_pool = BufferPool::new(2, disk_manager);
+        // Every forced log write takes at least half a second
+        let delay = Duration::from_millis(500);
+        let wal = WriteAheadLog::with_durability(
+            "test_buffer_pool_evict_unlocked.wal",
+            Durability::Group { delay },
+        )?;
+        let wal = Arc::new(Mutex::new(wal));
+        buffer_pool.set_wal(Arc::clone(&wal));
+
+        let dirty = buffer_pool.new_page()?.page_id();
+        let clean = buffer_pool.new_page()?.page_id();
+        wal.lock().unwrap().log(LogRecord::Begin(1))?;
+        buffer_pool.set_current_txn(Some(1));
+        buffer_pool.modify_page(dirty, |page| {
+            page.data[0] = 1;
+            Ok(())
+        })?;
+        buffer_pool.set_current_txn(None);
+        buffer_pool.get_page(clean)?;
+
+        // Making room writes back the dirty page, waiting for the log meanwhile;
+        // the cached page stays readable throughout
+        let pool = &buffer_pool;
+        thread::scope(|scope| -> Result<()> {
+            let started = Instant::now();
+            let evicting = scope.spawn(move || pool.new_page().map(|_| started.elapsed()));
+            while started.elapsed() < delay / 2 {
+                let read = Instant::now();
+                pool.get_page(clean)?;
+                assert!(read.elapsed() < delay / 2);
+            }
+            assert!(!evicting.is_finished());
+            assert!(evicting.join().unwrap()? >= delay);
+            Ok(())
+        })?;
+        assert_eq!(buffer_pool.stats().writes, 1);
+
+        std::fs::remove_file("test_buffer_pool_evict_unlocked.db")?;
+        std::fs::remove_file("test_buffer_pool_evict_unlocked.wal")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_freed_pages_are_reused() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_free_list.db");
+        {
+            let buffer_pool =
+                BufferPool::new(10, DiskManager::new("test_buffer_pool_free_list.db")?);
+            for _ in 0..3 {
+                buffer_pool.new_page()?;
+            }
+            buffer_pool.free_page(2)?;
+            buffer_pool.free_page(3)?;
+            assert!(buffer_pool.free_page(0).is_err());
+            assert!(buffer_pool.free_page(4).is_err());
+            assert_eq!(buffer_pool.free_page_ids()?, vec![3, 2]);
+            buffer_pool.flush()?;
+        }
+
+        let buffer_pool = BufferPool::new(10, DiskManager::new("test_buffer_pool_free_list.db")?);
+        assert_eq!(buffer_pool.free_page_ids()?, vec![3, 2]);
+        assert_eq!(buffer_pool.new_page()?.page_id(), 3);
+        assert_eq!(buffer_pool.new_page()?.page_id(), 2);
+        assert_eq!(buffer_pool.new_page()?.page_id(), 4);
+
+        // Reused pages come back empty
+        assert!(buffer_pool.get_page(3)?.page().data.iter().all(|&b| b == 0));
+
+        std::fs::remove_file("test_buffer_pool_free_list.db")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_freeing_waits_for_the_log() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_free_wal.db");
+        let _ = std::fs::remove_file("test_buffer_pool_free_wal.wal");
+        let mut buffer_pool =
+            BufferPool::new(10, DiskManager::new("test_buffer_pool_free_wal.db")?);
+        let wal = WriteAheadLog::with_durability("test_buffer_pool_free_wal.wal", Durability::Os)?;
+        let wal = Arc::new(Mutex::new(wal));
+        let flusher = wal.lock().unwrap().flusher();
+        buffer_pool.set_wal(Arc::clone(&wal));
+
+        let page_id = buffer_pool.new_page()?.page_id();
+        buffer_pool.modify_page(page_id, |page| {
+            page.data[0] = 7;
+            Ok(())
+        })?;
+        buffer_pool.flush()?;
+
+        wal.lock().unwrap().log(LogRecord::Begin(1))?;
+        buffer_pool.set_current_txn(Some(1));
+        buffer_pool.free_page(page_id)?;
+        let lsn = buffer_pool.get_page(page_id)?.page().lsn();
+        assert!(flusher.flushed_sequence() < lsn);
+
+        // Neither the freed page nor the header has reached the file yet
+        let mut disk_manager = DiskManager::new("test_buffer_pool_free_wal.db")?;
+        assert_eq!(disk_manager.header().free_list_head, 0);
+        assert_eq!(disk_manager.read_page(page_id)?.data[0], 7);
+
+        // Flushing forces the log first, then writes both back
+        buffer_pool.flush()?;
+        assert!(flusher.flushed_sequence() > lsn);
+        let mut disk_manager = DiskManager::new("test_buffer_pool_free_wal.db")?;
+        assert_eq!(disk_manager.header().free_list_head, page_id);
+        assert_eq!(disk_manager.read_page(page_id)?.data[0..4], [0; 4]);
+
+        std::fs::remove_file("test_buffer_pool_free_wal.db")?;
+        std::fs::remove_file("test_buffer_pool_free_wal.wal")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_latches() -> Result<()> {
+        let _ = std::fs::remove_file("test_buffer_pool_latches.db");
+        let disk_manager = DiskManager::new("test_buffer_pool_latches.db")?;
+        let buffer_pool = BufferPool::new(2, disk_manager);
+        let page_id = buffer_pool.new_page()?.page_id();
+        let pool = &buffer_pool;
+
+        // Readers share a latch
+        let shared = pool.latch(page_id, LatchMode::Shared)?;
+        thread::scope(|scope| {
+            scope
+                .spawn(|| pool.latch(page_id, LatchMode::Shared).map(drop))
+                .join()
+        })
+        .unwrap()?;
+
+        // A writer waits until the last reader lets go
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            let writer = scope.spawn(move || -> Result<()> {
+                let _exclusive = pool.latch(page_id, LatchMode::Exclusive)?;
+                sender.send(()).unwrap();
+                Ok(())
+            });
+            assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(shared);
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+            writer.join().unwrap()
+        })?;
+
+        // A latched page is never evicted
+        let latched = pool.latch(page_id, LatchMode::Exclusive)?;
+        let _in_use = pool.new_page()?;
+        assert!(matches!(
+            pool.new_page(),
+            Err(DatabaseError::BufferPoolFull)
+        ));
+        drop(latched);
+        assert!(pool.new_page().is_ok());
+
+        std::fs::remove_file("test_buffer_pool_latches.db")?;
+        Ok(())
+    }
 }
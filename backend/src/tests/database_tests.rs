@@ -1,16 +1,20 @@
 #[cfg(test)]
 mod tests {
-    use std::fs;
 
-    use crate::database_handler::database_handler::Database;
+    use crate::btree::MAX_KEY_SIZE;
+    use crate::database_handler::database_handler::{Database, DatabaseConfig};
+    use crate::server::parser::parse_import;
+    use crate::storage::key::Key;
     use crate::storage::value::Value;
+    use crate::storage::wal::Durability;
+    use crate::tests::remove_test_database;
 
     #[test]
     fn test_database_operations() -> Result<(), Box<dyn std::error::Error>> {
         let test_db_path = "test_db_ops.db";
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
-        let mut db = Database::new(test_db_path)?;
+        let db = Database::new(test_db_path)?;
 
         db.insert(1, &Value::Integer(100))?;
         assert_eq!(db.get(1)?, Some(Value::Integer(100)));
@@ -18,7 +22,259 @@ mod tests {
         db.delete(1)?;
         assert_eq!(db.get(1)?, None);
 
-        fs::remove_file(test_db_path)?;
+        remove_test_database(test_db_path);
         Ok(())
     }
+
+    #[test]
+    fn test_commit_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_commit_stats.db";
+        remove_test_database(test_db_path);
+
+        let config = DatabaseConfig {
+            durability: Durability::Always,
+            ..Default::default()
+        };
+        let db = Database::open(test_db_path, config)?;
+        db.insert(1, &Value::Integer(1))?;
+        db.insert(2, &Value::Integer(2))?;
+
+        let stats = db.wal_stats();
+        assert_eq!(stats.commits, 2);
+        assert_eq!(stats.fsyncs, 2);
+        assert!(stats.average_commit_latency() > std::time::Duration::ZERO);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_after_root_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_reopen_root.db";
+        remove_test_database(test_db_path);
+
+        {
+            let db = Database::new(test_db_path)?;
+            // Values large enough to split the leaves several times
+            for key in 0..50 {
+                db.insert(key, &padded(key))?;
+            }
+        }
+
+        let remaining = {
+            let db = Database::new(test_db_path)?;
+            for key in 0..50 {
+                assert_eq!(db.get(key)?, Some(padded(key)));
+            }
+            // Deletes rebalance and merge nodes under the root once vacuumed
+            for key in 0..45 {
+                db.delete(key)?;
+            }
+            db.vacuum()?;
+            db.all()?
+        };
+
+        let db = Database::new(test_db_path)?;
+        assert_eq!(db.all()?, remaining);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries, 5);
+        assert_eq!(db.get(49)?, Some(padded(49)));
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_iteration() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_iteration.db";
+        remove_test_database(test_db_path);
+
+        let db = Database::new(test_db_path)?;
+        for key in 0..50 {
+            db.insert(key, &padded(key))?;
+        }
+        db.insert("alice", &Value::Integer(1))?;
+
+        let keys = |pairs: Vec<(Key, Value)>| -> Vec<Key> {
+            pairs.into_iter().map(|(key, _)| key).collect()
+        };
+        let all = db.iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(all, db.all()?);
+        assert_eq!(all.len(), 51);
+        assert_eq!(all[50], (Key::from("alice"), Value::Integer(1)));
+
+        assert_eq!(
+            keys(
+                db.range(Key::from(10)..Key::from(13))
+                    .collect::<Result<_, _>>()?
+            ),
+            vec![Key::Int(10), Key::Int(11), Key::Int(12)]
+        );
+        assert_eq!(
+            keys(
+                db.rev_range(Key::from(10)..=Key::from(12))
+                    .collect::<Result<_, _>>()?
+            ),
+            vec![Key::Int(12), Key::Int(11), Key::Int(10)]
+        );
+        assert_eq!(
+            keys(
+                db.rev_range(Key::from(47)..)
+                    .take(4)
+                    .collect::<Result<_, _>>()?
+            ),
+            vec![Key::from("alice"), Key::Int(49), Key::Int(48), Key::Int(47)]
+        );
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_bulk_load() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_bulk_load.db";
+        remove_test_database(test_db_path);
+
+        let contents = "3 three\n\n\"bob\" 2.5\n1 one\n3 \"3 again\"\n0x01 true\n";
+        let pairs = parse_import(contents)?;
+        assert_eq!(
+            pairs,
+            vec![
+                (Key::Int(1), Value::String("one".to_string())),
+                (Key::Int(3), Value::String("\"3 again\"".to_string())),
+                (Key::from("bob"), Value::Float(2.5)),
+                (Key::Bytes(vec![1]), Value::Boolean(true)),
+            ]
+        );
+        assert!(parse_import("1 one\n2\n").is_err());
+        // Errors name the line but never echo the file
+        let err = parse_import("1 one\nsecret 1\n").unwrap_err().to_string();
+        assert_eq!(err, "Line 2: invalid key");
+        let long_key = format!("1 one\n\"{}\" 2\n", "k".repeat(MAX_KEY_SIZE));
+        let err = parse_import(&long_key).unwrap_err().to_string();
+        assert!(err.starts_with("Line 2: key of"), "{}", err);
+
+        {
+            let db = Database::new(test_db_path)?;
+            let pairs = (0..5000).map(|key| (Key::Int(key), padded(key as i32)));
+            // A transaction that began before the load does not see it
+            let mut earlier = db.session();
+            earlier.begin()?;
+            assert_eq!(db.bulk_load(pairs)?, 5000);
+            assert!(db.bulk_load(vec![(Key::Int(9000), Value::Null)]).is_err());
+            assert_eq!(earlier.get(4321)?, None);
+            assert!(earlier.update(4321, &Value::Null).is_err());
+            earlier.rollback()?;
+            assert_eq!(db.session().get(4321)?, Some(padded(4321)));
+        }
+
+        // The load is logged like any other change
+        let db = Database::new(test_db_path)?;
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries, 5000);
+        assert_eq!(db.get(4321)?, Some(padded(4321)));
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_concurrent_writers() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_concurrent.db";
+        remove_test_database(test_db_path);
+
+        {
+            let config = DatabaseConfig {
+                durability: Durability::Os,
+                ..Default::default()
+            };
+            let db = Database::open(test_db_path, config)?;
+            std::thread::scope(|scope| {
+                for writer in 0..4 {
+                    let db = &db;
+                    scope.spawn(move || {
+                        for key in (writer..800).step_by(4) {
+                            db.insert(key, &padded(key)).unwrap();
+                        }
+                        for key in (writer..800).step_by(4).filter(|key| key % 3 == 0) {
+                            db.delete(key).unwrap();
+                        }
+                    });
+                }
+                // Checkpoints wait for the transactions in flight and run between them
+                scope.spawn(|| {
+                    for _ in 0..5 {
+                        db.checkpoint().unwrap();
+                    }
+                });
+            });
+        }
+
+        let db = Database::new(test_db_path)?;
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        let expected: Vec<_> = (0..800)
+            .filter(|key| key % 3 != 0)
+            .map(|key| (Key::Int(key as i64), padded(key)))
+            .collect();
+        assert_eq!(db.all()?, expected);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let test_db_path = "test_db_sessions.db";
+        remove_test_database(test_db_path);
+
+        {
+            let db = Database::new(test_db_path)?;
+            db.insert(1, &Value::Integer(100))?;
+
+            let mut session = db.session();
+            session.begin()?;
+            session.update(1, &Value::Integer(70))?;
+            session.insert(2, &Value::Integer(30))?;
+            // Checkpoints run between the statements of an open transaction
+            db.checkpoint()?;
+            session.delete(1)?;
+            assert_eq!(session.get(1)?, None);
+            assert_eq!(session.all()?, vec![(Key::Int(2), Value::Integer(30))]);
+            assert_eq!(db.get(1)?, Some(Value::Integer(100)));
+            assert_eq!(db.get(2)?, None);
+            session.commit()?;
+            assert!(session.commit().is_err());
+            assert_eq!(db.all()?, vec![(Key::Int(2), Value::Integer(30))]);
+
+            session.begin()?;
+            session.insert(3, &Value::Integer(3))?;
+            session.rollback()?;
+            assert_eq!(db.get(3)?, None);
+
+            let mut abandoned = db.session();
+            abandoned.begin()?;
+            abandoned.insert(4, &Value::Integer(4))?;
+            drop(abandoned);
+            db.insert(4, &Value::Integer(40))?;
+        }
+
+        let db = Database::new(test_db_path)?;
+        assert_eq!(
+            db.all()?,
+            vec![
+                (Key::Int(2), Value::Integer(30)),
+                (Key::Int(4), Value::Integer(40))
+            ]
+        );
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    fn padded(key: i32) -> Value {
+        Value::String(format!("{:0>500}", key))
+    }
 }
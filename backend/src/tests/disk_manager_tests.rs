@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::storage::disk_manager::DiskManager;
+    use crate::storage::error::{DatabaseError, Result};
+    use crate::storage::page::{Page, PAGE_SIZE};
+
+    #[test]
+    fn test_header_survives_reopen() -> Result<()> {
+        let db_path = "test_disk_header.db";
+        let _ = fs::remove_file(db_path);
+
+        {
+            let mut disk_manager = DiskManager::new(db_path)?;
+            assert_eq!(disk_manager.header().next_page_id, 1);
+
+            let mut page = Page::new(2);
+            page.data = vec![7, 8, 9];
+            disk_manager.write_page(&page)?;
+            let mut header = disk_manager.header().clone();
+            header.next_page_id = 3;
+            header.root_page_id = 2;
+            disk_manager.write_header(&header)?;
+        }
+
+        let mut disk_manager = DiskManager::new(db_path)?;
+        assert_eq!(disk_manager.header().page_size as usize, PAGE_SIZE);
+        assert_eq!(disk_manager.header().next_page_id, 3);
+        assert_eq!(disk_manager.header().root_page_id, 2);
+        assert_eq!(&disk_manager.read_page(2)?.data[..3], &[7, 8, 9]);
+        assert!(matches!(
+            disk_manager.read_page(3),
+            Err(DatabaseError::PageNotFound(3))
+        ));
+
+        fs::remove_file(db_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_open() -> Result<()> {
+        let db_path = "test_disk_read_only.db";
+        let _ = fs::remove_file(db_path);
+
+        assert!(DiskManager::open_read_only(db_path).is_err());
+        assert!(!std::path::Path::new(db_path).exists());
+
+        {
+            let mut disk_manager = DiskManager::new(db_path)?;
+            let mut page = Page::new(1);
+            page.data = vec![1, 2, 3];
+            disk_manager.write_page(&page)?;
+        }
+        let contents = fs::read(db_path)?;
+
+        let mut disk_manager = DiskManager::open_read_only(db_path)?;
+        assert_eq!(&disk_manager.read_page(1)?.data[..3], &[1, 2, 3]);
+        assert!(disk_manager.write_page(&Page::new(1)).is_err());
+        let header = disk_manager.header().clone();
+        assert!(disk_manager.write_header(&header).is_err());
+        assert_eq!(fs::read(db_path)?, contents);
+
+        fs::remove_file(db_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_foreign_file() -> Result<()> {
+        let db_path = "test_disk_foreign.db";
+        fs::write(db_path, vec![0xAB; PAGE_SIZE])?;
+
+        assert!(DiskManager::new(db_path).is_err());
+
+        fs::remove_file(db_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_detects_corrupted_page() -> Result<()> {
+        let db_path = "test_disk_checksum.db";
+        let _ = fs::remove_file(db_path);
+
+        {
+            let mut disk_manager = DiskManager::new(db_path)?;
+            for page_id in 1..=2 {
+                let mut page = Page::new(page_id);
+                page.data.fill(page_id as u8);
+                disk_manager.write_page(&page)?;
+            }
+        }
+
+        // Flip a bit in page 1 and tear page 2 by losing its second half
+        let mut contents = fs::read(db_path)?;
+        contents[PAGE_SIZE + 100] ^= 1;
+        contents[2 * PAGE_SIZE + PAGE_SIZE / 2..3 * PAGE_SIZE].fill(0);
+        fs::write(db_path, contents)?;
+
+        let mut disk_manager = DiskManager::new(db_path)?;
+        for page_id in 1..=2 {
+            assert!(matches!(
+                disk_manager.read_page(page_id),
+                Err(DatabaseError::Corruption { page_id: id }) if id == page_id
+            ));
+        }
+
+        fs::remove_file(db_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::database_handler::database_handler::{Database, DatabaseConfig, Session};
+    use crate::storage::key::Key;
+    use crate::storage::transaction::IsolationLevel;
+    use crate::storage::value::Value;
+    use crate::tests::remove_test_database;
+
+    type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+    const LEVELS: [IsolationLevel; 3] = [
+        IsolationLevel::ReadCommitted,
+        IsolationLevel::RepeatableRead,
+        IsolationLevel::Serializable,
+    ];
+
+    fn open(name: &str) -> Result<Database> {
+        remove_test_database(name);
+        let config = DatabaseConfig {
+            lock_timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        Database::open(name, config)
+    }
+
+    fn begin(db: &Database, isolation: IsolationLevel) -> Result<Session<'_>> {
+        let mut session = db.session();
+        session.begin_with_isolation(isolation)?;
+        Ok(session)
+    }
+
+    fn int(session: &mut Session, key: i64) -> Result<i64> {
+        match session.get(key)? {
+            Some(Value::Integer(n)) => Ok(n),
+            other => Err(format!("Expected an integer, got {:?}", other).into()),
+        }
+    }
+
+    /// Sums the integers with keys in `start..end`.
+    fn sum(session: &mut Session, start: i64, end: i64) -> Result<i64> {
+        let mut total = 0;
+        for pair in session.range(Key::Int(start)..Key::Int(end))? {
+            if let (_, Value::Integer(n)) = pair? {
+                total += n;
+            }
+        }
+        Ok(total)
+    }
+
+    #[test]
+    fn test_parse_isolation_level() {
+        assert_eq!(
+            "read  committed".parse::<IsolationLevel>().unwrap(),
+            IsolationLevel::ReadCommitted
+        );
+        assert_eq!(
+            "REPEATABLE READ".parse::<IsolationLevel>().unwrap(),
+            IsolationLevel::RepeatableRead
+        );
+        assert_eq!(
+            "Serializable".parse::<IsolationLevel>().unwrap(),
+            IsolationLevel::Serializable
+        );
+        assert!("READ UNCOMMITTED".parse::<IsolationLevel>().is_err());
+    }
+
+    #[test]
+    fn test_no_dirty_reads() -> Result<()> {
+        let db = open("test_isolation_dirty_read.db")?;
+        db.insert(1, &Value::Integer(100))?;
+
+        for isolation in LEVELS {
+            let mut writer = db.session();
+            writer.begin()?;
+            writer.update(1, &Value::Integer(-1))?;
+
+            let mut reader = begin(&db, isolation)?;
+            assert_eq!(int(&mut reader, 1)?, 100, "{:?}", isolation);
+            writer.rollback()?;
+            assert_eq!(int(&mut reader, 1)?, 100, "{:?}", isolation);
+            reader.commit()?;
+        }
+
+        remove_test_database("test_isolation_dirty_read.db");
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_repeatable_read() -> Result<()> {
+        let db = open("test_isolation_repeatable.db")?;
+        db.insert(1, &Value::Integer(100))?;
+
+        // READ COMMITTED sees a commit made after the transaction began
+        let mut reader = begin(&db, IsolationLevel::ReadCommitted)?;
+        assert_eq!(int(&mut reader, 1)?, 100);
+        db.update(1, &Value::Integer(101))?;
+        assert_eq!(int(&mut reader, 1)?, 101);
+        reader.commit()?;
+
+        // REPEATABLE READ keeps reading its snapshot
+        let mut reader = begin(&db, IsolationLevel::RepeatableRead)?;
+        assert_eq!(int(&mut reader, 1)?, 101);
+        db.update(1, &Value::Integer(102))?;
+        assert_eq!(int(&mut reader, 1)?, 101);
+        reader.commit()?;
+
+        // A serializable reader keeps others from writing what it read
+        let mut reader = begin(&db, IsolationLevel::Serializable)?;
+        assert_eq!(int(&mut reader, 1)?, 102);
+        let mut writer = begin(&db, IsolationLevel::Serializable)?;
+        let err = writer.update(1, &Value::Integer(0)).unwrap_err();
+        assert!(err.to_string().contains("Timed out"), "{}", err);
+        assert_eq!(int(&mut reader, 1)?, 102);
+        reader.commit()?;
+        writer.update(1, &Value::Integer(0))?;
+        writer.commit()?;
+
+        remove_test_database("test_isolation_repeatable.db");
+        Ok(())
+    }
+
+    /// Two transactions each read a balance and write it back plus their deposit.
+    #[test]
+    fn test_lost_update() -> Result<()> {
+        let db = open("test_isolation_lost_update.db")?;
+
+        // READ COMMITTED lets the second write land on top of the first
+        db.insert(1, &Value::Integer(100))?;
+        let mut t1 = begin(&db, IsolationLevel::ReadCommitted)?;
+        let mut t2 = begin(&db, IsolationLevel::ReadCommitted)?;
+        let (b1, b2) = (int(&mut t1, 1)?, int(&mut t2, 1)?);
+        t1.update(1, &Value::Integer(b1 + 10))?;
+        t1.commit()?;
+        t2.update(1, &Value::Integer(b2 + 20))?;
+        t2.commit()?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(120)));
+
+        // REPEATABLE READ refuses to write over a version the snapshot missed
+        db.update(1, &Value::Integer(100))?;
+        let mut t1 = begin(&db, IsolationLevel::RepeatableRead)?;
+        let mut t2 = begin(&db, IsolationLevel::RepeatableRead)?;
+        let (b1, b2) = (int(&mut t1, 1)?, int(&mut t2, 1)?);
+        t1.update(1, &Value::Integer(b1 + 10))?;
+        t1.commit()?;
+        let err = t2.update(1, &Value::Integer(b2 + 20)).unwrap_err();
+        assert!(err.to_string().contains("another transaction"), "{}", err);
+        t2.rollback()?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(110)));
+
+        // SERIALIZABLE: both hold shared locks, so both upgrades wait on each
+        // other; whichever comes second closes the cycle, and either way the
+        // younger transaction is rolled back
+        db.update(1, &Value::Integer(100))?;
+        let mut t1 = begin(&db, IsolationLevel::Serializable)?;
+        let mut t2 = begin(&db, IsolationLevel::Serializable)?;
+        let (b1, b2) = (int(&mut t1, 1)?, int(&mut t2, 1)?);
+        let ready = Barrier::new(2);
+        thread::scope(|s| -> Result<()> {
+            let first = s.spawn(|| {
+                ready.wait();
+                t1.update(1, &Value::Integer(b1 + 10))
+                    .and_then(|_| t1.commit())
+                    .map_err(|e| e.to_string())
+            });
+            ready.wait();
+            let err = t2.update(1, &Value::Integer(b2 + 20)).unwrap_err();
+            assert!(err.to_string().contains("Deadlock"), "{}", err);
+            assert!(!t2.in_transaction());
+            first.join().unwrap()?;
+            Ok(())
+        })?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(110)));
+
+        // A write made outside any lock is still not lost: the serializable
+        // write is checked against the snapshot the balance was read through
+        db.update(1, &Value::Integer(100))?;
+        let mut t1 = begin(&db, IsolationLevel::Serializable)?;
+        let b1 = int(&mut t1, 1)?;
+        db.update(1, &Value::Integer(50))?;
+        let err = t1.update(1, &Value::Integer(b1 + 10)).unwrap_err();
+        assert!(err.to_string().contains("another transaction"), "{}", err);
+        t1.rollback()?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(50)));
+
+        remove_test_database("test_isolation_lost_update.db");
+        Ok(())
+    }
+
+    /// Two doctors are on call; each goes off call if the other still is.
+    #[test]
+    fn test_write_skew() -> Result<()> {
+        let db = open("test_isolation_write_skew.db")?;
+        let go_off_call = |session: &mut Session, me: i64| -> Result<()> {
+            if int(session, 1)? + int(session, 2)? == 2 {
+                session.update(me, &Value::Integer(0))?;
+            }
+            Ok(())
+        };
+
+        // Without locks both go, leaving no one on call
+        for isolation in [
+            IsolationLevel::ReadCommitted,
+            IsolationLevel::RepeatableRead,
+        ] {
+            db.insert(1, &Value::Integer(1))?;
+            db.insert(2, &Value::Integer(1))?;
+            let mut alice = begin(&db, isolation)?;
+            let mut bob = begin(&db, isolation)?;
+            let (a1, a2) = (int(&mut alice, 1)?, int(&mut alice, 2)?);
+            go_off_call(&mut bob, 2)?;
+            bob.commit()?;
+            if a1 + a2 == 2 {
+                alice.update(1, &Value::Integer(0))?;
+            }
+            alice.commit()?;
+            let mut check = db.session();
+            assert_eq!(
+                int(&mut check, 1)? + int(&mut check, 2)?,
+                0,
+                "{:?}",
+                isolation
+            );
+            db.delete(1)?;
+            db.delete(2)?;
+        }
+
+        // Under SERIALIZABLE the two writes wait on each other's reads; one of the
+        // doctors is rolled back and stays on call
+        db.insert(1, &Value::Integer(1))?;
+        db.insert(2, &Value::Integer(1))?;
+        let mut alice = begin(&db, IsolationLevel::Serializable)?;
+        let mut bob = begin(&db, IsolationLevel::Serializable)?;
+        assert_eq!(int(&mut alice, 1)? + int(&mut alice, 2)?, 2);
+        assert_eq!(int(&mut bob, 1)? + int(&mut bob, 2)?, 2);
+        let ready = Barrier::new(2);
+        thread::scope(|s| -> Result<()> {
+            let first = s.spawn(|| {
+                ready.wait();
+                alice
+                    .update(1, &Value::Integer(0))
+                    .and_then(|_| alice.commit())
+                    .map_err(|e| e.to_string())
+            });
+            ready.wait();
+            let err = bob.update(2, &Value::Integer(0)).unwrap_err();
+            assert!(err.to_string().contains("Deadlock"), "{}", err);
+            first.join().unwrap()?;
+            Ok(())
+        })?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(0)));
+        assert_eq!(db.get(2)?, Some(Value::Integer(1)));
+
+        // Retried, bob now sees alice is off call and stays
+        let mut bob = begin(&db, IsolationLevel::Serializable)?;
+        go_off_call(&mut bob, 2)?;
+        bob.commit()?;
+        assert_eq!(db.get(2)?, Some(Value::Integer(1)));
+
+        remove_test_database("test_isolation_write_skew.db");
+        Ok(())
+    }
+
+    #[test]
+    fn test_phantoms() -> Result<()> {
+        let db = open("test_isolation_phantoms.db")?;
+        db.insert(1, &Value::Integer(1))?;
+        db.insert(2, &Value::Integer(1))?;
+
+        // A serializable scan keeps other serializable transactions from
+        // inserting into the range it read
+        let mut reader = begin(&db, IsolationLevel::Serializable)?;
+        assert_eq!(sum(&mut reader, 1, 10)?, 2);
+        let mut writer = begin(&db, IsolationLevel::Serializable)?;
+        let err = writer.insert(5, &Value::Integer(1)).unwrap_err();
+        assert!(err.to_string().contains("Timed out"), "{}", err);
+        writer.insert(10, &Value::Integer(1))?;
+        assert_eq!(sum(&mut reader, 1, 10)?, 2);
+        reader.commit()?;
+        writer.insert(5, &Value::Integer(1))?;
+        writer.commit()?;
+        db.delete(5)?;
+        db.delete(10)?;
+
+        // Doctors counted by a scan: each scan locks the other's write out
+        let mut alice = begin(&db, IsolationLevel::Serializable)?;
+        let mut bob = begin(&db, IsolationLevel::Serializable)?;
+        assert_eq!(sum(&mut alice, 1, 3)?, 2);
+        assert_eq!(sum(&mut bob, 1, 3)?, 2);
+        let ready = Barrier::new(2);
+        thread::scope(|s| -> Result<()> {
+            let first = s.spawn(|| {
+                ready.wait();
+                alice
+                    .update(1, &Value::Integer(0))
+                    .and_then(|_| alice.commit())
+                    .map_err(|e| e.to_string())
+            });
+            ready.wait();
+            let err = bob.update(2, &Value::Integer(0)).unwrap_err();
+            assert!(err.to_string().contains("Deadlock"), "{}", err);
+            first.join().unwrap()?;
+            Ok(())
+        })?;
+        assert_eq!(db.get(2)?, Some(Value::Integer(1)));
+
+        // Writers outside any transaction take no locks, so they can still
+        // insert a phantom; a write to a scanned key is refused, though
+        let mut reader = begin(&db, IsolationLevel::Serializable)?;
+        assert_eq!(sum(&mut reader, 1, 10)?, 1);
+        db.insert(5, &Value::Integer(5))?;
+        db.update(2, &Value::Integer(0))?;
+        assert_eq!(sum(&mut reader, 1, 10)?, 5);
+        let err = reader.update(2, &Value::Integer(2)).unwrap_err();
+        assert!(err.to_string().contains("another transaction"), "{}", err);
+        reader.rollback()?;
+
+        remove_test_database("test_isolation_phantoms.db");
+        Ok(())
+    }
+}
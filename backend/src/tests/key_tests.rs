@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod tests {
+    use crate::storage::key::Key;
+
+    #[test]
+    fn test_key_encoding_preserves_order() -> Result<(), Box<dyn std::error::Error>> {
+        let mut keys = vec![
+            Key::Int(i64::MIN),
+            Key::Int(-1),
+            Key::Int(0),
+            Key::Int(1),
+            Key::Int(256),
+            Key::Int(i64::MAX),
+            Key::from(""),
+            Key::from("a"),
+            Key::from("a\0"),
+            Key::from("a\0b"),
+            Key::from("ab"),
+            Key::from("b"),
+            Key::from("jane doe"),
+            Key::Bytes(vec![]),
+            Key::Bytes(vec![0]),
+            Key::Bytes(vec![0, 0]),
+            Key::Bytes(vec![0, 1]),
+            Key::Bytes(vec![1]),
+            Key::Bytes(vec![0xff, 0xff]),
+        ];
+        keys.sort();
+
+        for pair in keys.windows(2) {
+            assert!(
+                pair[0].encode() < pair[1].encode(),
+                "{} should encode below {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        for key in &keys {
+            assert_eq!(&Key::decode(&key.encode())?, key);
+        }
+        assert!(Key::decode(&[2, b'a']).is_err());
+        assert!(Key::decode(&[]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_parsing() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!("-42".parse::<Key>()?, Key::Int(-42));
+        assert_eq!("9000000000".parse::<Key>()?, Key::Int(9_000_000_000));
+        assert_eq!("\"jane doe\"".parse::<Key>()?, Key::from("jane doe"));
+        assert_eq!(
+            r#""say \"hi\" \\""#.parse::<Key>()?,
+            Key::from(r#"say "hi" \"#)
+        );
+        assert_eq!("0x00ff".parse::<Key>()?, Key::Bytes(vec![0x00, 0xff]));
+
+        assert!("jane".parse::<Key>().is_err());
+        assert!("\"unterminated".parse::<Key>().is_err());
+        assert!("0xabc".parse::<Key>().is_err());
+
+        assert_eq!(Key::from("jane").to_string(), "\"jane\"");
+        assert_eq!(Key::Bytes(vec![1, 0xab]).to_string(), "0x01ab");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::database_handler::database_handler::{Database, DatabaseConfig};
+    use crate::storage::error::DatabaseError;
+    use crate::storage::key::Key;
+    use crate::storage::lock_manager::{LockManager, LockMode};
+    use crate::storage::transaction::TransactionId;
+    use crate::tests::remove_test_database;
+
+    type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+    const T1: TransactionId = TransactionId(1);
+    const T2: TransactionId = TransactionId(2);
+    const T3: TransactionId = TransactionId(3);
+
+    fn key(name: &str) -> Key {
+        Key::from(name)
+    }
+
+    #[test]
+    fn test_shared_and_exclusive() -> Result<()> {
+        let locks = LockManager::new(Duration::from_millis(50));
+        locks.acquire(T1, &key("a"), LockMode::Shared)?;
+        locks.acquire(T2, &key("a"), LockMode::Shared)?;
+
+        // Exclusive waits for every other shared holder
+        let err = locks
+            .acquire(T1, &key("a"), LockMode::Exclusive)
+            .unwrap_err();
+        assert!(matches!(err, DatabaseError::LockTimeout(_)));
+        assert_eq!(locks.held_mode(T1, &key("a")), Some(LockMode::Shared));
+
+        locks.release_all(T2);
+        locks.acquire(T1, &key("a"), LockMode::Exclusive)?;
+        // A shared request does not weaken a lock already held
+        locks.acquire(T1, &key("a"), LockMode::Shared)?;
+        assert_eq!(locks.held_mode(T1, &key("a")), Some(LockMode::Exclusive));
+        assert!(locks.acquire(T3, &key("a"), LockMode::Shared).is_err());
+
+        locks.release_all(T1);
+        assert_eq!(locks.held_mode(T1, &key("a")), None);
+        locks.acquire(T3, &key("a"), LockMode::Shared)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_locks() -> Result<()> {
+        let locks = LockManager::new(Duration::from_millis(50));
+        locks.acquire_range(T1, Key::Int(10)..Key::Int(20))?;
+
+        // Keys in the range can be read but not written, even ones not there yet
+        locks.acquire(T2, &Key::Int(12), LockMode::Shared)?;
+        let err = locks
+            .acquire(T2, &Key::Int(15), LockMode::Exclusive)
+            .unwrap_err();
+        assert!(matches!(err, DatabaseError::LockTimeout(Key::Int(15))));
+        locks.acquire(T2, &Key::Int(20), LockMode::Exclusive)?;
+        // The range's holder can write in it
+        locks.acquire(T1, &Key::Int(15), LockMode::Exclusive)?;
+        locks.release_all(T1);
+
+        // A range waits for the keys written in it
+        let err = locks.acquire_range(T3, Key::Int(15)..).unwrap_err();
+        assert!(matches!(err, DatabaseError::LockTimeout(Key::Int(20))));
+        locks.acquire_range(T3, ..Key::Int(20))?;
+        locks.release_all(T2);
+        locks.acquire_range(T3, Key::Int(15)..)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_wakes_waiters() -> Result<()> {
+        let locks = Arc::new(LockManager::new(Duration::from_secs(10)));
+        locks.acquire(T1, &key("a"), LockMode::Exclusive)?;
+
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || {
+                let started = Instant::now();
+                locks
+                    .acquire(T2, &key("a"), LockMode::Exclusive)
+                    .map(|_| started.elapsed())
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        locks.release_all(T1);
+
+        let waited = waiter.join().unwrap()?;
+        assert!(waited >= Duration::from_millis(100));
+        assert!(waited < Duration::from_secs(10));
+        assert_eq!(locks.held_mode(T2, &key("a")), Some(LockMode::Exclusive));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deadlock_aborts_youngest() -> Result<()> {
+        let locks = Arc::new(LockManager::new(Duration::from_secs(10)));
+        locks.acquire(T1, &key("a"), LockMode::Exclusive)?;
+        locks.acquire(T2, &key("b"), LockMode::Exclusive)?;
+        locks.acquire(T3, &key("c"), LockMode::Exclusive)?;
+
+        // T2 waits for T3, and T3 for T1
+        let spawn_waiter = |txn_id, name: &'static str| {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || {
+                let result = locks.acquire(txn_id, &key(name), LockMode::Exclusive);
+                if result.is_err() {
+                    // What rolling back the victim does
+                    locks.release_all(txn_id);
+                }
+                result
+            })
+        };
+        let t2 = spawn_waiter(T2, "c");
+        thread::sleep(Duration::from_millis(50));
+        let t3 = spawn_waiter(T3, "a");
+        thread::sleep(Duration::from_millis(50));
+
+        // T1 closes the cycle; T3 is the youngest in it, so T3 gives way
+        let started = Instant::now();
+        let t1 = spawn_waiter(T1, "b");
+        assert!(matches!(
+            t3.join().unwrap(),
+            Err(DatabaseError::Deadlock(3))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // T2 gets the victim's lock, and T1 T2's once it finishes
+        t2.join().unwrap()?;
+        locks.release_all(T2);
+        t1.join().unwrap()?;
+        assert_eq!(locks.held_mode(T1, &key("b")), Some(LockMode::Exclusive));
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_deadlock() -> Result<()> {
+        let locks = Arc::new(LockManager::new(Duration::from_secs(10)));
+        locks.acquire(T1, &key("a"), LockMode::Shared)?;
+        locks.acquire(T2, &key("a"), LockMode::Shared)?;
+
+        let t1 = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.acquire(T1, &key("a"), LockMode::Exclusive))
+        };
+        thread::sleep(Duration::from_millis(50));
+        // Both want to upgrade, so neither can; the requester is the youngest
+        let err = locks
+            .acquire(T2, &key("a"), LockMode::Exclusive)
+            .unwrap_err();
+        assert!(matches!(err, DatabaseError::Deadlock(2)));
+
+        locks.release_all(T2);
+        t1.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transactions_release_locks() -> Result<()> {
+        let test_db_path = "test_lock_manager.db";
+        remove_test_database(test_db_path);
+
+        let config = DatabaseConfig {
+            lock_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let db = Database::open(test_db_path, config)?;
+        let mut first = db.begin_transaction()?;
+        let mut second = db.begin_transaction()?;
+        first.lock(&Key::Int(1), LockMode::Exclusive)?;
+
+        let started = Instant::now();
+        let err = second.lock(&Key::Int(1), LockMode::Shared).unwrap_err();
+        assert!(matches!(err, DatabaseError::LockTimeout(Key::Int(1))));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // Locks are held until the transaction finishes
+        first.commit()?;
+        second.lock(&Key::Int(1), LockMode::Exclusive)?;
+        second.rollback()?;
+        let mut third = db.begin_transaction()?;
+        third.lock(&Key::Int(1), LockMode::Exclusive)?;
+        third.commit()?;
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+}
@@ -5,10 +5,33 @@ pub mod buffer_pool_tests;
 #[cfg(test)]
 pub mod database_tests;
 #[cfg(test)]
+pub mod disk_manager_tests;
+#[cfg(test)]
+pub mod isolation_tests;
+#[cfg(test)]
+pub mod key_tests;
+#[cfg(test)]
+pub mod lock_manager_tests;
+#[cfg(test)]
+pub mod mvcc_tests;
+#[cfg(test)]
 pub mod protocol_tests;
 #[cfg(test)]
+pub mod recovery_tests;
+#[cfg(test)]
+pub mod rollback_tests;
+#[cfg(test)]
 pub mod server_tests;
 #[cfg(test)]
 pub mod value_tests;
 #[cfg(test)]
 pub mod wal_tests;
+
+/// Deletes a test database and the write-ahead log `Database` keeps next to it,
+/// whichever of them exist.
+#[cfg(test)]
+pub fn remove_test_database(path: &str) {
+    let path = std::path::Path::new(path);
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(path.with_extension("wal"));
+}
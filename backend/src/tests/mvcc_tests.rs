@@ -0,0 +1,302 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use crate::btree::BTree;
+    use crate::database_handler::database_handler::Database;
+    use crate::storage::buffer_pool::BufferPool;
+    use crate::storage::disk_manager::DiskManager;
+    use crate::storage::error::DatabaseError;
+    use crate::storage::key::Key;
+    use crate::storage::mvcc::VersionStore;
+    use crate::storage::operations;
+    use crate::storage::transaction::{Snapshot, Transaction, TransactionManager};
+    use crate::storage::value::Value;
+    use crate::storage::wal::WriteAheadLog;
+    use crate::tests::remove_test_database;
+
+    type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+    /// A B-tree with versions, without the `Database` around it, so tests can
+    /// interleave transactions.
+    struct Store {
+        name: &'static str,
+        buffer_pool: BufferPool,
+        btree: BTree,
+        versions: VersionStore,
+        tm: TransactionManager,
+        wal: Arc<Mutex<WriteAheadLog>>,
+    }
+
+    impl Store {
+        fn open(name: &'static str) -> Result<Self> {
+            let _ = fs::remove_file(format!("{}.db", name));
+            let _ = fs::remove_file(format!("{}.wal", name));
+            let mut buffer_pool = BufferPool::new(100, DiskManager::new(&format!("{}.db", name))?);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::new(format!("{}.wal", name))?));
+            buffer_pool.set_wal(Arc::clone(&wal));
+            let btree = BTree::new(buffer_pool.new_page()?.page_id());
+            btree.init(&buffer_pool)?;
+            Ok(Self {
+                name,
+                buffer_pool,
+                btree,
+                versions: VersionStore::new(),
+                tm: TransactionManager::new(),
+                wal,
+            })
+        }
+
+        fn begin(&self) -> Result<Transaction> {
+            Ok(self.tm.begin_transaction(Arc::clone(&self.wal))?)
+        }
+
+        fn set(&self, txn: &mut Transaction, key: i64, value: Value) -> Result<()> {
+            let (btree, versions, bp) = (&self.btree, &self.versions, &self.buffer_pool);
+            operations::insert(txn, btree, versions, bp, &Key::Int(key), &value)
+        }
+
+        fn delete(&self, txn: &mut Transaction, key: i64) -> Result<()> {
+            let (btree, versions, bp) = (&self.btree, &self.versions, &self.buffer_pool);
+            operations::delete(txn, btree, versions, bp, &Key::Int(key))
+        }
+
+        fn read(&self, snapshot: &Snapshot, key: i64) -> Result<Option<Value>> {
+            let (btree, versions, bp) = (&self.btree, &self.versions, &self.buffer_pool);
+            operations::get(btree, versions, bp, snapshot, &Key::Int(key))
+        }
+
+        fn get(&self, key: i64) -> Result<Option<Value>> {
+            self.read(&self.tm.snapshot(), key)
+        }
+
+        fn collect_garbage(&self) -> Result<usize> {
+            Ok(self.versions.collect_garbage(
+                self.tm.horizon(),
+                |txn_id| self.tm.is_aborted(txn_id),
+                &self.btree,
+                &self.buffer_pool,
+            )?)
+        }
+
+        fn remove(self) -> Result<()> {
+            fs::remove_file(format!("{}.db", self.name))?;
+            fs::remove_file(format!("{}.wal", self.name))?;
+            Ok(())
+        }
+    }
+
+    fn is_conflict(result: Result<()>) -> bool {
+        matches!(
+            result.map_err(|e| e.downcast::<DatabaseError>().map(|e| *e)),
+            Err(Ok(DatabaseError::WriteConflict(_)))
+        )
+    }
+
+    #[test]
+    fn test_snapshot_isolation() -> Result<()> {
+        let s = Store::open("test_mvcc_isolation")?;
+        let mut setup = s.begin()?;
+        s.set(&mut setup, 1, Value::Integer(1))?;
+        setup.commit()?;
+
+        let before = s.tm.snapshot();
+        let mut writer = s.begin()?;
+        let mut other = s.begin()?;
+        s.set(&mut writer, 1, Value::Integer(2))?;
+
+        // Uncommitted writes are seen only by their own transaction
+        assert_eq!(s.read(&writer.snapshot, 1)?, Some(Value::Integer(2)));
+        assert_eq!(s.read(&other.snapshot, 1)?, Some(Value::Integer(1)));
+        assert_eq!(s.get(1)?, Some(Value::Integer(1)));
+
+        // The first writer wins, before and after it commits
+        assert!(is_conflict(s.set(&mut other, 1, Value::Integer(3))));
+        writer.commit()?;
+        assert!(is_conflict(s.delete(&mut other, 1)));
+        other.rollback()?;
+
+        // Older snapshots keep reading the value they started with
+        assert_eq!(s.read(&before, 1)?, Some(Value::Integer(1)));
+        assert_eq!(s.get(1)?, Some(Value::Integer(2)));
+
+        let mut deleter = s.begin()?;
+        s.delete(&mut deleter, 1)?;
+        deleter.commit()?;
+        assert_eq!(s.get(1)?, None);
+        assert_eq!(s.read(&before, 1)?, Some(Value::Integer(1)));
+
+        drop(before);
+        s.remove()
+    }
+
+    #[test]
+    fn test_rollback_hides_writes() -> Result<()> {
+        let s = Store::open("test_mvcc_rollback")?;
+        let mut setup = s.begin()?;
+        for key in 0..3 {
+            s.set(&mut setup, key, Value::Integer(key))?;
+        }
+        setup.commit()?;
+
+        let mut txn = s.begin()?;
+        s.set(&mut txn, 0, Value::Integer(100))?;
+        s.delete(&mut txn, 1)?;
+        s.set(&mut txn, 5, Value::Integer(5))?;
+        operations::rollback(&mut txn, &s.btree, &s.versions, &s.buffer_pool)?;
+        assert!(s.versions.is_empty());
+        assert_eq!(s.btree.all(&s.buffer_pool)?.len(), 3);
+
+        assert_eq!(s.get(0)?, Some(Value::Integer(0)));
+        assert_eq!(s.get(1)?, Some(Value::Integer(1)));
+        assert_eq!(s.get(5)?, None);
+
+        // Later writers build on the versions from before the rollback
+        let mut next = s.begin()?;
+        s.delete(&mut next, 0)?;
+        s.set(&mut next, 1, Value::Integer(11))?;
+        assert!(s.delete(&mut next, 5).is_err());
+        next.commit()?;
+
+        assert_eq!(s.get(0)?, None);
+        assert_eq!(s.get(1)?, Some(Value::Integer(11)));
+        assert_eq!(s.get(2)?, Some(Value::Integer(2)));
+
+        s.remove()
+    }
+
+    #[test]
+    fn test_scan_sees_consistent_view() -> Result<()> {
+        let s = Store::open("test_mvcc_scan")?;
+        let mut setup = s.begin()?;
+        for key in 0..300 {
+            s.set(&mut setup, key, Value::Integer(key))?;
+        }
+        setup.commit()?;
+        let expected: Vec<_> = (0..300)
+            .map(|key| (Key::Int(key), Value::Integer(key)))
+            .collect();
+
+        let (btree, versions, bp) = (&s.btree, &s.versions, &s.buffer_pool);
+        let mut scan = operations::cursor(btree, versions, bp, s.tm.snapshot(), ..);
+        let mut seen = scan
+            .by_ref()
+            .take(100)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Change every key, before and after the scan's position
+        let mut writer = s.begin()?;
+        for key in 0..300 {
+            if key % 2 == 0 {
+                s.delete(&mut writer, key)?;
+            } else {
+                s.set(&mut writer, key, Value::Integer(-key))?;
+            }
+        }
+        for key in 1000..1100 {
+            s.set(&mut writer, key, Value::Null)?;
+        }
+        writer.commit()?;
+
+        seen.extend(scan.collect::<std::result::Result<Vec<_>, _>>()?);
+        assert_eq!(seen, expected);
+
+        let mut backwards = operations::cursor(btree, versions, bp, s.tm.snapshot(), ..);
+        assert_eq!(
+            backwards.prev().transpose()?,
+            Some((Key::Int(1099), Value::Null))
+        );
+        let now = operations::all(btree, versions, bp, s.tm.snapshot())?;
+        assert_eq!(now.len(), 250);
+        assert_eq!(now[0], (Key::Int(1), Value::Integer(-1)));
+
+        drop(backwards);
+        s.remove()
+    }
+
+    #[test]
+    fn test_garbage_collection() -> Result<()> {
+        let s = Store::open("test_mvcc_gc")?;
+        let mut setup = s.begin()?;
+        for key in 0..10 {
+            s.set(&mut setup, key, Value::Integer(key))?;
+        }
+        setup.commit()?;
+
+        let old = s.tm.snapshot();
+        for round in 1..=3 {
+            let mut txn = s.begin()?;
+            for key in 0..5 {
+                s.set(&mut txn, key, Value::Integer(key * 10 + round))?;
+            }
+            txn.commit()?;
+        }
+        let mut txn = s.begin()?;
+        for key in 5..8 {
+            s.delete(&mut txn, key)?;
+        }
+        txn.commit()?;
+        let mut aborted = s.begin()?;
+        s.set(&mut aborted, 20, Value::Null)?;
+        aborted.rollback()?;
+        assert_eq!(s.versions.len(), 15);
+
+        // Only the rolled-back insert goes while the old snapshot may read the rest
+        assert_eq!(s.collect_garbage()?, 1);
+        assert_eq!(s.versions.len(), 15);
+        for key in 0..10 {
+            assert_eq!(s.read(&old, key)?, Some(Value::Integer(key)));
+        }
+        assert_eq!(s.btree.all(&s.buffer_pool)?.len(), 10);
+
+        drop(old);
+        assert_eq!(s.collect_garbage()?, 15 + 3);
+        assert!(s.versions.is_empty());
+        assert_eq!(s.btree.all(&s.buffer_pool)?.len(), 7);
+        assert_eq!(s.get(3)?, Some(Value::Integer(33)));
+        assert_eq!(s.collect_garbage()?, 0);
+
+        s.remove()
+    }
+
+    #[test]
+    fn test_vacuum_and_reopen() -> Result<()> {
+        let test_db_path = "test_mvcc_reopen.db";
+        remove_test_database(test_db_path);
+
+        let last_txn_id = {
+            let db = Database::new(test_db_path)?;
+            for key in 0..20 {
+                db.insert(key, &Value::Integer(key as i64))?;
+            }
+            for key in 0..10 {
+                db.delete(key)?;
+            }
+            assert_eq!(db.check()?.entries, 20);
+            assert_eq!(db.vacuum()?, 10);
+            assert_eq!(db.check()?.entries, 10);
+
+            // A deletion still in the tree when the log is trimmed
+            db.delete(10)?;
+            db.checkpoint()?;
+            let mut txn = db.begin_transaction()?;
+            txn.rollback()?;
+            txn.id()
+        };
+
+        // Transaction IDs carry on past those in the tree, so its versions
+        // read the same after reopening
+        let db = Database::new(test_db_path)?;
+        let mut txn = db.begin_transaction()?;
+        assert!(txn.id() > last_txn_id);
+        txn.rollback()?;
+        assert_eq!(db.get(10)?, None);
+        assert_eq!(db.get(11)?, Some(Value::Integer(11)));
+        assert_eq!(db.all()?.len(), 9);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use crate::btree::BTree;
+    use crate::database_handler::database_handler::Database;
+    use crate::storage::buffer_pool::BufferPool;
+    use crate::storage::checkpoint;
+    use crate::storage::disk_manager::DiskManager;
+    use crate::storage::error::DatabaseError;
+    use crate::storage::page::{PAGE_HEADER_SIZE, PAGE_SIZE};
+    use crate::storage::recovery;
+    use crate::storage::value::Value;
+    use crate::storage::wal::{LogRecord, WriteAheadLog};
+
+    #[test]
+    fn test_recovery_undoes_unfinished_transaction() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_recovery_undo.db";
+        let wal_path = "test_recovery_undo.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        let (root_page_id, next_page_id) = {
+            let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+            buffer_pool.set_wal(Arc::clone(&wal));
+
+            let root_page_id = buffer_pool.new_page()?.page_id();
+            let btree = BTree::new(root_page_id);
+            btree.init(&buffer_pool)?;
+
+            wal.lock().unwrap().log(LogRecord::Begin(1))?;
+            buffer_pool.set_current_txn(Some(1));
+            btree.insert(1, Value::Integer(10), &buffer_pool)?;
+            btree.insert(2, Value::Integer(20), &buffer_pool)?;
+            buffer_pool.set_current_txn(None);
+            wal.lock().unwrap().log(LogRecord::Commit(1))?;
+            let next_page_id = buffer_pool.header().next_page_id;
+
+            // Crash part-way through a transaction that splits the root
+            wal.lock().unwrap().log(LogRecord::Begin(2))?;
+            buffer_pool.set_current_txn(Some(2));
+            for key in 3..400 {
+                btree.insert(key, Value::Integer(key as i64 * 10), &buffer_pool)?;
+            }
+            assert!(buffer_pool.header().next_page_id > next_page_id);
+            (root_page_id, next_page_id)
+        };
+
+        let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+        buffer_pool.set_wal(Arc::clone(&wal));
+        let report = recovery::recover(&wal, &buffer_pool)?;
+        assert_eq!(report.losers, vec![2]);
+        assert_eq!(report.max_txn_id, 2);
+        // Pages allocated by the split are handed back
+        assert_eq!(buffer_pool.header().next_page_id, next_page_id);
+
+        let btree = BTree::new(root_page_id);
+        assert_eq!(btree.search(1, &buffer_pool)?, Some(Value::Integer(10)));
+        assert_eq!(btree.search(2, &buffer_pool)?, Some(Value::Integer(20)));
+        for key in 3..400 {
+            assert_eq!(btree.search(key, &buffer_pool)?, None);
+        }
+
+        // A second pass must not undo anything again
+        let report = recovery::recover(&wal, &buffer_pool)?;
+        assert!(report.losers.is_empty());
+        assert_eq!(btree.search(2, &buffer_pool)?, Some(Value::Integer(20)));
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_redoes_committed_writes() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_recovery_redo.db";
+        let wal_path = "test_recovery_redo.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        {
+            let db = Database::new(db_path)?;
+            db.insert(1, &Value::Integer(100))?;
+            db.insert(2, &Value::String("two".to_string()))?;
+        }
+
+        // Lose every page write that reached the data file except the header page
+        let mut contents = fs::read(db_path)?;
+        contents[PAGE_SIZE..].fill(0);
+        fs::write(db_path, contents)?;
+
+        let db = Database::new(db_path)?;
+        assert_eq!(db.get(1)?, Some(Value::Integer(100)));
+        assert_eq!(db.get(2)?, Some(Value::String("two".to_string())));
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_replays_page_deltas() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_recovery_deltas.db";
+        let wal_path = "test_recovery_deltas.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        let (root_page_id, expected) = {
+            let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+            buffer_pool.set_wal(Arc::clone(&wal));
+
+            let root_page_id = buffer_pool.new_page()?.page_id();
+            let btree = BTree::new(root_page_id);
+            btree.init(&buffer_pool)?;
+
+            wal.lock().unwrap().log(LogRecord::Begin(1))?;
+            buffer_pool.set_current_txn(Some(1));
+            for key in 0..400 {
+                btree.insert(key, Value::Integer(key as i64), &buffer_pool)?;
+            }
+            for key in 0..100 {
+                btree.delete(key, &buffer_pool)?;
+            }
+            buffer_pool.set_current_txn(None);
+            wal.lock().unwrap().log(LogRecord::Commit(1))?;
+
+            let records = wal.lock().unwrap().read_records()?;
+            let mut pages = std::collections::HashSet::new();
+            for (_, record) in &records {
+                if let LogRecord::Write {
+                    page_id,
+                    before,
+                    after,
+                    ..
+                } = record
+                {
+                    pages.insert(*page_id);
+                    assert_eq!(before.len(), after.len());
+                    // A node can fill its page, so a delta may span most of one,
+                    // but never the page header as a full image would
+                    assert!(
+                        after.len() <= PAGE_SIZE - PAGE_HEADER_SIZE,
+                        "Expected a delta"
+                    );
+                }
+            }
+            // Splits, borrows and merges touch more than the root
+            assert!(pages.len() > 1);
+            (btree.root_page_id(), btree.all(&buffer_pool)?)
+        };
+
+        // Lose every page write that reached the data file except the header page
+        let mut contents = fs::read(db_path)?;
+        contents[PAGE_SIZE..].fill(0);
+        fs::write(db_path, contents)?;
+
+        let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+        buffer_pool.set_wal(Arc::clone(&wal));
+        recovery::recover(&wal, &buffer_pool)?;
+
+        let btree = BTree::new(root_page_id);
+        assert_eq!(btree.all(&buffer_pool)?, expected);
+        assert_eq!(btree.search(399, &buffer_pool)?, Some(Value::Integer(399)));
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_log_and_bounds_redo() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_recovery_checkpoint.db";
+        let wal_path = "test_recovery_checkpoint.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        let root_page_id = {
+            let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+            buffer_pool.set_wal(Arc::clone(&wal));
+
+            let root_page_id = buffer_pool.new_page()?.page_id();
+            let btree = BTree::new(root_page_id);
+            btree.init(&buffer_pool)?;
+
+            wal.lock().unwrap().log(LogRecord::Begin(1))?;
+            buffer_pool.set_current_txn(Some(1));
+            btree.insert(1, Value::Integer(10), &buffer_pool)?;
+            buffer_pool.set_current_txn(None);
+            wal.lock().unwrap().log(LogRecord::Commit(1))?;
+
+            let sequence =
+                checkpoint::checkpoint(&mut wal.lock().unwrap(), &buffer_pool, Vec::new())?;
+            assert_eq!(
+                wal.lock().unwrap().read_records()?,
+                vec![(
+                    sequence,
+                    LogRecord::Checkpoint {
+                        active_txns: vec![]
+                    }
+                )]
+            );
+
+            // Transaction 2 is still running at the next checkpoint, so its
+            // records must survive truncation
+            wal.lock().unwrap().log(LogRecord::Begin(2))?;
+            buffer_pool.set_current_txn(Some(2));
+            btree.insert(2, Value::Integer(20), &buffer_pool)?;
+            checkpoint::checkpoint(&mut wal.lock().unwrap(), &buffer_pool, vec![2])?;
+            let records = wal.lock().unwrap().read_records()?;
+            assert_eq!(records[0].1, LogRecord::Begin(2));
+
+            btree.insert(3, Value::Integer(30), &buffer_pool)?;
+            root_page_id
+        };
+
+        let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+        buffer_pool.set_wal(Arc::clone(&wal));
+        let report = recovery::recover(&wal, &buffer_pool)?;
+        assert_eq!(report.losers, vec![2]);
+        // Only the write after the last checkpoint is replayed
+        assert_eq!(report.redone, 1);
+        assert_eq!(report.undone, 2);
+
+        let btree = BTree::new(root_page_id);
+        assert_eq!(btree.search(1, &buffer_pool)?, Some(Value::Integer(10)));
+        assert_eq!(btree.search(2, &buffer_pool)?, None);
+        assert_eq!(btree.search(3, &buffer_pool)?, None);
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_refuses_torn_page() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_recovery_torn.db";
+        let wal_path = "test_recovery_torn.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        let root_page_id = {
+            let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+            buffer_pool.set_wal(Arc::clone(&wal));
+
+            let root_page_id = buffer_pool.new_page()?.page_id();
+            let btree = BTree::new(root_page_id);
+            btree.init(&buffer_pool)?;
+            for key in 0..50 {
+                btree.insert(key, Value::Integer(key as i64), &buffer_pool)?;
+            }
+            buffer_pool.flush()?;
+
+            // A committed change whose page write is torn by the crash
+            wal.lock().unwrap().log(LogRecord::Begin(1))?;
+            buffer_pool.set_current_txn(Some(1));
+            btree.insert(50, Value::Integer(50), &buffer_pool)?;
+            buffer_pool.set_current_txn(None);
+            wal.lock().unwrap().log(LogRecord::Commit(1))?;
+            root_page_id
+        };
+
+        // Only the first half of the new page image reached the file
+        let start = root_page_id as usize * PAGE_SIZE;
+        let mut contents = fs::read(db_path)?;
+        contents[start + PAGE_SIZE / 2..start + PAGE_SIZE].fill(0xEE);
+        fs::write(db_path, &contents)?;
+
+        let mut buffer_pool = BufferPool::new(100, DiskManager::new(db_path)?);
+        let wal = Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?));
+        buffer_pool.set_wal(Arc::clone(&wal));
+        let err = recovery::recover(&wal, &buffer_pool).unwrap_err();
+        assert!(
+            matches!(err, DatabaseError::Corruption { page_id } if page_id == root_page_id),
+            "{}",
+            err
+        );
+        // The damaged page is left as it was for inspection
+        drop(buffer_pool);
+        assert_eq!(fs::read(db_path)?, contents);
+        assert!(Database::new(db_path).is_err());
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_database_checkpoint() -> Result<(), Box<dyn std::error::Error>> {
+        let db_path = "test_database_checkpoint.db";
+        let wal_path = "test_database_checkpoint.wal";
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(wal_path);
+
+        {
+            let db = Database::new(db_path)?;
+            for key in 0..2 {
+                db.insert(key, &Value::Integer(key as i64))?;
+            }
+            let before = fs::metadata(wal_path)?.len();
+            assert!(recovery::pending_writes(&WriteAheadLog::read_file(wal_path)?) > 0);
+            db.checkpoint()?;
+            assert!(fs::metadata(wal_path)?.len() < before);
+            assert_eq!(
+                recovery::pending_writes(&WriteAheadLog::read_file(wal_path)?),
+                0
+            );
+            db.insert(2, &Value::Integer(2))?;
+        }
+
+        let db = Database::new(db_path)?;
+        for key in 0..3 {
+            assert_eq!(db.get(key)?, Some(Value::Integer(key as i64)));
+        }
+
+        fs::remove_file(db_path)?;
+        fs::remove_file(wal_path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+
+    use crate::database_handler::database_handler::{Database, DatabaseConfig, Session};
+    use crate::storage::key::Key;
+    use crate::storage::transaction::IsolationLevel;
+    use crate::storage::value::Value;
+    use crate::tests::remove_test_database;
+
+    type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+    /// Fills keys 0..100 with values large enough to spread them over many leaves.
+    fn populate(db: &Database) -> Result<Vec<(Key, Value)>> {
+        for key in 0..100 {
+            db.insert(key, &padded(key))?;
+        }
+        db.all()
+    }
+
+    /// Changes every key in `keys` within the session's transaction: deleting,
+    /// updating, and inserting new keys next to them, so nodes split and merge.
+    fn scribble(session: &mut Session, keys: std::ops::Range<i32>) -> Result<()> {
+        for key in keys {
+            match key % 3 {
+                0 => session.delete(key)?,
+                1 => session.update(key, &Value::String("x".repeat(1500)))?,
+                _ => session.insert(key + 1000, &padded(key))?,
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_restores_tree() -> Result<()> {
+        let test_db_path = "test_rollback_restores.db";
+        remove_test_database(test_db_path);
+
+        let db = Database::new(test_db_path)?;
+        let before = populate(&db)?;
+
+        let mut session = db.session();
+        session.begin()?;
+        scribble(&mut session, 0..100)?;
+        assert_ne!(session.all()?, before);
+        session.rollback()?;
+
+        assert_eq!(db.all()?, before);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries, 100);
+        // Nothing is left behind for vacuum to clean up
+        assert_eq!(db.vacuum()?, 0);
+
+        drop(session);
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_statement_leaves_no_partial_effects() -> Result<()> {
+        let test_db_path = "test_rollback_failed_statement.db";
+        remove_test_database(test_db_path);
+
+        let db = Database::new(test_db_path)?;
+        let before = populate(&db)?;
+
+        let mut session = db.session();
+        session.begin()?;
+        scribble(&mut session, 0..50)?;
+        // Failing statements leave the transaction open, and the rollback still
+        // takes back everything before them
+        let too_large = Key::from("k".repeat(1000).as_str());
+        assert!(session.insert(too_large, &Value::Null).is_err());
+        assert!(session.delete(5000).is_err());
+        session.rollback()?;
+        drop(session);
+
+        assert_eq!(db.all()?, before);
+        assert_eq!(db.check()?.entries, 100);
+        assert_eq!(db.vacuum()?, 0);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_survives_reopen() -> Result<()> {
+        let test_db_path = "test_rollback_reopen.db";
+        remove_test_database(test_db_path);
+
+        let before = {
+            let db = Database::new(test_db_path)?;
+            let before = populate(&db)?;
+            let mut session = db.session();
+            session.begin()?;
+            scribble(&mut session, 0..100)?;
+            // The changed pages reach disk before the rollback
+            db.checkpoint()?;
+            session.rollback()?;
+            before
+        };
+
+        // Rolled-back writes are gone from the pages, not just hidden
+        let db = Database::new(test_db_path)?;
+        assert_eq!(db.all()?, before);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries, 100);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_mid_transaction() -> Result<()> {
+        let test_db_path = "test_rollback_crash.db";
+        remove_test_database(test_db_path);
+
+        let before = {
+            let db = Database::new(test_db_path)?;
+            let before = populate(&db)?;
+            let mut session = db.session();
+            session.begin()?;
+            scribble(&mut session, 0..50)?;
+            db.checkpoint()?;
+            scribble(&mut session, 50..100)?;
+            // Crash: neither COMMIT nor ROLLBACK, and no clean shutdown
+            std::mem::forget(session);
+            before
+        };
+
+        let db = Database::new(test_db_path)?;
+        assert_eq!(db.all()?, before);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries, 100);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_keeps_interleaved_commits() -> Result<()> {
+        let test_db_path = "test_rollback_crash_interleaved.db";
+        remove_test_database(test_db_path);
+
+        {
+            let db = Database::new(test_db_path)?;
+            db.insert(5, &Value::Integer(5))?;
+            let mut session = db.session();
+            session.begin()?;
+            session.insert(3, &Value::Integer(3))?;
+            // Committed next to the unfinished insert, on the same leaf
+            db.insert(1, &Value::Integer(1))?;
+            db.insert(9, &Value::Integer(9))?;
+            std::mem::forget(session);
+            std::mem::forget(db);
+        }
+
+        let db = Database::new(test_db_path)?;
+        let keys: Vec<Key> = db.all()?.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![Key::from(1), Key::from(5), Key::from(9)]);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_crash_after_split_keeps_commits() -> Result<()> {
+        let test_db_path = "test_rollback_crash_split.db";
+        remove_test_database(test_db_path);
+
+        let expected = {
+            let db = Database::new(test_db_path)?;
+            populate(&db)?;
+            let mut session = db.session();
+            session.begin()?;
+            // Splits and merges leaves that the commits below write to as well
+            scribble(&mut session, 0..60)?;
+            for key in (0..60).filter(|key| key % 3 != 2) {
+                db.insert(key + 1000, &padded(key))?;
+            }
+            for key in 60..100 {
+                db.update(key, &Value::Integer(key as i64))?;
+            }
+            db.checkpoint()?;
+            db.delete(99)?;
+            // The unfinished transaction's writes are not visible outside it
+            let expected = db.all()?;
+            std::mem::forget(session);
+            std::mem::forget(db);
+            expected
+        };
+
+        assert_eq!(expected.len(), 99 + 40);
+        let db = Database::new(test_db_path)?;
+        assert_eq!(db.all()?, expected);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert!(report.unreachable_pages.is_empty(), "{}", report);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_commit_ends_transaction() -> Result<()> {
+        let test_db_path = "test_rollback_failed_commit.db";
+        remove_test_database(test_db_path);
+
+        let config = DatabaseConfig {
+            lock_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let db = Database::open(test_db_path, config)?;
+        let before = populate(&db)?;
+
+        // The commit record cannot be written, so the transaction is rolled
+        // back; if the rollback fails too, it still ends as aborted
+        for failures in [1, 2] {
+            let mut session = db.session();
+            session.begin_with_isolation(IsolationLevel::Serializable)?;
+            scribble(&mut session, 0..30)?;
+            db.fail_log_appends(failures);
+            assert!(session.commit().is_err());
+            assert!(!session.in_transaction());
+            assert_eq!(db.all()?, before, "{} failures", failures);
+
+            // Its locks are released and its writes conflict with no one
+            let mut other = db.session();
+            other.begin_with_isolation(IsolationLevel::Serializable)?;
+            other.update(1, &padded(1))?;
+            other.commit()?;
+            db.insert(1002, &padded(2))?;
+            db.delete(1002)?;
+        }
+        // No transaction is left active to hold back vacuum
+        db.update(50, &Value::Null)?;
+        assert!(db.vacuum()? > 0);
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_savepoints() -> Result<()> {
+        let test_db_path = "test_rollback_savepoints.db";
+        remove_test_database(test_db_path);
+
+        let db = Database::new(test_db_path)?;
+        let before = populate(&db)?;
+
+        let mut session = db.session();
+        assert!(session.savepoint("outside").is_err());
+        session.begin()?;
+        scribble(&mut session, 0..30)?;
+        let at_first = session.all()?;
+        session.savepoint("first")?;
+        scribble(&mut session, 30..60)?;
+        session.savepoint("second")?;
+        scribble(&mut session, 60..100)?;
+
+        // Rolling back to a savepoint discards those set after it, but not itself
+        session.rollback_to_savepoint("first")?;
+        assert_eq!(session.all()?, at_first);
+        assert!(session.rollback_to_savepoint("second").is_err());
+        scribble(&mut session, 30..40)?;
+        session.rollback_to_savepoint("first")?;
+        assert_eq!(session.all()?, at_first);
+
+        // A name set again shadows the older savepoint until released
+        session.delete(1)?;
+        session.savepoint("first")?;
+        session.savepoint("inner")?;
+        session.delete(2)?;
+        session.release_savepoint("inner")?;
+        assert_eq!(session.get(2)?, None);
+        session.rollback_to_savepoint("first")?;
+        assert_eq!(session.get(1)?, None);
+        assert_eq!(session.get(2)?, Some(padded(2)));
+        session.release_savepoint("first")?;
+        session.rollback_to_savepoint("first")?;
+        assert_eq!(session.all()?, at_first);
+        session.release_savepoint("first")?;
+        assert!(session.release_savepoint("first").is_err());
+
+        // The whole transaction still rolls back past released savepoints
+        session.rollback()?;
+        assert_eq!(db.all()?, before);
+        let report = db.check()?;
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(db.vacuum()?, 0);
+
+        drop(session);
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_savepoint_then_commit() -> Result<()> {
+        let test_db_path = "test_rollback_savepoint_commit.db";
+        remove_test_database(test_db_path);
+
+        let expected = {
+            let db = Database::new(test_db_path)?;
+            populate(&db)?;
+            let mut session = db.session();
+            session.begin()?;
+            scribble(&mut session, 0..50)?;
+            let expected = session.all()?;
+            session.savepoint("step")?;
+            scribble(&mut session, 50..100)?;
+            db.checkpoint()?;
+            session.rollback_to_savepoint("step")?;
+            session.commit()?;
+            assert_eq!(db.all()?, expected);
+            expected
+        };
+
+        let db = Database::new(test_db_path)?;
+        assert_eq!(db.all()?, expected);
+        assert!(db.check()?.is_consistent());
+
+        remove_test_database(test_db_path);
+        Ok(())
+    }
+
+    fn padded(key: i32) -> Value {
+        Value::String(format!("{:0>500}", key))
+    }
+}
@@ -7,11 +7,12 @@ mod tests {
     use crate::database_handler::database_handler::Database;
     use crate::protocol::connection::Connection;
     use crate::server::Server;
+    use crate::tests::remove_test_database;
 
-    fn setup_test_server(test_type: &str) -> u16 {
+    fn setup_test_server(test_type: &str, port: u16) -> u16 {
+        remove_test_database(test_type);
         let db = Database::new(test_type).unwrap();
-        let port = 5433;
-        let server = Server::new(db, port);
+        let server = Server::new(db, port).with_import_dir(".");
 
         thread::spawn(move || {
             server.run().unwrap();
@@ -23,9 +24,9 @@ mod tests {
 
     #[test]
     fn test_server_startup() {
-        let port = setup_test_server("test_server.db");
+        let port = setup_test_server("test_server.db", 5433);
         assert!(std::net::TcpStream::connect(format!("127.0.0.1:{}", port)).is_ok());
-        std::fs::remove_file("test_server.db").unwrap();
+        remove_test_database("test_server.db");
     }
 
     fn send_raw_command(stream: &TcpStream, command: &str) -> String {
@@ -38,7 +39,7 @@ mod tests {
 
     #[test]
     fn test_basic_operations() {
-        let port = setup_test_server("test_basic_operations.db");
+        let port = setup_test_server("test_basic_operations.db", 5433);
         let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
 
         assert_eq!(send_raw_command(&stream, "SET 1 42"), "Ok\n");
@@ -60,6 +61,51 @@ mod tests {
             "Testing null value"
         );
 
+        assert!(
+            send_raw_command(&stream, "RANGE 3 5 LIMIT 1").starts_with("Range([(Int(3), String")
+        );
+
+        assert_eq!(send_raw_command(&stream, "SET \"jane doe\" 7"), "Ok\n");
+        assert_eq!(
+            send_raw_command(&stream, "GET \"jane doe\""),
+            "Integer(7)\n"
+        );
+        assert_eq!(send_raw_command(&stream, "SET \"user-1\" 5"), "Ok\n");
+        assert_eq!(
+            send_raw_command(&stream, "SET 6 EXPR(GET \"user-1\" + GET \"jane doe\")"),
+            "Ok\n"
+        );
+        assert_eq!(send_raw_command(&stream, "GET 6"), "Integer(12)\n");
+        assert_eq!(send_raw_command(&stream, "SET 0x00ff 1"), "Ok\n");
+        assert_eq!(send_raw_command(&stream, "GET 0x00ff"), "Integer(1)\n");
+        assert!(send_raw_command(&stream, "GET jane").contains("quote string keys"));
+
+        assert!(send_raw_command(&stream, "IMPORT missing_import.txt").contains("Cannot read"));
+        // Nothing outside the import directory can be read
+        for path in ["/etc/hostname", "../Cargo.toml", "src/../../Cargo.toml"] {
+            let response = send_raw_command(&stream, &format!("IMPORT {}", path));
+            assert!(
+                response.contains("name a file in the import directory"),
+                "{}",
+                response
+            );
+        }
+        std::os::unix::fs::symlink("../Cargo.toml", "test_server_import_link.txt").unwrap();
+        let response = send_raw_command(&stream, "IMPORT test_server_import_link.txt");
+        std::fs::remove_file("test_server_import_link.txt").unwrap();
+        assert!(
+            response.contains("outside the import directory"),
+            "{}",
+            response
+        );
+        std::fs::write("test_server_import.txt", "100 1\n").unwrap();
+        assert!(send_raw_command(&stream, "IMPORT test_server_import.txt").contains("empty tree"));
+        std::fs::remove_file("test_server_import.txt").unwrap();
+
+        assert_eq!(send_raw_command(&stream, "CHECKPOINT"), "Ok\n");
+        assert!(send_raw_command(&stream, "CHECK").starts_with("Check(CheckReport {"));
+        assert_eq!(send_raw_command(&stream, "GET 4"), "Boolean(true)\n");
+
         assert_eq!(send_raw_command(&stream, "DEL 1"), "Ok\n");
         assert_eq!(
             send_raw_command(&stream, "GET 1"),
@@ -68,6 +114,67 @@ mod tests {
         );
 
         // Cleanup
-        std::fs::remove_file("test_basic_operations.db").unwrap();
+        remove_test_database("test_basic_operations.db");
+    }
+
+    #[test]
+    fn test_transactions() {
+        let port = setup_test_server("test_server_transactions.db", 5434);
+        let connect = || TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        let (alice, bob) = (connect(), connect());
+
+        assert_eq!(send_raw_command(&alice, "SET 1 100"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "SET 2 0"), "Ok\n");
+
+        // A transfer is seen by its own connection only until it commits
+        assert_eq!(send_raw_command(&alice, "BEGIN"), "Ok\n");
+        assert!(send_raw_command(&alice, "BEGIN").contains("already open"));
+        assert_eq!(send_raw_command(&alice, "SET 1 EXPR(GET 1 - 30)"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "SET 2 EXPR(GET 2 + 30)"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "GET 1"), "Integer(70)\n");
+        assert_eq!(send_raw_command(&bob, "GET 1"), "Integer(100)\n");
+        assert!(send_raw_command(&bob, "SET 2 5").contains("another transaction"));
+        assert!(send_raw_command(&alice, "IMPORT x.txt").contains("inside a transaction"));
+        assert_eq!(send_raw_command(&alice, "COMMIT"), "Ok\n");
+        assert_eq!(send_raw_command(&bob, "GET 2"), "Integer(30)\n");
+
+        assert_eq!(send_raw_command(&alice, "BEGIN"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "DEL 1"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "ROLLBACK"), "Ok\n");
+        assert!(send_raw_command(&alice, "COMMIT").contains("No transaction"));
+        assert_eq!(send_raw_command(&bob, "GET 1"), "Integer(70)\n");
+
+        // A failed step is undone back to its savepoint, keeping earlier ones
+        assert_eq!(send_raw_command(&alice, "BEGIN"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "SET 1 10"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "SAVEPOINT step"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "SET 2 20"), "Ok\n");
+        assert_eq!(
+            send_raw_command(&alice, "ROLLBACK TO SAVEPOINT step"),
+            "Ok\n"
+        );
+        assert_eq!(send_raw_command(&alice, "GET 2"), "Integer(30)\n");
+        assert_eq!(send_raw_command(&alice, "RELEASE step"), "Ok\n");
+        assert!(send_raw_command(&alice, "ROLLBACK TO step").contains("does not exist"));
+        assert_eq!(send_raw_command(&alice, "COMMIT"), "Ok\n");
+        assert_eq!(send_raw_command(&bob, "GET 1"), "Integer(10)\n");
+        assert!(send_raw_command(&bob, "SAVEPOINT step").contains("No transaction"));
+
+        let begin = "BEGIN ISOLATION LEVEL READ COMMITTED";
+        assert_eq!(send_raw_command(&alice, begin), "Ok\n");
+        assert_eq!(send_raw_command(&bob, "SET 2 40"), "Ok\n");
+        assert_eq!(send_raw_command(&alice, "GET 2"), "Integer(40)\n");
+        assert_eq!(send_raw_command(&alice, "COMMIT"), "Ok\n");
+        assert!(send_raw_command(&alice, "BEGIN ISOLATION LEVEL CHAOS").contains("Unknown"));
+
+        // Disconnecting rolls back the open transaction
+        assert_eq!(send_raw_command(&bob, "BEGIN"), "Ok\n");
+        assert_eq!(send_raw_command(&bob, "SET 3 1"), "Ok\n");
+        drop(bob);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(send_raw_command(&alice, "GET 3"), "Value(None)\n");
+        assert_eq!(send_raw_command(&alice, "SET 3 2"), "Ok\n");
+
+        remove_test_database("test_server_transactions.db");
     }
 }
@@ -5,9 +5,9 @@ mod tests {
     #[test]
     fn test_value_operations() -> Result<(), Box<dyn std::error::Error>> {
         let a = Value::Integer(42);
-        let b = Value::Float(3.14);
+        let b = Value::Float(2.5);
 
-        assert_eq!(a.add(&b)?, Value::Float(45.14));
+        assert_eq!(a.add(&b)?, Value::Float(44.5));
         assert_eq!(a.mul(&Value::Integer(2))?, Value::Integer(84));
 
         Ok(())
@@ -1,7 +1,21 @@
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
     use crate::storage::error::Result;
-    use crate::storage::wal::{LogRecord, WriteAheadLog};
+    use crate::storage::wal::{Durability, LogRecord, WriteAheadLog};
+
+    fn commit(wal: &Arc<Mutex<WriteAheadLog>>, txn_id: u64) -> Result<()> {
+        let (sequence, flusher) = {
+            let mut wal = wal.lock().unwrap();
+            (wal.log(LogRecord::Commit(txn_id))?, wal.flusher())
+        };
+        flusher.wait_durable(sequence)?;
+        flusher.record_commit(Duration::ZERO);
+        Ok(())
+    }
 
     #[test]
     fn test_wal_operations() -> Result<()> {
@@ -16,4 +30,132 @@ mod tests {
         std::fs::remove_file("test_wal.db")?;
         Ok(())
     }
+
+    #[test]
+    fn test_wal_read_back_stops_at_torn_record() -> Result<()> {
+        let path = "test_wal_torn.wal";
+        let _ = std::fs::remove_file(path);
+
+        let write = LogRecord::Write {
+            txn_id: 1,
+            page_id: 3,
+            offset: 16,
+            before: vec![0; 4],
+            after: vec![1, 2, 3, 4],
+        };
+        let undo_insert = LogRecord::Undo {
+            txn_id: 1,
+            key: vec![7, 8],
+            entry: None,
+        };
+        let undo_update = LogRecord::Undo {
+            txn_id: 1,
+            key: vec![9],
+            entry: Some(vec![5, 6, 7]),
+        };
+        {
+            let mut wal = WriteAheadLog::new(path)?;
+            wal.log(LogRecord::Begin(1))?;
+            wal.log(undo_insert.clone())?;
+            wal.log(undo_update.clone())?;
+            wal.log(write.clone())?;
+            wal.log(LogRecord::Commit(1))?;
+        }
+
+        // Chop the last record in half, as a crash mid-append would
+        let len = std::fs::metadata(path)?.len();
+        let file = std::fs::OpenOptions::new().write(true).open(path)?;
+        file.set_len(len - 6)?;
+
+        let mut wal = WriteAheadLog::new(path)?;
+        assert_eq!(wal.get_sequence(), 4);
+        assert_eq!(
+            wal.read_records()?,
+            vec![
+                (1, LogRecord::Begin(1)),
+                (2, undo_insert),
+                (3, undo_update),
+                (4, write)
+            ]
+        );
+
+        // New records continue after the intact prefix
+        wal.log(LogRecord::Rollback(1))?;
+        assert_eq!(wal.read_records()?.len(), 5);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_modes() -> Result<()> {
+        for (path, durability, expected_fsyncs) in [
+            ("test_wal_always.wal", Durability::Always, 3),
+            ("test_wal_os.wal", Durability::Os, 0),
+        ] {
+            let _ = std::fs::remove_file(path);
+            let wal = Arc::new(Mutex::new(WriteAheadLog::with_durability(
+                path, durability,
+            )?));
+            for txn_id in 1..=3 {
+                commit(&wal, txn_id)?;
+            }
+
+            let stats = wal.lock().unwrap().flusher().stats();
+            assert_eq!(stats.commits, 3);
+            assert_eq!(stats.fsyncs, expected_fsyncs, "{:?}", durability);
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_commit_shares_fsyncs() -> Result<()> {
+        let path = "test_wal_group.wal";
+        let _ = std::fs::remove_file(path);
+        let wal = Arc::new(Mutex::new(WriteAheadLog::with_durability(
+            path,
+            Durability::Group {
+                delay: Duration::from_millis(50),
+            },
+        )?));
+
+        let committers = 8;
+        let barrier = Arc::new(Barrier::new(committers));
+        let handles: Vec<_> = (0..committers as u64)
+            .map(|txn_id| {
+                let wal = Arc::clone(&wal);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    commit(&wal, txn_id)
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        let flusher = wal.lock().unwrap().flusher();
+        let stats = flusher.stats();
+        assert_eq!(stats.commits, committers as u64);
+        assert!(stats.fsyncs >= 1 && stats.fsyncs < committers as u64);
+        assert_eq!(flusher.flushed_sequence(), committers as u64);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_durability() {
+        assert_eq!("always".parse::<Durability>().unwrap(), Durability::Always);
+        assert_eq!("OS".parse::<Durability>().unwrap(), Durability::Os);
+        assert_eq!(
+            "group:5".parse::<Durability>().unwrap(),
+            Durability::Group {
+                delay: Duration::from_millis(5)
+            }
+        );
+        assert!("sometimes".parse::<Durability>().is_err());
+    }
 }
@@ -47,10 +47,11 @@ impl Completer for DbHelper {
 
         // Suggest EXPR for SET/UPDATE commands
         let first_word = line_parts[0].to_uppercase();
-        if (first_word == "SET" || first_word == "UPDATE") && line_parts.len() == 3 {
-            if "EXPR(".starts_with(&current_word) {
-                return Ok((start_pos, vec!["EXPR(".to_string()]));
-            }
+        if (first_word == "SET" || first_word == "UPDATE")
+            && line_parts.len() == 3
+            && "EXPR(".starts_with(&current_word)
+        {
+            return Ok((start_pos, vec!["EXPR(".to_string()]));
         }
 
         Ok((pos, vec![]))
@@ -143,6 +144,7 @@ impl Client {
                 Response::Error(err) => Ok(format!("ERROR: {}\n", err)),
                 Response::Pong => Ok("PONG\n".into()),
                 Response::Size(size) => Ok(format!("{}\n", size)),
+                Response::Check(report) => Ok(report.to_string()),
             },
             Err(ProtocolError::ConnectionClosed) => Err("Connection closed by server".into()),
             Err(e) => Err(Box::new(e)),
@@ -173,10 +175,29 @@ fn print_help() -> String {
 │ UPDATE <key> EXPR(<expr>)  │ Update key with expression       │
 │ DEL <key>                  │ Delete key-value pair            │
 │ ALL                        │ Get all key-value pairs          │
+│ RANGE <start> <end>        │ Pairs with start <= key < end    │
+│   [LIMIT <n>]              │ in key order, at most n of them  │
 │ STRLEN <key>               │ Get length of value by key       │
 │ STRCAT <key> <value>       │ Concatenate value to key         │
 │ SUBSTR <key> <start> <len> │ Get substring of value by key    │
+│ IMPORT <file>              │ Load "key value" lines from a    │
+│                            │ file in the server's import      │
+│                            │ directory into an empty database │
+│ CHECKPOINT                 │ Flush pages and trim the WAL     │
+│ CHECK                      │ Check the B-tree for corruption  │
+│ BEGIN                      │ Start a transaction              │
+│ BEGIN ISOLATION LEVEL <l>  │ READ COMMITTED, REPEATABLE READ  │
+│                            │ or SERIALIZABLE                  │
+│ COMMIT                     │ Commit the open transaction      │
+│ ROLLBACK                   │ Undo the open transaction        │
+│ SAVEPOINT <name>           │ Mark a point to roll back to     │
+│ ROLLBACK TO <name>         │ Undo writes since the savepoint  │
+│ RELEASE <name>             │ Forget the savepoint             │
 │ EXPR(<expression>)         │ Calculate expression             │
+│ Keys:                      │                                  │
+│ 42                         │ Integer key                      │
+│ "alice"                    │ String key                       │
+│ 0x01ff                     │ Byte key                         │
 │ Expression Examples:       │                                  │
 │ EXPR(GET 1 + GET 2)        │ Calculate sum of values          │
 │ SET 3 EXPR(GET 1 * 2)      │ Set using expression             │
@@ -199,9 +220,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "UPDATE".to_string(),
             "DEL".to_string(),
             "ALL".to_string(),
+            "RANGE".to_string(),
             "STRLEN".to_string(),
             "STRCAT".to_string(),
             "SUBSTR".to_string(),
+            "IMPORT".to_string(),
+            "CHECKPOINT".to_string(),
+            "CHECK".to_string(),
+            "BEGIN".to_string(),
+            "COMMIT".to_string(),
+            "ROLLBACK".to_string(),
+            "SAVEPOINT".to_string(),
+            "RELEASE".to_string(),
             "EXPR".to_string(),
             "exit".to_string(),
             "help".to_string(),